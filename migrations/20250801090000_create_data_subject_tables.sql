@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE data_access_tokens (
+    data_access_token TEXT NOT NULL,
+    subscription_id UUID NOT NULL
+        REFERENCES subscriptions (id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (data_access_token)
+);
+CREATE TABLE erased_subscribers (
+    email_hash TEXT NOT NULL,
+    erased_at timestamptz NOT NULL,
+    PRIMARY KEY (email_hash)
+);
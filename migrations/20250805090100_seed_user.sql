@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Initial administrator, password `everythinghastostartsomewhere`.
+-- Change it as soon as the instance is deployed.
+INSERT INTO users (user_id, username, password_hash)
+VALUES (
+    'ddf8994f-d522-4659-8d02-c1d479057be6',
+    'admin',
+    '$argon2id$v=19$m=15000,t=2,p=1$uDC8ZzxCuWZBmaeVDfGy4Q$9DAPSO8KSNQX3U3ck/WYVTevGfC4jZ5Pt7sA51uQD0c'
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE consent_events (
+    consent_event_id uuid PRIMARY KEY,
+    subscription_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    event_type TEXT NOT NULL,
+    ip_address TEXT NULL,
+    user_agent TEXT NULL,
+    source TEXT NULL,
+    privacy_policy_version TEXT NOT NULL,
+    wording TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX consent_events_subscription_id_idx
+    ON consent_events (subscription_id, occurred_at);
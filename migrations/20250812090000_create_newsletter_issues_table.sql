@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE newsletter_issues (
+    newsletter_issue_id uuid NOT NULL,
+    title TEXT NOT NULL,
+    markdown_content TEXT NOT NULL,
+    -- Filled in when the issue is published, so that the issue keeps
+    -- looking the same after the templates change.
+    html_content TEXT NULL,
+    text_content TEXT NULL,
+    created_at timestamptz NOT NULL,
+    published_at timestamptz NULL,
+    PRIMARY KEY (newsletter_issue_id)
+);
@@ -0,0 +1,13 @@
+-- Every delivery event reported by the email provider, kept verbatim so
+-- that we can audit why a subscriber stopped receiving issues.
+CREATE TABLE email_events (
+    email_event_id uuid NOT NULL PRIMARY KEY,
+    provider TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    event_type TEXT NULL,
+    email TEXT NULL,
+    subscriber_id uuid NULL REFERENCES subscriptions (id),
+    payload JSONB NOT NULL,
+    received_at timestamptz NOT NULL
+);
+CREATE INDEX email_events_subscriber_id_idx ON email_events (subscriber_id);
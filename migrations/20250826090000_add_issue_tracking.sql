@@ -0,0 +1,19 @@
+-- Engagement tracking is opt-in, issue by issue.
+ALTER TABLE newsletter_issues ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE TABLE issue_opens (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    opened_at timestamptz NOT NULL
+);
+CREATE INDEX issue_opens_newsletter_issue_id_idx ON issue_opens (newsletter_issue_id);
+CREATE TABLE issue_clicks (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    url TEXT NOT NULL,
+    clicked_at timestamptz NOT NULL
+);
+CREATE INDEX issue_clicks_newsletter_issue_id_idx ON issue_clicks (newsletter_issue_id);
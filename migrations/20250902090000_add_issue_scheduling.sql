@@ -0,0 +1,17 @@
+-- Issues move from draft to sending (possibly through scheduled) to sent.
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'draft';
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_at timestamptz NULL;
+UPDATE newsletter_issues i SET status = CASE
+    WHEN i.published_at IS NULL THEN 'draft'
+    WHEN EXISTS (
+        SELECT 1 FROM issue_delivery_queue q
+        WHERE q.newsletter_issue_id = i.newsletter_issue_id
+    ) THEN 'sending'
+    ELSE 'sent'
+END;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'sending', 'sent'));
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_scheduled_at_check
+    CHECK (status <> 'scheduled' OR scheduled_at IS NOT NULL);
+CREATE INDEX newsletter_issues_scheduled_idx ON newsletter_issues (scheduled_at)
+    WHERE status = 'scheduled';
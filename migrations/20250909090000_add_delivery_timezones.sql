@@ -0,0 +1,7 @@
+-- An IANA timezone name; NULL means the configured default.
+ALTER TABLE subscriptions ADD COLUMN timezone TEXT NULL;
+-- Set when an issue goes out at a local time of day for each subscriber.
+ALTER TABLE newsletter_issues ADD COLUMN local_delivery_time timestamp NULL;
+-- Deliveries are not attempted before this time.
+ALTER TABLE issue_delivery_queue ADD COLUMN not_before timestamptz NOT NULL DEFAULT now();
+CREATE INDEX issue_delivery_queue_not_before_idx ON issue_delivery_queue (not_before);
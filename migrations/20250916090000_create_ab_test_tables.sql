@@ -0,0 +1,27 @@
+CREATE TABLE issue_ab_tests (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    -- The share of the audience that receives each variant.
+    sample_fraction DOUBLE PRECISION NOT NULL
+        CHECK (sample_fraction > 0 AND sample_fraction <= 1),
+    wait_minutes INTEGER NOT NULL CHECK (wait_minutes > 0),
+    metric TEXT NOT NULL CHECK (metric IN ('opens', 'clicks')),
+    -- Set when the samples are queued.
+    decide_at timestamptz NULL,
+    winning_variant SMALLINT NULL,
+    decided_at timestamptz NULL,
+    PRIMARY KEY (newsletter_issue_id)
+);
+CREATE TABLE issue_variants (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    variant SMALLINT NOT NULL,
+    -- NULL means the title (or content) of the issue itself.
+    subject TEXT NULL,
+    markdown_content TEXT NULL,
+    html_content TEXT NULL,
+    text_content TEXT NULL,
+    PRIMARY KEY (newsletter_issue_id, variant)
+);
+ALTER TABLE issue_delivery_queue ADD COLUMN variant SMALLINT NULL;
+ALTER TABLE issue_deliveries ADD COLUMN variant SMALLINT NULL;
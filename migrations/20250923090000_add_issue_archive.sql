@@ -0,0 +1,16 @@
+-- Published issues get a public page in the archive, unless kept private.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
+ALTER TABLE newsletter_issues ADD COLUMN private BOOLEAN NOT NULL DEFAULT FALSE;
+-- Lets caches tell that an issue left (or came back to) the archive.
+ALTER TABLE newsletter_issues ADD COLUMN visibility_changed_at timestamptz NULL;
+-- The issue as rendered for the web: no unsubscribe link, no tracking.
+ALTER TABLE newsletter_issues ADD COLUMN web_html_content TEXT NULL;
+UPDATE newsletter_issues
+SET slug = concat_ws(
+    '-',
+    NULLIF(trim(BOTH '-' FROM lower(regexp_replace(title, '[^a-zA-Z0-9]+', '-', 'g'))), ''),
+    left(newsletter_issue_id::text, 8)
+)
+WHERE published_at IS NOT NULL;
+CREATE INDEX newsletter_issues_archive_idx ON newsletter_issues (published_at DESC)
+    WHERE slug IS NOT NULL AND NOT private;
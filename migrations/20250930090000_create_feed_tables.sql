@@ -0,0 +1,26 @@
+-- External feeds we turn into newsletter issues, keyed by the name they
+-- have in the configuration.
+CREATE TABLE feeds (
+    feed_name TEXT NOT NULL,
+    first_polled_at timestamptz NOT NULL,
+    last_polled_at timestamptz NOT NULL,
+    -- When the last weekly digest went out, for feeds in digest mode.
+    last_digest_at timestamptz NULL,
+    PRIMARY KEY (feed_name)
+);
+-- Every item we have seen, so that it is only ever sent once.
+-- Items already in the feed when we first polled it are `seen`; new ones
+-- are `pending` until they make it into an issue.
+CREATE TABLE feed_items (
+    feed_name TEXT NOT NULL REFERENCES feeds (feed_name),
+    guid TEXT NOT NULL,
+    title TEXT NOT NULL,
+    link TEXT NULL,
+    description TEXT NULL,
+    published_at timestamptz NULL,
+    first_seen_at timestamptz NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('seen', 'pending', 'issued')),
+    newsletter_issue_id uuid NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    PRIMARY KEY (feed_name, guid)
+);
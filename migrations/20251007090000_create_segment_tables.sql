@@ -0,0 +1,21 @@
+-- The lists a subscriber joined, usually through the signup form.
+CREATE TABLE subscriber_lists (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    list_name TEXT NOT NULL,
+    joined_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, list_name)
+);
+CREATE INDEX subscriber_lists_list_name_idx ON subscriber_lists (list_name);
+-- Saved audiences, written in the segment language (see `src/segments`).
+CREATE TABLE segments (
+    segment_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    definition TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY (segment_id)
+);
+-- NULL sends the issue to every confirmed subscriber.
+ALTER TABLE newsletter_issues ADD COLUMN segment_id uuid NULL
+    REFERENCES segments (segment_id);
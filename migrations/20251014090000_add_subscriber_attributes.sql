@@ -0,0 +1,11 @@
+-- Custom fields (company, plan, country, ...), checked against the
+-- definitions below whenever they are written.
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+CREATE TABLE attribute_definitions (
+    name TEXT NOT NULL,
+    attribute_type TEXT NOT NULL
+        CHECK (attribute_type IN ('string', 'number', 'bool', 'date')),
+    required BOOLEAN NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (name)
+);
@@ -0,0 +1,41 @@
+-- Endpoints (such as the CRM's) told about subscription lifecycle events.
+CREATE TABLE webhook_endpoints (
+    endpoint_id uuid PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- Signs every delivery, so that the receiver can tell they come from us.
+    secret TEXT NOT NULL,
+    event_types TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- Both the queue and the state of every delivery: `pending` ones are
+-- (re)tried from `next_attempt_at` on.
+CREATE TABLE webhook_deliveries (
+    delivery_id uuid PRIMARY KEY,
+    endpoint_id uuid NOT NULL
+        REFERENCES webhook_endpoints (endpoint_id) ON DELETE CASCADE,
+    event_type TEXT NOT NULL,
+    -- The payload names the subscriber: erasing them deletes it.
+    subscriber_id uuid NOT NULL,
+    payload JSONB NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('pending', 'delivered', 'failed')),
+    attempts SMALLINT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX webhook_deliveries_due_idx
+    ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_endpoint_id_idx
+    ON webhook_deliveries (endpoint_id, created_at);
+
+CREATE TABLE webhook_delivery_attempts (
+    delivery_id uuid NOT NULL
+        REFERENCES webhook_deliveries (delivery_id) ON DELETE CASCADE,
+    attempt SMALLINT NOT NULL,
+    attempted_at timestamptz NOT NULL,
+    -- NULL when no response came back at all.
+    response_status SMALLINT NULL,
+    error TEXT NULL,
+    duration_ms INTEGER NOT NULL,
+    PRIMARY KEY (delivery_id, attempt)
+);
@@ -0,0 +1,16 @@
+-- Credentials for machine clients (partner integrations, scripts, ...).
+-- Only a hash of each key is kept: the key itself is shown once.
+CREATE TABLE api_keys (
+    api_key_id uuid PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- The public part of the key, to tell keys apart in the UI and to
+    -- look them up.
+    prefix TEXT NOT NULL UNIQUE,
+    key_hash TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_by uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NULL,
+    last_used_at timestamptz NULL,
+    revoked_at timestamptz NULL
+);
@@ -0,0 +1,18 @@
+-- The administrator created so far owns the instance; everybody added from
+-- now on is given a role explicitly.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'viewer'));
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
+
+CREATE TABLE admin_invitations (
+    invitation_id UUID PRIMARY KEY,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    -- The token is only ever sent by email: we keep its SHA-256 hash.
+    token_hash TEXT NOT NULL UNIQUE,
+    invited_by UUID NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    accepted_at timestamptz NULL
+);
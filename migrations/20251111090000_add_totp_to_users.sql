@@ -0,0 +1,15 @@
+-- Secrets are stored base32-encoded, as they appear in the otpauth URI.
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+-- Set while an enrolment waits for its first valid code.
+ALTER TABLE users ADD COLUMN pending_totp_secret TEXT NULL;
+-- The time step of the last accepted code: each code works only once.
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT NULL;
+ALTER TABLE users ADD COLUMN failed_totp_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN totp_locked_until timestamptz NULL;
+
+CREATE TABLE totp_recovery_codes (
+    user_id UUID NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at timestamptz NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
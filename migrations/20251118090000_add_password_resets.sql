@@ -0,0 +1,11 @@
+-- Stored in each session at login and bumped whenever the password changes:
+-- sessions of an older generation are no longer accepted.
+ALTER TABLE users ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE password_reset_tokens (
+    -- The token is only ever sent by email: we keep its SHA-256 hash.
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
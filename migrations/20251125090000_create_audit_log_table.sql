@@ -0,0 +1,30 @@
+-- No foreign keys: the log outlives the users, keys and records it mentions.
+CREATE TABLE audit_log (
+    audit_event_id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    actor_user_id UUID NULL,
+    actor_api_key_id UUID NULL,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    -- Only the fields that changed.
+    before_state JSONB NULL,
+    after_state JSONB NULL,
+    ip_address TEXT NULL,
+    request_id TEXT NULL
+);
+CREATE INDEX audit_log_target ON audit_log (target_type, target_id);
+CREATE INDEX audit_log_actor_user_id ON audit_log (actor_user_id);
+
+-- Whatever the application does, what happened stays written.
+CREATE FUNCTION reject_audit_log_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'The audit log is append-only.';
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER audit_log_is_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION reject_audit_log_changes();
+CREATE TRIGGER audit_log_cannot_be_truncated
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_log_changes();
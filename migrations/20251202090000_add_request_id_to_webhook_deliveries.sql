@@ -0,0 +1,4 @@
+-- The request that caused the event, sent along with every attempt so that
+-- receivers can correlate it with our logs. Null for events raised by
+-- background work.
+ALTER TABLE webhook_deliveries ADD COLUMN request_id TEXT NULL;
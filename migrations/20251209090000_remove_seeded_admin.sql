@@ -0,0 +1,17 @@
+-- 20250805090100_seed_user.sql seeds an administrator with a published
+-- password; the first owner is now created with `zero2prod --create-owner`.
+-- Remove the seeded administrator if nothing refers to it, and otherwise
+-- replace its password hash with one that no password matches, logging it
+-- out everywhere.
+DELETE FROM users u
+WHERE u.user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+    AND u.password_hash = '$argon2id$v=19$m=15000,t=2,p=1$uDC8ZzxCuWZBmaeVDfGy4Q$9DAPSO8KSNQX3U3ck/WYVTevGfC4jZ5Pt7sA51uQD0c'
+    AND NOT EXISTS (SELECT 1 FROM api_keys WHERE created_by = u.user_id)
+    AND NOT EXISTS (SELECT 1 FROM admin_invitations WHERE invited_by = u.user_id);
+
+UPDATE users
+SET
+    password_hash = '$argon2id$v=19$m=15000,t=2,p=1$QKb2JWejeAiSgkebNsxoIg$CAMTnRe2KNqvD6T/zjsyMREB0pxaKDa0flyOGA3Bwq8',
+    session_generation = session_generation + 1
+WHERE user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+    AND password_hash = '$argon2id$v=19$m=15000,t=2,p=1$uDC8ZzxCuWZBmaeVDfGy4Q$9DAPSO8KSNQX3U3ck/WYVTevGfC4jZ5Pt7sA51uQD0c';
@@ -0,0 +1,4 @@
+-- Deliveries that fail for a reason that may go away (a timeout, an outage
+-- at the email provider) are retried, with `not_before` pushed back further
+-- after each failed attempt.
+ALTER TABLE issue_delivery_queue ADD COLUMN attempts SMALLINT NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Scheduled issues that could not be started are set aside with the reason,
+-- rather than attempted again on every tick of the scheduler. They can be
+-- edited, and scheduled or published again, like drafts.
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'sending', 'sent', 'failed'));
+ALTER TABLE newsletter_issues ADD COLUMN failure_reason TEXT NULL;
@@ -0,0 +1,6 @@
+-- Items that could not be turned into an issue are set aside with the
+-- reason, so that one broken item does not hold up the rest of the feed.
+ALTER TABLE feed_items DROP CONSTRAINT feed_items_status_check;
+ALTER TABLE feed_items ADD CONSTRAINT feed_items_status_check
+    CHECK (status IN ('seen', 'pending', 'issued', 'failed'));
+ALTER TABLE feed_items ADD COLUMN failure_reason TEXT NULL;
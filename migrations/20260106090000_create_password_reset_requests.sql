@@ -0,0 +1,21 @@
+-- Every request for a password reset link, whether or not the address is an
+-- admin's: the requests are throttled per address and per client address,
+-- and the emails are sent by a background worker, so that the answer gives
+-- nothing away. Rows are deleted after a day.
+CREATE TABLE password_reset_requests (
+    password_reset_request_id uuid NOT NULL,
+    email TEXT NOT NULL,
+    ip_address TEXT NULL,
+    requested_at timestamptz NOT NULL,
+    -- The admin with that address, if any: only they get an email.
+    user_id uuid NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- Set when the worker picks the request up. It is never attempted
+    -- twice: whoever asked can ask again.
+    processed_at timestamptz NULL,
+    PRIMARY KEY (password_reset_request_id)
+);
+CREATE INDEX password_reset_requests_requested_at_idx
+    ON password_reset_requests (requested_at);
+CREATE INDEX password_reset_requests_pending_idx
+    ON password_reset_requests (requested_at)
+    WHERE user_id IS NOT NULL AND processed_at IS NULL;
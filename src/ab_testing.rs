@@ -0,0 +1,215 @@
+//! A/B tests of newsletter issues.
+//!
+//! Each variant of an issue goes to its own random sample of the audience.
+//! Once the test has run for long enough, the variant with the best open
+//! (or click) rate wins and goes to everybody else.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use uuid::Uuid;
+
+/// How many variants a single issue can be tested with.
+pub const MAX_VARIANTS: usize = 4;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WinnerMetric {
+    Opens,
+    Clicks,
+}
+
+impl WinnerMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WinnerMetric::Opens => "opens",
+            WinnerMetric::Clicks => "clicks",
+        }
+    }
+}
+
+impl TryFrom<String> for WinnerMetric {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "opens" => Ok(WinnerMetric::Opens),
+            "clicks" => Ok(WinnerMetric::Clicks),
+            other => Err(format!("{} is not a supported winner metric.", other)),
+        }
+    }
+}
+
+/// How a variant did, as counted from the deliveries attributed to it.
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct VariantResult {
+    pub variant: i16,
+    pub subject: String,
+    pub delivered: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+}
+
+impl VariantResult {
+    fn rate(&self, metric: WinnerMetric) -> f64 {
+        if self.delivered == 0 {
+            return 0.0;
+        }
+        let hits = match metric {
+            WinnerMetric::Opens => self.unique_opens,
+            WinnerMetric::Clicks => self.unique_clicks,
+        };
+        hits as f64 / self.delivered as f64
+    }
+}
+
+/// Pick the test recipients and the variant each of them gets.
+///
+/// Every variant goes to `sample_fraction` of the audience (rounded up), so
+/// the sample as a whole is `variant_count` times as large. Recipients that
+/// are left out get the winning variant later.
+pub fn assign_variants<R: Rng + ?Sized>(
+    mut audience: Vec<Uuid>,
+    variant_count: usize,
+    sample_fraction: f64,
+    rng: &mut R,
+) -> Vec<(Uuid, i16)> {
+    if variant_count == 0 {
+        return Vec::new();
+    }
+    audience.shuffle(rng);
+    let per_variant = (audience.len() as f64 * sample_fraction).ceil() as usize;
+    audience.truncate(per_variant * variant_count);
+    audience
+        .into_iter()
+        .enumerate()
+        .map(|(i, subscriber_id)| (subscriber_id, (i % variant_count) as i16))
+        .collect()
+}
+
+/// The variant with the best rate for `metric`. Ties go to the variant that
+/// was listed first.
+pub fn choose_winner(results: &[VariantResult], metric: WinnerMetric) -> Option<i16> {
+    results
+        .iter()
+        .fold(None::<&VariantResult>, |best, candidate| match best {
+            Some(best) if best.rate(metric) >= candidate.rate(metric) => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|winner| winner.variant)
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_variant_results<'c, E: sqlx::PgExecutor<'c>>(
+    executor: E,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<VariantResult>, sqlx::Error> {
+    sqlx::query_as!(
+        VariantResult,
+        r#"
+        SELECT
+            v.variant,
+            COALESCE(v.subject, i.title) AS "subject!",
+            (SELECT COUNT(*) FROM issue_deliveries d
+             WHERE d.newsletter_issue_id = v.newsletter_issue_id
+                AND d.variant = v.variant
+                AND d.outcome = 'delivered') AS "delivered!",
+            (SELECT COUNT(DISTINCT o.subscriber_id) FROM issue_opens o
+             JOIN issue_deliveries d USING (newsletter_issue_id, subscriber_id)
+             WHERE o.newsletter_issue_id = v.newsletter_issue_id
+                AND d.variant = v.variant) AS "unique_opens!",
+            (SELECT COUNT(DISTINCT c.subscriber_id) FROM issue_clicks c
+             JOIN issue_deliveries d USING (newsletter_issue_id, subscriber_id)
+             WHERE c.newsletter_issue_id = v.newsletter_issue_id
+                AND d.variant = v.variant) AS "unique_clicks!"
+        FROM issue_variants v
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE v.newsletter_issue_id = $1
+        ORDER BY v.variant
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ab_testing::{assign_variants, choose_winner, VariantResult, WinnerMetric};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
+    use uuid::Uuid;
+
+    fn audience(size: usize) -> Vec<Uuid> {
+        (0..size).map(|_| Uuid::new_v4()).collect()
+    }
+
+    fn result(variant: i16, delivered: i64, unique_opens: i64, unique_clicks: i64) -> VariantResult {
+        VariantResult {
+            variant,
+            subject: format!("Subject {}", variant),
+            delivered,
+            unique_opens,
+            unique_clicks,
+        }
+    }
+
+    #[test]
+    fn each_variant_gets_its_share_of_the_audience() {
+        let audience = audience(100);
+
+        let sample = assign_variants(audience.clone(), 3, 0.1, &mut StdRng::seed_from_u64(42));
+
+        assert_eq!(sample.len(), 30);
+        for variant in 0..3 {
+            assert_eq!(sample.iter().filter(|(_, v)| *v == variant).count(), 10);
+        }
+        let recipients: HashSet<_> = sample.iter().map(|(id, _)| *id).collect();
+        assert_eq!(recipients.len(), 30);
+        assert!(recipients.iter().all(|id| audience.contains(id)));
+    }
+
+    #[test]
+    fn the_sample_is_random_but_reproducible() {
+        let audience = audience(50);
+
+        let first = assign_variants(audience.clone(), 2, 0.2, &mut StdRng::seed_from_u64(7));
+        let again = assign_variants(audience.clone(), 2, 0.2, &mut StdRng::seed_from_u64(7));
+        let other = assign_variants(audience.clone(), 2, 0.2, &mut StdRng::seed_from_u64(8));
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        // Not simply the head of the list.
+        assert_ne!(
+            first.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            audience[..20].to_vec()
+        );
+    }
+
+    #[test]
+    fn small_audiences_are_sampled_entirely() {
+        let sample = assign_variants(audience(3), 2, 0.4, &mut StdRng::seed_from_u64(1));
+        assert_eq!(sample.len(), 3);
+
+        assert!(assign_variants(Vec::new(), 2, 0.5, &mut StdRng::seed_from_u64(1)).is_empty());
+    }
+
+    #[test]
+    fn the_best_rate_wins_regardless_of_sample_size() {
+        let results = vec![result(0, 100, 30, 2), result(1, 10, 4, 1)];
+
+        assert_eq!(choose_winner(&results, WinnerMetric::Opens), Some(1));
+        assert_eq!(choose_winner(&results, WinnerMetric::Clicks), Some(1));
+        let results = vec![result(0, 100, 30, 20), result(1, 10, 4, 1)];
+        assert_eq!(choose_winner(&results, WinnerMetric::Clicks), Some(0));
+    }
+
+    #[test]
+    fn ties_go_to_the_first_variant() {
+        let results = vec![result(0, 10, 1, 0), result(1, 20, 2, 0), result(2, 0, 0, 0)];
+
+        assert_eq!(choose_winner(&results, WinnerMetric::Opens), Some(0));
+        assert_eq!(choose_winner(&results, WinnerMetric::Clicks), Some(0));
+        assert_eq!(choose_winner(&[], WinnerMetric::Opens), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Custom subscriber attributes, such as `company`, `plan` or `country`.
+//!
+//! Administrators define each attribute with a type and whether it is
+//! required. Values are checked against these definitions whenever they are
+//! written (at signup, on import or through the admin API) and stored as a
+//! JSON object in `subscriptions.attributes`.
+
+use chrono::NaiveDate;
+use serde_json::{Map, Value};
+use sqlx::PgExecutor;
+use std::collections::HashMap;
+
+/// The attributes of a subscriber, by name.
+pub type Attributes = Map<String, Value>;
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_STRING_LENGTH: usize = 1024;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeType {
+    String,
+    Number,
+    Bool,
+    /// Stored as `YYYY-MM-DD`.
+    Date,
+}
+
+impl AttributeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeType::String => "string",
+            AttributeType::Number => "number",
+            AttributeType::Bool => "bool",
+            AttributeType::Date => "date",
+        }
+    }
+}
+
+impl TryFrom<String> for AttributeType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "string" => Ok(AttributeType::String),
+            "number" => Ok(AttributeType::Number),
+            "bool" => Ok(AttributeType::Bool),
+            "date" => Ok(AttributeType::Date),
+            other => Err(format!("{} is not a supported attribute type.", other)),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub attribute_type: AttributeType,
+    pub required: bool,
+}
+
+impl AttributeDefinition {
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let (valid, expected) = match (self.attribute_type, value) {
+            (AttributeType::String, Value::String(s)) => {
+                (s.chars().count() <= MAX_STRING_LENGTH, "string")
+            }
+            (AttributeType::Number, Value::Number(_)) => (true, "number"),
+            (AttributeType::Bool, Value::Bool(_)) => (true, "boolean"),
+            (AttributeType::Date, Value::String(s)) => (
+                NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok(),
+                "date such as \"2025-01-31\"",
+            ),
+            (AttributeType::String, _) => (false, "string"),
+            (AttributeType::Number, _) => (false, "number"),
+            (AttributeType::Bool, _) => (false, "boolean"),
+            (AttributeType::Date, _) => (false, "date such as \"2025-01-31\""),
+        };
+        if valid {
+            Ok(())
+        } else if self.attribute_type == AttributeType::String {
+            Err(format!(
+                "`{}` must be a string of at most {} characters.",
+                self.name, MAX_STRING_LENGTH
+            ))
+        } else {
+            Err(format!("`{}` must be a {}.", self.name, expected))
+        }
+    }
+
+    /// Values from HTML forms all arrive as strings.
+    fn parse_form_value(&self, raw: &str) -> Result<Value, String> {
+        let invalid = || format!("`{}` is not a valid value for `{}`.", raw, self.name);
+        match self.attribute_type {
+            AttributeType::String | AttributeType::Date => Ok(Value::String(raw.to_owned())),
+            AttributeType::Number => raw
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(invalid),
+            // Checkboxes send `on` when ticked, and nothing otherwise.
+            AttributeType::Bool => match raw.to_ascii_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => Ok(Value::Bool(true)),
+                "false" | "off" | "no" | "0" => Ok(Value::Bool(false)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Attribute names double as merge tags, `{{ attributes.<name> }}`, so they
+/// are kept to lowercase letters, digits and underscores.
+pub fn validate_attribute_name(name: &str) -> Result<(), String> {
+    let is_valid = name.len() <= MAX_NAME_LENGTH
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is not a valid attribute name: start with a lowercase letter, then use \
+             up to {} lowercase letters, digits and `_`.",
+            name, MAX_NAME_LENGTH
+        ))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct AttributeSchema {
+    definitions: Vec<AttributeDefinition>,
+}
+
+impl AttributeSchema {
+    pub fn new(definitions: Vec<AttributeDefinition>) -> Self {
+        Self { definitions }
+    }
+
+    pub fn definitions(&self) -> &[AttributeDefinition] {
+        &self.definitions
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AttributeDefinition> {
+        self.definitions.iter().find(|d| d.name == name)
+    }
+
+    /// Check attributes submitted as JSON. `null` leaves an attribute unset.
+    pub fn validate(&self, attributes: &Attributes) -> Result<Attributes, String> {
+        let mut valid = Attributes::new();
+        for (name, value) in attributes {
+            let definition = self
+                .get(name)
+                .ok_or_else(|| format!("`{}` is not a known attribute.", name))?;
+            if value.is_null() {
+                continue;
+            }
+            definition.check(value)?;
+            valid.insert(name.clone(), value.clone());
+        }
+        for definition in self.definitions.iter().filter(|d| d.required) {
+            if !valid.contains_key(&definition.name) {
+                return Err(format!("`{}` is required.", definition.name));
+            }
+        }
+        Ok(valid)
+    }
+
+    /// Check attributes submitted through a form. Empty fields leave an
+    /// attribute unset.
+    pub fn parse_form(&self, fields: &HashMap<String, String>) -> Result<Attributes, String> {
+        let mut attributes = Attributes::new();
+        for (name, raw) in fields {
+            let definition = self
+                .get(name)
+                .ok_or_else(|| format!("`{}` is not a known attribute.", name))?;
+            let raw = raw.trim();
+            if !raw.is_empty() {
+                attributes.insert(name.clone(), definition.parse_form_value(raw)?);
+            }
+        }
+        self.validate(&attributes)
+    }
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_attribute_schema<'c, E: PgExecutor<'c>>(
+    executor: E,
+) -> Result<AttributeSchema, anyhow::Error> {
+    let definitions = sqlx::query!(
+        "SELECT name, attribute_type, required FROM attribute_definitions ORDER BY name"
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(AttributeDefinition {
+            name: r.name,
+            attribute_type: AttributeType::try_from(r.attribute_type)
+                .map_err(anyhow::Error::msg)?,
+            required: r.required,
+        })
+    })
+    .collect::<Result<_, anyhow::Error>>()?;
+    Ok(AttributeSchema::new(definitions))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attributes::{
+        validate_attribute_name, AttributeDefinition, AttributeSchema, AttributeType, Attributes,
+    };
+    use claims::{assert_err, assert_ok};
+    use std::collections::HashMap;
+
+    fn schema() -> AttributeSchema {
+        let definition = |name: &str, attribute_type, required| AttributeDefinition {
+            name: name.into(),
+            attribute_type,
+            required,
+        };
+        AttributeSchema::new(vec![
+            definition("company", AttributeType::String, true),
+            definition("seats", AttributeType::Number, false),
+            definition("trial", AttributeType::Bool, false),
+            definition("renews_on", AttributeType::Date, false),
+        ])
+    }
+
+    fn attributes(value: serde_json::Value) -> Attributes {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn values_of_the_right_type_are_accepted() {
+        let valid = schema()
+            .validate(&attributes(serde_json::json!({
+                "company": "Acme",
+                "seats": 12,
+                "trial": false,
+                "renews_on": "2026-01-31",
+                "seats": null,
+            })))
+            .unwrap();
+
+        assert_eq!(valid["company"], "Acme");
+        assert_eq!(valid["renews_on"], "2026-01-31");
+        // `null` unsets the attribute.
+        assert!(!valid.contains_key("seats"));
+    }
+
+    #[test]
+    fn unknown_mistyped_and_missing_attributes_are_rejected() {
+        let schema = schema();
+        let test_cases = vec![
+            serde_json::json!({ "company": "Acme", "plan": "pro" }),
+            serde_json::json!({ "company": 42 }),
+            serde_json::json!({ "company": "Acme", "seats": "12" }),
+            serde_json::json!({ "company": "Acme", "trial": "yes" }),
+            serde_json::json!({ "company": "Acme", "renews_on": "next year" }),
+            serde_json::json!({ "company": "a".repeat(1025) }),
+            serde_json::json!({ "seats": 12 }),
+            serde_json::json!({ "company": null }),
+        ];
+
+        for test_case in test_cases {
+            assert_err!(
+                schema.validate(&attributes(test_case.clone())),
+                "{}",
+                test_case
+            );
+        }
+    }
+
+    #[test]
+    fn form_values_are_converted_to_their_type() {
+        let fields: HashMap<String, String> = [
+            ("company", " Acme "),
+            ("seats", "12.5"),
+            ("trial", "on"),
+            ("renews_on", ""),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+
+        let parsed = schema().parse_form(&fields).unwrap();
+
+        assert_eq!(parsed["company"], "Acme");
+        assert_eq!(parsed["seats"], 12.5);
+        assert_eq!(parsed["trial"], true);
+        assert!(!parsed.contains_key("renews_on"));
+        let mut fields = fields;
+        fields.insert("seats".into(), "a dozen".into());
+        assert_err!(schema().parse_form(&fields));
+    }
+
+    #[test]
+    fn attribute_names_can_be_used_in_merge_tags() {
+        assert_ok!(validate_attribute_name("company"));
+        assert_ok!(validate_attribute_name("renews_on_2"));
+        assert_err!(validate_attribute_name(""));
+        assert_err!(validate_attribute_name("2nd_company"));
+        assert_err!(validate_attribute_name("Company"));
+        assert_err!(validate_attribute_name("company name"));
+        assert_err!(validate_attribute_name(&"a".repeat(65)));
+    }
+}
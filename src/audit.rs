@@ -0,0 +1,154 @@
+//! An append-only record of what admins, and API keys, did: who published
+//! which issue, who changed which setting, from where.
+
+use actix_web::{HttpMessage, HttpRequest};
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::PgExecutor;
+
+use crate::authentication::{ApiKey, UserId};
+use crate::consent::client_ip_address;
+use crate::request_id::RequestId;
+
+/// Something that changed, and how.
+pub struct AuditEvent {
+    /// `<target type>.<verb>`, such as `newsletter_issue.publish`.
+    action: &'static str,
+    target_type: &'static str,
+    target_id: String,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &'static str, target_type: &'static str, target_id: impl ToString) -> Self {
+        Self {
+            action,
+            target_type,
+            target_id: target_id.to_string(),
+            before: None,
+            after: None,
+        }
+    }
+
+    /// The state of the target before the change, if it existed.
+    pub fn before(mut self, state: Value) -> Self {
+        self.before = Some(state);
+        self
+    }
+
+    /// The state of the target after the change, if it still exists.
+    pub fn after(mut self, state: Value) -> Self {
+        self.after = Some(state);
+        self
+    }
+}
+
+/// Keep only the fields that changed, when both states are objects: the log
+/// shows the change rather than the whole record.
+fn diff(before: Option<Value>, after: Option<Value>) -> (Option<Value>, Option<Value>) {
+    match (before, after) {
+        (Some(Value::Object(mut before)), Some(Value::Object(mut after))) => {
+            let unchanged: Vec<String> = before
+                .iter()
+                .filter(|(key, value)| after.get(*key) == Some(value))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in unchanged {
+                before.remove(&key);
+                after.remove(&key);
+            }
+            (Some(Value::Object(before)), Some(Value::Object(after)))
+        }
+        states => states,
+    }
+}
+
+/// Record `event` as done by whoever made `request`. Every admin handler
+/// that changes something goes through here, in the same transaction as the
+/// change when it has one.
+#[tracing::instrument(
+    name = "Record an audit event",
+    skip_all,
+    fields(action = event.action, target_id = %event.target_id)
+)]
+pub async fn record_audit_event<'e>(
+    executor: impl PgExecutor<'e>,
+    request: &HttpRequest,
+    event: AuditEvent,
+) -> Result<(), sqlx::Error> {
+    let (actor_user_id, actor_api_key_id, request_id) = {
+        let extensions = request.extensions();
+        (
+            // Set by `reject_anonymous_users`, and by `Authorized` for API keys.
+            extensions.get::<UserId>().map(|user_id| **user_id),
+            extensions.get::<ApiKey>().map(|api_key| api_key.api_key_id),
+            // Set by `assign_request_id`.
+            extensions.get::<RequestId>().map(|id| id.to_string()),
+        )
+    };
+    let (before, after) = diff(event.before, event.after);
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (
+            occurred_at,
+            actor_user_id,
+            actor_api_key_id,
+            action,
+            target_type,
+            target_id,
+            before_state,
+            after_state,
+            ip_address,
+            request_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        Utc::now(),
+        actor_user_id,
+        actor_api_key_id,
+        event.action,
+        event.target_type,
+        event.target_id,
+        before,
+        after,
+        client_ip_address(request),
+        request_id,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::diff;
+    use serde_json::json;
+
+    #[test]
+    fn only_changed_fields_are_kept() {
+        let (before, after) = diff(
+            Some(json!({ "title": "Old", "private": false, "segment_id": null })),
+            Some(json!({ "title": "New", "private": false, "segment_id": "abc" })),
+        );
+
+        assert_eq!(before, Some(json!({ "title": "Old", "segment_id": null })));
+        assert_eq!(after, Some(json!({ "title": "New", "segment_id": "abc" })));
+    }
+
+    #[test]
+    fn fields_that_appear_or_disappear_are_kept() {
+        let (before, after) = diff(Some(json!({ "a": 1 })), Some(json!({ "b": 2 })));
+
+        assert_eq!(before, Some(json!({ "a": 1 })));
+        assert_eq!(after, Some(json!({ "b": 2 })));
+    }
+
+    #[test]
+    fn creations_and_deletions_are_kept_whole() {
+        let state = json!({ "name": "CRM sync" });
+
+        assert_eq!(diff(None, Some(state.clone())), (None, Some(state.clone())));
+        assert_eq!(diff(Some(state.clone()), None), (Some(state), None));
+    }
+}
@@ -0,0 +1,296 @@
+//! API keys let machine clients (partner integrations, scripts, ...) call
+//! the API without the admin password.
+//!
+//! A key looks like `zp_<prefix>_<secret>`. The prefix is stored as is, to
+//! look keys up and tell them apart in the UI; the key as a whole is only
+//! stored hashed. Keys are long and random, so a fast hash is enough: there
+//! is nothing to brute-force.
+
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use uuid::Uuid;
+
+use super::UserId;
+use crate::utils::{constant_time_eq, e500};
+
+const KEY_PREFIX: &str = "zp_";
+const PREFIX_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+
+/// What an API key is allowed to do.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "subscribers:read")]
+    SubscribersRead,
+    #[serde(rename = "subscribers:write")]
+    SubscribersWrite,
+    /// Create and edit drafts.
+    #[serde(rename = "issues:write")]
+    IssuesWrite,
+    /// Schedule and send issues.
+    #[serde(rename = "issues:publish")]
+    IssuesPublish,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::SubscribersRead => "subscribers:read",
+            Scope::SubscribersWrite => "subscribers:write",
+            Scope::IssuesWrite => "issues:write",
+            Scope::IssuesPublish => "issues:publish",
+        }
+    }
+}
+
+/// A freshly generated key, before it is stored.
+pub struct NewApiKey {
+    /// Shown once, and never stored.
+    pub key: String,
+    pub prefix: String,
+    pub key_hash: String,
+}
+
+fn random_string(length: usize) -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+impl NewApiKey {
+    pub fn generate() -> Self {
+        let prefix = format!("{}{}", KEY_PREFIX, random_string(PREFIX_LENGTH));
+        let key = format!("{}_{}", prefix, random_string(SECRET_LENGTH));
+        Self {
+            key_hash: hash_key(&key),
+            key,
+            prefix,
+        }
+    }
+}
+
+/// The public part of `key`, if it looks like one of ours.
+fn parse_prefix(key: &str) -> Option<&str> {
+    let (prefix, secret) = key.rsplit_once('_')?;
+    let valid = prefix.len() == KEY_PREFIX.len() + PREFIX_LENGTH
+        && prefix.starts_with(KEY_PREFIX)
+        && secret.len() == SECRET_LENGTH;
+    valid.then_some(prefix)
+}
+
+/// The key a request authenticates with, checked and still valid.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub api_key_id: Uuid,
+    scopes: Vec<String>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| s == scope.as_str())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiKeyError {
+    #[error("Invalid API key.")]
+    InvalidKey,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Look up `key` and record that it was used. Unknown, expired and revoked
+/// keys are all rejected in the same way.
+#[tracing::instrument(name = "Authenticate an API key", skip(key, pool))]
+pub async fn authenticate_api_key(key: &str, pool: &PgPool) -> Result<ApiKey, ApiKeyError> {
+    let prefix = parse_prefix(key).ok_or(ApiKeyError::InvalidKey)?;
+    let stored = sqlx::query!(
+        r#"
+        SELECT api_key_id, key_hash, scopes
+        FROM api_keys
+        WHERE prefix = $1
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > now())
+        "#,
+        prefix,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the API key.")?
+    .ok_or(ApiKeyError::InvalidKey)?;
+    if !constant_time_eq(&hash_key(key), &stored.key_hash) {
+        return Err(ApiKeyError::InvalidKey);
+    }
+    sqlx::query!(
+        "UPDATE api_keys SET last_used_at = $2 WHERE api_key_id = $1",
+        stored.api_key_id,
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the use of the API key.")?;
+    Ok(ApiKey {
+        api_key_id: stored.api_key_id,
+        scopes: stored.scopes,
+    })
+}
+
+/// Ties a scope to a type, so that handlers can name the scope they need
+/// in their signature: `_: Authorized<IssuesPublish>`.
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+macro_rules! required_scope {
+    ($name:ident) => {
+        pub struct $name;
+
+        impl RequiredScope for $name {
+            const SCOPE: Scope = Scope::$name;
+        }
+    };
+}
+
+required_scope!(SubscribersRead);
+required_scope!(SubscribersWrite);
+required_scope!(IssuesWrite);
+required_scope!(IssuesPublish);
+
+/// Who is making the request.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    User(UserId),
+    ApiKey(ApiKey),
+}
+
+/// Extracts the caller of a handler that machine clients may use, too.
+///
+/// Requests with an `Authorization: Bearer` header must carry a valid key
+/// with the scope `S`. Otherwise, the caller must be a user let through by
+/// `reject_anonymous_users` (and by the role check of the route): a session
+/// alone is not enough, or it would bypass the roles outside `/admin`.
+pub struct Authorized<S: RequiredScope> {
+    pub principal: Principal,
+    scope: PhantomData<S>,
+}
+
+fn unauthorized(e: anyhow::Error) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .finish();
+    InternalError::from_response(e, response).into()
+}
+
+fn bearer_token(request: &HttpRequest) -> Option<Result<String, actix_web::Error>> {
+    let value = request.headers().get(header::AUTHORIZATION)?;
+    let token = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_owned())
+        .ok_or_else(|| unauthorized(anyhow::anyhow!("Malformed 'Authorization' header.")));
+    Some(token)
+}
+
+impl<S: RequiredScope + 'static> FromRequest for Authorized<S> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let authorized = |principal| Authorized {
+            principal,
+            scope: PhantomData,
+        };
+        let token = bearer_token(request);
+        // Set by `reject_anonymous_users` in the admin scope.
+        let user_id = request.extensions().get::<UserId>().copied();
+        let pool = request.app_data::<web::Data<PgPool>>().cloned();
+        let request = request.clone();
+        Box::pin(async move {
+            match token {
+                Some(token) => {
+                    let pool = pool.ok_or_else(|| e500("No database pool is configured."))?;
+                    let api_key = match authenticate_api_key(&token?, &pool).await {
+                        Ok(api_key) => api_key,
+                        Err(ApiKeyError::InvalidKey) => {
+                            return Err(unauthorized(anyhow::anyhow!("Invalid API key.")))
+                        }
+                        Err(e) => return Err(e500(e)),
+                    };
+                    if !api_key.has_scope(S::SCOPE) {
+                        let e =
+                            anyhow::anyhow!("The API key lacks the `{}` scope.", S::SCOPE.as_str());
+                        let response = HttpResponse::Forbidden()
+                            .body(format!("This requires the `{}` scope.", S::SCOPE.as_str()));
+                        return Err(InternalError::from_response(e, response).into());
+                    }
+                    // For the audit log to know who acted.
+                    request.extensions_mut().insert(api_key.clone());
+                    Ok(authorized(Principal::ApiKey(api_key)))
+                }
+                None => match user_id {
+                    Some(user_id) => Ok(authorized(Principal::User(user_id))),
+                    None => Err(unauthorized(anyhow::anyhow!("No API key was provided."))),
+                },
+            }
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct StoredApiKey {
+    pub api_key_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::api_keys::{parse_prefix, NewApiKey};
+
+    #[test]
+    fn generated_keys_carry_their_prefix() {
+        let new_key = NewApiKey::generate();
+
+        assert_eq!(parse_prefix(&new_key.key), Some(new_key.prefix.as_str()));
+        assert!(new_key.key.starts_with("zp_"));
+        assert_ne!(new_key.key_hash, new_key.key);
+        assert_ne!(new_key.key, NewApiKey::generate().key);
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected_before_any_lookup() {
+        for key in [
+            "",
+            "zp_",
+            "zp_abcdefgh",
+            "zp_abcdefgh_tooshort",
+            "xx_abcdefgh_0123456789abcdef0123456789abcdef",
+            "zp_abc_0123456789abcdef0123456789abcdef",
+        ] {
+            assert_eq!(parse_prefix(key), None, "{}", key);
+        }
+    }
+}
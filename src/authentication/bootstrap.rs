@@ -0,0 +1,77 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::roles::Role;
+use super::{compute_password_hash, validate_new_password};
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BootstrapError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The instance already has an owner: they can invite other admins.")]
+    OwnerExists,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Create the owner of a new instance, who nobody could invite. Refused
+/// once there is an owner, so that it cannot be used to take one over.
+#[tracing::instrument(name = "Create the first owner", skip(password, pool))]
+pub async fn create_first_owner(
+    username: &str,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<Uuid, BootstrapError> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(BootstrapError::ValidationError(
+            "The username cannot be empty.".into(),
+        ));
+    }
+    validate_new_password(&password).map_err(BootstrapError::ValidationError)?;
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // Two of these at once must not both find no owner.
+    sqlx::query!("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to lock the users table.")?;
+    let owner_exists = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE role = $1) AS "exists!""#,
+        Role::Owner.as_str(),
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to check for an existing owner.")?
+    .exists;
+    if owner_exists {
+        return Err(BootstrapError::OwnerExists);
+    }
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        Role::Owner.as_str(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the owner.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to create the first owner.")?;
+    Ok(user_id)
+}
@@ -0,0 +1,105 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::ops::Deref;
+use uuid::Uuid;
+
+use super::roles::Role;
+use crate::session_state::TypedSession;
+use crate::utils::e500;
+
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(pub(super) Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        let response = HttpResponse::Unauthorized().finish();
+        let e = anyhow::anyhow!("The user has not logged in");
+        return Err(InternalError::from_response(e, response).into());
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| e500("No database pool is configured."))?;
+    // Sessions from before the generation was recorded are of the first one.
+    let session_generation = session.get_session_generation().map_err(e500)?.unwrap_or(0);
+    // The user is looked up on every request, so that a change of role
+    // applies at once. The session of a user who has since been removed, or
+    // who changed their password since, is worthless.
+    match get_session_user(user_id, pool).await.map_err(e500)? {
+        Some(user) if user.session_generation == session_generation => {
+            req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(user.role);
+            next.call(req).await
+        }
+        _ => {
+            let response = HttpResponse::Unauthorized().finish();
+            let e = anyhow::anyhow!("The session is no longer valid");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+struct SessionUser {
+    role: Role,
+    session_generation: i32,
+}
+
+#[tracing::instrument(name = "Get the user of a session", skip(pool))]
+async fn get_session_user(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SessionUser>, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT role, session_generation FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the user of a session.")?;
+    row.map(|row| {
+        Ok(SessionUser {
+            role: Role::try_from(row.role).map_err(anyhow::Error::msg)?,
+            session_generation: row.session_generation,
+        })
+    })
+    .transpose()
+}
+
+/// Stored in each session at login: bumping it logs the user out everywhere.
+#[tracing::instrument(name = "Get the session generation of a user", skip(pool))]
+pub async fn get_session_generation(user_id: Uuid, pool: &PgPool) -> Result<i32, anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT session_generation FROM users WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve the session generation of a user.")?;
+    Ok(row.session_generation)
+}
@@ -0,0 +1,22 @@
+mod api_keys;
+mod bootstrap;
+mod middleware;
+mod password;
+mod roles;
+mod totp;
+
+pub use api_keys::{
+    authenticate_api_key, ApiKey, ApiKeyError, Authorized, IssuesPublish, IssuesWrite, NewApiKey,
+    Principal, RequiredScope, Scope, StoredApiKey, SubscribersRead, SubscribersWrite,
+};
+pub use bootstrap::{create_first_owner, BootstrapError};
+pub use middleware::{get_session_generation, reject_anonymous_users, UserId};
+pub use password::{
+    compute_password_hash, set_password, validate_credentials, validate_new_password, AuthError,
+    Credentials,
+};
+pub use roles::{require_editor, require_owner, Role};
+pub use totp::{
+    generate_recovery_codes, has_totp, hash_recovery_code, verify_second_factor, TotpError,
+    TotpSecret,
+};
@@ -0,0 +1,221 @@
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::telemetry::spawn_blocking_with_tracing;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+/// Argon2 does not care, but there is no point hashing a novel.
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+static BREACHED_PASSWORDS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    include_str!("breached_passwords.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    Ok(row)
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    // We verify against a dummy hash when the username is unknown, so that
+    // response times do not reveal which usernames exist.
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Hash `password` for storage.
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+/// Check a password somebody picked against our rules: a sensible length,
+/// and not one of the passwords attackers try first.
+pub fn validate_new_password(password: &Secret<String>) -> Result<(), String> {
+    let password = password.expose_secret();
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Passwords must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(format!(
+            "Passwords must be at most {} characters long.",
+            MAX_PASSWORD_LENGTH
+        ));
+    }
+    if BREACHED_PASSWORDS.contains(password.to_lowercase().as_str()) {
+        return Err("This password is known from data breaches: pick another one.".into());
+    }
+    Ok(())
+}
+
+/// Replace the password of `user_id`. This logs them out of every session,
+/// and voids the password reset links they were sent.
+#[tracing::instrument(name = "Set the password of a user", skip(password, pool))]
+pub async fn set_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $2, session_generation = session_generation + 1
+        WHERE user_id = $1
+        "#,
+        user_id,
+        password_hash.expose_secret(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change the password hash.")?;
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the password reset tokens.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to set a password.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::password::validate_new_password;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn check(password: &str) -> Result<(), String> {
+        validate_new_password(&Secret::new(password.to_owned()))
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert_err!(check("short"));
+        assert_err!(check("elevenchars"));
+        assert_ok!(check("twelve chars"));
+    }
+
+    #[test]
+    fn long_passwords_are_rejected() {
+        assert_ok!(check(&"a1".repeat(64)));
+        assert_err!(check(&"a1".repeat(65)));
+    }
+
+    #[test]
+    fn length_is_counted_in_characters_not_bytes() {
+        assert_ok!(check("ééééééééééé1"));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_whatever_their_case() {
+        assert_err!(check("password1234"));
+        assert_err!(check("Password1234"));
+        assert_err!(check("CorrectHorseBatteryStaple"));
+    }
+}
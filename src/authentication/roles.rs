@@ -0,0 +1,96 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse};
+
+/// What an admin user is allowed to do. Each role can do everything the
+/// ones before it can.
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Reads issues, their statistics and the segments.
+    Viewer,
+    /// Drafts issues, but cannot send them.
+    Editor,
+    /// Sends issues, manages subscribers, integrations and other admins.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "owner" => Ok(Role::Owner),
+            other => Err(format!("`{}` is not a role.", other)),
+        }
+    }
+}
+
+async fn require_role(
+    minimum: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // Set by `reject_anonymous_users`, which wraps the whole admin scope.
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= minimum => next.call(req).await,
+        _ => {
+            let response = HttpResponse::Forbidden()
+                .body(format!("This requires the `{}` role.", minimum.as_str()));
+            let e = anyhow::anyhow!("The user does not have the `{}` role.", minimum.as_str());
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// Only let editors and owners through.
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Only let owners through.
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::roles::Role;
+
+    #[test]
+    fn each_role_includes_the_ones_before_it() {
+        assert!(Role::Owner > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_name() {
+        for role in [Role::Viewer, Role::Editor, Role::Owner] {
+            assert_eq!(Role::try_from(role.as_str().to_owned()), Ok(role));
+        }
+        assert!(Role::try_from("admin".to_owned()).is_err());
+    }
+}
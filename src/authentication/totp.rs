@@ -0,0 +1,362 @@
+//! Time-based one-time passwords (RFC 6238), the second step of admin
+//! logins once enabled.
+//!
+//! Codes are 6 digits, derived with HMAC-SHA1 from a shared secret and the
+//! current 30-second time step: the defaults of every authenticator app.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng, RngCore};
+use sha1::Sha1;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::utils::{constant_time_eq, hash_token};
+
+const DIGITS: usize = 6;
+const STEP_SECONDS: i64 = 30;
+const SECRET_BYTES: usize = 20;
+/// Codes of the steps just before and after the current one are accepted
+/// too, to make up for clock drift and slow typing.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const ISSUER: &str = "zero2prod";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Failed attempts in a row before the second step is locked.
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+const LOCKOUT_MINUTES: i64 = 15;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.chars().filter(|c| *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// RFC 4226: the code for `counter`, before zero-padding.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS as u32)
+}
+
+fn time_step(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(STEP_SECONDS)
+}
+
+/// The secret shared with the authenticator app of an admin.
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut secret = vec![0; SECRET_BYTES];
+        thread_rng().fill_bytes(&mut secret);
+        Self(secret)
+    }
+
+    pub fn parse(encoded: &str) -> Result<Self, anyhow::Error> {
+        base32_decode(encoded)
+            .filter(|secret| !secret.is_empty())
+            .map(Self)
+            .context("The TOTP secret is not valid base32.")
+    }
+
+    /// The form we store, and authenticator apps expect.
+    pub fn encoded(&self) -> String {
+        base32_encode(&self.0)
+    }
+
+    fn code_for_step(&self, step: i64) -> String {
+        format!("{:0width$}", hotp(&self.0, step as u64), width = DIGITS)
+    }
+
+    pub fn code_at(&self, at: DateTime<Utc>) -> String {
+        self.code_for_step(time_step(at))
+    }
+
+    /// The time step `code` belongs to, if it is valid at `at`.
+    pub fn matching_step(&self, code: &str, at: DateTime<Utc>) -> Option<i64> {
+        let current = time_step(at);
+        (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+            .filter(|step| *step >= 0)
+            .find(|step| constant_time_eq(&self.code_for_step(*step), code.trim()))
+    }
+
+    /// What authenticator apps enrol from, usually shown as a QR code.
+    pub fn otpauth_uri(&self, account: &str) -> String {
+        let mut uri = reqwest::Url::parse("otpauth://totp/").expect("The base URI is valid.");
+        uri.set_path(&format!("/{}:{}", ISSUER, account));
+        uri.query_pairs_mut()
+            .append_pair("secret", &self.encoded())
+            .append_pair("issuer", ISSUER)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &STEP_SECONDS.to_string());
+        uri.to_string()
+    }
+}
+
+/// Single-use codes that stand in for the authenticator app when it is
+/// lost, formatted as `abcd-efgh-ijkl-mnop`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(16)
+                .collect();
+            code.as_bytes()
+                .chunks(4)
+                .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes get typed in by hand: dashes, spaces and case do not matter.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalised: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalised)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TotpError {
+    #[error("Invalid code.")]
+    InvalidCode,
+    #[error("Too many failed attempts, try again later.")]
+    TooManyAttempts,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[tracing::instrument(name = "Check whether a user has enabled TOTP", skip(pool))]
+pub async fn has_totp(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!("SELECT totp_secret FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve the TOTP secret of a user.")?;
+    Ok(row.totp_secret.is_some())
+}
+
+/// Check the second factor of `user_id`: a code from their authenticator
+/// app, or one of their unused recovery codes.
+///
+/// After `MAX_FAILED_ATTEMPTS` failures in a row, every attempt is refused
+/// for `LOCKOUT_MINUTES`, valid or not.
+#[tracing::instrument(name = "Verify a second factor", skip(code, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    now: DateTime<Utc>,
+    pool: &PgPool,
+) -> Result<(), TotpError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let user = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_step, failed_totp_attempts, totp_locked_until
+        FROM users
+        WHERE user_id = $1
+        FOR UPDATE
+        "#,
+        user_id,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to retrieve the TOTP state of the user.")?;
+    if user.totp_locked_until.is_some_and(|until| until > now) {
+        return Err(TotpError::TooManyAttempts);
+    }
+    let secret = TotpSecret::parse(
+        user.totp_secret
+            .as_deref()
+            .context("The user has not enabled TOTP.")?,
+    )?;
+    // A code that was already used, even if still valid, is a replay.
+    let step = secret
+        .matching_step(code, now)
+        .filter(|step| user.totp_last_step.is_none_or(|last| *step > last));
+    let verified =
+        step.is_some() || use_recovery_code(&mut transaction, user_id, code, now).await?;
+    let (failed_attempts, locked_until) = if verified {
+        (0, None)
+    } else if user.failed_totp_attempts + 1 >= MAX_FAILED_ATTEMPTS {
+        (0, Some(now + chrono::Duration::minutes(LOCKOUT_MINUTES)))
+    } else {
+        (user.failed_totp_attempts + 1, None)
+    };
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_step = COALESCE($2, totp_last_step),
+            failed_totp_attempts = $3,
+            totp_locked_until = $4
+        WHERE user_id = $1
+        "#,
+        user_id,
+        step,
+        failed_attempts,
+        locked_until,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the TOTP state of the user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to verify a second factor.")?;
+    if verified {
+        Ok(())
+    } else {
+        Err(TotpError::InvalidCode)
+    }
+}
+
+async fn use_recovery_code(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    code: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let used = sqlx::query!(
+        r#"
+        UPDATE totp_recovery_codes
+        SET used_at = $3
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code),
+        now,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to use a recovery code.")?;
+    Ok(used.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::totp::{
+        base32_decode, base32_encode, generate_recovery_codes, hash_recovery_code, TotpSecret,
+    };
+    use chrono::{TimeZone, Utc};
+
+    /// The SHA-1 secret of the test vectors in RFC 6238, appendix B.
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(b"12345678901234567890".to_vec())
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        // The RFC gives 8 digits: ours are the last 6.
+        for (timestamp, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            let at = Utc.timestamp_opt(timestamp, 0).unwrap();
+            assert_eq!(rfc_secret().code_at(at), code, "at {}", timestamp);
+        }
+    }
+
+    #[test]
+    fn codes_of_neighbouring_steps_are_accepted() {
+        let secret = TotpSecret::generate();
+        let at = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+
+        for offset in [-30, 0, 30] {
+            let code = secret.code_at(at + chrono::Duration::seconds(offset));
+            assert!(secret.matching_step(&code, at).is_some(), "{}", offset);
+        }
+        let stale = secret.code_at(at - chrono::Duration::seconds(90));
+        assert_eq!(secret.matching_step(&stale, at), None);
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(
+            base32_encode(b"12345678901234567890"),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        for length in 0..12 {
+            let bytes: Vec<u8> = (0..length).map(|i: u8| i.wrapping_mul(37)).collect();
+            assert_eq!(base32_decode(&base32_encode(&bytes)), Some(bytes));
+        }
+        assert_eq!(base32_decode("not base32!"), None);
+    }
+
+    #[test]
+    fn the_otpauth_uri_carries_the_secret() {
+        let secret = rfc_secret();
+
+        let uri = secret.otpauth_uri("ursula le guin");
+
+        assert!(uri.starts_with("otpauth://totp/zero2prod:ursula%20le%20guin?"));
+        assert!(uri.contains("secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_forgiving_to_type() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), 10);
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&format!(" {} ", codes[0].replace('-', "").to_uppercase()))
+        );
+    }
+}
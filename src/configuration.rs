@@ -4,6 +4,9 @@ use  secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use crate::domain::{SubscriberEmail};
+use crate::email_client::{DkimSigner, EmailClient};
+use crate::feed_poller::FeedClient;
+use crate::outbound_webhooks::WebhookClient;
 
 
 #[derive(serde::Deserialize, Clone)]
@@ -11,18 +14,178 @@ pub struct Settings {
     pub database: DatabaseSettings,
     // pub application_port: u16,
     pub application: ApplicationSetting,
-    pub email_client: EmailClientSettings
+    pub email_client: EmailClientSettings,
+    pub consent: ConsentSettings,
+    pub delivery: DeliverySettings,
+    pub archive: ArchiveSettings,
+    pub feeds: FeedSettings,
+    pub webhooks: WebhookSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    /// From `APP_ENVIRONMENT`, rather than from the files.
+    #[serde(skip)]
+    pub environment: Environment,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub spool_directory: Option<String>,
+    /// Only used by the `smtp` and `file_spool` transports: Postmark signs
+    /// the emails it sends itself.
+    pub dkim: Option<DkimSettings>,
+    pub postmark_webhook: PostmarkWebhookSettings,
+}
+
+/// Basic auth credentials Postmark must present when calling our webhook.
+#[derive(serde::Deserialize, Clone)]
+pub struct PostmarkWebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// How emails leave the application.
+#[derive(serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransportKind {
+    /// Postmark's HTTP API, at `base_url`.
+    #[default]
+    Postmark,
+    /// Any SMTP relay, configured in `smtp`.
+    Smtp,
+    /// `.eml` files in `spool_directory`, for local development.
+    FileSpool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// TLS from the first byte, usually on port 465.
+    Tls,
+    /// Plain connection upgraded with STARTTLS, usually on port 587.
+    #[default]
+    StartTls,
+    /// No encryption at all: only for a relay on the same host.
+    None,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct DkimSettings {
+    pub domain: String,
+    pub selector: String,
+    /// A PEM file: PKCS#1 or PKCS#8 for RSA keys, PKCS#8 for ed25519 ones.
+    pub private_key_path: String,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+    #[serde(default = "default_dkim_signed_headers")]
+    pub signed_headers: Vec<String>,
+    #[serde(default)]
+    pub header_canonicalization: Canonicalization,
+    #[serde(default)]
+    pub body_canonicalization: Canonicalization,
+}
+
+fn default_dkim_signed_headers() -> Vec<String> {
+    [
+        "From",
+        "Reply-To",
+        "Subject",
+        "Date",
+        "Message-ID",
+        "To",
+        "Cc",
+        "MIME-Version",
+        "Content-Type",
+        "List-Unsubscribe",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+impl DkimSettings {
+    pub fn private_key(&self) -> Result<Secret<String>, std::io::Error> {
+        std::fs::read_to_string(&self.private_key_path).map(Secret::new)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DkimAlgorithm {
+    #[default]
+    RsaSha256,
+    Ed25519Sha256,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Canonicalization {
+    Simple,
+    /// Survives the whitespace changes some relays make.
+    #[default]
+    Relaxed,
+}
+
+impl Canonicalization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Canonicalization::Simple => "simple",
+            Canonicalization::Relaxed => "relaxed",
+        }
+    }
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let dkim_signer = self.dkim.as_ref().map(|settings| {
+            let private_key = settings
+                .private_key()
+                .expect("Failed to read the DKIM private key.");
+            DkimSigner::new(settings, private_key).expect("Invalid DKIM private key.")
+        });
+        let client = match self.transport {
+            EmailTransportKind::Postmark => EmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout
+            ),
+            EmailTransportKind::Smtp => {
+                let smtp = self.smtp.expect("The SMTP transport needs `email_client.smtp`.");
+                EmailClient::smtp(sender_email, &smtp, timeout)
+                    .expect("Failed to configure the SMTP transport.")
+            }
+            EmailTransportKind::FileSpool => {
+                let directory = self
+                    .spool_directory
+                    .expect("The file spool transport needs `email_client.spool_directory`.");
+                EmailClient::file_spool(sender_email, directory.into())
+            }
+        };
+        match dkim_signer {
+            Some(signer) => client.with_dkim(signer),
+            None => client,
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -47,24 +210,124 @@ pub struct DatabaseSettings {
 pub struct ApplicationSetting{
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
-    pub host: String
+    pub host: String,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub templates_directory: String,
+    /// Keep the `X-Request-Id` of incoming requests rather than generating
+    /// one. Only safe behind a proxy that sets it, or strips it.
+    #[serde(default)]
+    pub trust_request_id_header: bool,
+    /// Take the client's address from `Forwarded` or `X-Forwarded-For`
+    /// rather than from the connection. Only safe behind a proxy that sets
+    /// them.
+    #[serde(default)]
+    pub trust_forwarded_headers: bool,
 }
 
+/// What we show people when we ask for (or act on) their consent.
+/// Stored alongside every consent event so that we can later prove
+/// exactly what somebody agreed to.
+#[derive(serde::Deserialize, Clone)]
+pub struct ConsentSettings {
+    pub privacy_policy_version: String,
+    pub signup_wording: String,
+    pub confirmation_wording: String,
+    pub unsubscribe_wording: String,
+    pub preference_change_wording: String,
+    /// Recorded for subscribers added through an import.
+    pub import_wording: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct DeliverySettings {
+    /// Used for subscribers who did not tell us their timezone.
+    pub default_timezone: chrono_tz::Tz,
+}
+
+/// How the public archive of past issues presents itself.
+#[derive(serde::Deserialize, Clone)]
+pub struct ArchiveSettings {
+    /// Used as the title of the archive pages and of the feeds.
+    pub title: String,
+    pub description: String,
+    /// How many issues each archive page (and each feed) lists.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub page_size: u16,
+}
+
+/// External feeds (such as the blog's) that issues are generated from.
+#[derive(serde::Deserialize, Clone)]
+pub struct FeedSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    #[serde(default)]
+    pub sources: Vec<FeedSource>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct FeedSource {
+    /// Identifies the feed in the database: keep it when the URL changes,
+    /// otherwise every item looks new.
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub mode: FeedMode,
+    /// Combine the new items of each week into a single issue.
+    #[serde(default)]
+    pub digest: bool,
+    /// Defaults to "This week on <name>".
+    pub digest_title: Option<String>,
+}
+
+/// What happens to the issues generated from a feed.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedMode {
+    /// Left as drafts, for somebody to review and publish.
+    #[default]
+    Draft,
+    /// Sent to every confirmed subscriber straight away.
+    Publish,
+}
+
+impl FeedSettings {
+    pub fn client(&self) -> FeedClient {
+        FeedClient::new(self.timeout())
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum Environment {
+    #[default]
     Local,
     Production
 }
 
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let environment: Environment = std::env::var("APP_ENVIRONMENT")
+        .unwrap_or_else(|_| "local".into())
+        .try_into()
+        .map_err(|e| config::ConfigError::Message(format!("APP_ENVIRONMENT: {}", e)))?;
+    read_configuration(environment)
+}
+
+fn read_configuration(environment: Environment) -> Result<Settings, config::ConfigError> {
     let base_path = std::env::current_dir()
         .expect("Failed to determine the current directory");
     let configurtion_directory = base_path.join("configuration");
 
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
     let enviroment_filename = format!("{}.yaml", environment.as_str());
 
     let settings = config::Config::builder()
@@ -81,7 +344,9 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    let mut settings = settings.try_deserialize::<Settings>()?;
+    settings.environment = environment;
+    Ok(settings)
 }
 
 impl DatabaseSettings {
@@ -117,7 +382,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -151,4 +416,397 @@ impl TryFrom<String> for Environment {
         }
     }
     
-}
\ No newline at end of file
+}
+
+/// Deliveries of outbound webhooks, see `crate::outbound_webhooks`.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// Deliveries are given up on after this many failed attempts.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i16,
+}
+
+impl WebhookSettings {
+    pub fn client(&self) -> WebhookClient {
+        WebhookClient::new(self.timeout())
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// What goes into our logs, see `crate::telemetry`.
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    /// Mask subscribers' email addresses and names. Only worth turning off
+    /// on a developer's machine.
+    pub redact_pii: bool,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self { redact_pii: true }
+    }
+}
+
+/// Something wrong with one configuration value, under the key that sets it.
+#[derive(Debug)]
+pub struct ConfigurationProblem {
+    pub key: String,
+    pub message: String,
+}
+
+/// Every problem `Settings::validate` found, rather than only the first one.
+#[derive(Debug)]
+pub struct InvalidConfiguration(pub Vec<ConfigurationProblem>);
+
+impl std::fmt::Display for InvalidConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration has {} problem(s):", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}: {}", problem.key, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidConfiguration {}
+
+const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
+#[derive(Default)]
+struct Problems(Vec<ConfigurationProblem>);
+
+impl Problems {
+    fn add(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigurationProblem {
+            key: key.into(),
+            message: message.into(),
+        });
+    }
+
+    fn check(&mut self, ok: bool, key: impl Into<String>, message: impl Into<String>) {
+        if !ok {
+            self.add(key, message);
+        }
+    }
+
+    fn check_url(&mut self, key: impl Into<String>, url: &str) {
+        match reqwest::Url::parse(url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => self.add(key, format!("`{}` is not an http(s) URL", url)),
+            Err(e) => self.add(key, format!("`{}` is not a valid URL: {}", url, e)),
+        }
+    }
+
+    fn check_secret(&mut self, key: impl Into<String>, secret: &Secret<String>) {
+        self.check(
+            !secret.expose_secret().trim().is_empty(),
+            key,
+            "must not be empty",
+        );
+    }
+}
+
+impl Settings {
+    /// Check what deserialization cannot, so that the binary refuses to start
+    /// instead of failing on the first email, webhook or feed.
+    pub fn validate(&self) -> Result<(), InvalidConfiguration> {
+        let production = self.environment == Environment::Production;
+        let mut problems = Problems::default();
+
+        let application = &self.application;
+        problems.check_url("application.base_url", &application.base_url);
+        problems.check(
+            !application.base_url.ends_with('/'),
+            "application.base_url",
+            "must not end with a `/`",
+        );
+        // Also the key of the session cookies, which must have 64 bytes.
+        problems.check(
+            application.hmac_secret.expose_secret().len() >= MIN_HMAC_SECRET_LENGTH,
+            "application.hmac_secret",
+            format!("must be at least {} bytes long", MIN_HMAC_SECRET_LENGTH),
+        );
+        problems.check(
+            std::path::Path::new(&application.templates_directory).is_dir(),
+            "application.templates_directory",
+            format!("`{}` is not a directory", application.templates_directory),
+        );
+
+        let database = &self.database;
+        problems.check(
+            database.port != 0,
+            "database.port",
+            "must be between 1 and 65535",
+        );
+        problems.check(
+            !database.host.is_empty(),
+            "database.host",
+            "must not be empty",
+        );
+        problems.check(
+            !database.database_name.is_empty(),
+            "database.database_name",
+            "must not be empty",
+        );
+        if production {
+            problems.check_secret("database.password", &database.password);
+        }
+
+        self.email_client.validate(production, &mut problems);
+
+        problems.check(
+            self.archive.page_size > 0,
+            "archive.page_size",
+            "must be at least 1",
+        );
+
+        let webhooks = &self.webhooks;
+        problems.check(
+            webhooks.timeout_milliseconds > 0,
+            "webhooks.timeout_milliseconds",
+            "must be at least 1",
+        );
+        problems.check(
+            webhooks.max_attempts > 0,
+            "webhooks.max_attempts",
+            "must be at least 1",
+        );
+
+        let feeds = &self.feeds;
+        problems.check(
+            feeds.poll_interval_seconds > 0,
+            "feeds.poll_interval_seconds",
+            "must be at least 1",
+        );
+        problems.check(
+            feeds.timeout_milliseconds > 0,
+            "feeds.timeout_milliseconds",
+            "must be at least 1",
+        );
+        for (i, source) in feeds.sources.iter().enumerate() {
+            let key = |field: &str| format!("feeds.sources[{}].{}", i, field);
+            problems.check(
+                !source.name.trim().is_empty(),
+                key("name"),
+                "must not be empty",
+            );
+            problems.check(
+                !feeds.sources[..i]
+                    .iter()
+                    .any(|other| other.name == source.name),
+                key("name"),
+                format!("`{}` is used by another source", source.name),
+            );
+            problems.check_url(key("url"), &source.url);
+        }
+
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidConfiguration(problems.0))
+        }
+    }
+}
+
+impl EmailClientSettings {
+    fn validate(&self, production: bool, problems: &mut Problems) {
+        if let Err(e) = self.sender() {
+            problems.add("email_client.sender_email", e);
+        }
+        problems.check(
+            self.timeout_milliseconds > 0,
+            "email_client.timeout_milliseconds",
+            "must be at least 1",
+        );
+        match self.transport {
+            EmailTransportKind::Postmark => {
+                problems.check_url("email_client.base_url", &self.base_url);
+                problems.check_secret(
+                    "email_client.authorization_token",
+                    &self.authorization_token,
+                );
+                if production {
+                    problems.check_secret(
+                        "email_client.postmark_webhook.password",
+                        &self.postmark_webhook.password,
+                    );
+                }
+            }
+            EmailTransportKind::Smtp => match &self.smtp {
+                Some(smtp) => {
+                    problems.check(
+                        !smtp.host.is_empty(),
+                        "email_client.smtp.host",
+                        "must not be empty",
+                    );
+                    problems.check(
+                        smtp.port != 0,
+                        "email_client.smtp.port",
+                        "must be between 1 and 65535",
+                    );
+                    problems.check(
+                        smtp.username.is_some() == smtp.password.is_some(),
+                        "email_client.smtp",
+                        "set both `username` and `password`, or neither",
+                    );
+                }
+                None => problems.add("email_client.smtp", "must be set for the `smtp` transport"),
+            },
+            EmailTransportKind::FileSpool => problems.check(
+                self.spool_directory.is_some(),
+                "email_client.spool_directory",
+                "must be set for the `file_spool` transport",
+            ),
+        }
+        if let Some(dkim) = &self.dkim {
+            problems.check(
+                !dkim.domain.is_empty(),
+                "email_client.dkim.domain",
+                "must not be empty",
+            );
+            problems.check(
+                !dkim.selector.is_empty(),
+                "email_client.dkim.selector",
+                "must not be empty",
+            );
+            match dkim.private_key() {
+                Ok(key) => {
+                    if let Err(e) = DkimSigner::new(dkim, key) {
+                        problems.add("email_client.dkim.private_key_path", format!("{:#}", e));
+                    }
+                }
+                Err(e) => problems.add(
+                    "email_client.dkim.private_key_path",
+                    format!("cannot read `{}`: {}", dkim.private_key_path, e),
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_configuration, read_configuration, Environment, Settings};
+    use secrecy::Secret;
+
+    fn settings() -> Settings {
+        get_configuration().expect("Failed to read the configuration")
+    }
+
+    fn problem_keys(settings: &Settings) -> Vec<String> {
+        match settings.validate() {
+            Ok(()) => vec![],
+            Err(e) => e.0.into_iter().map(|problem| problem.key).collect(),
+        }
+    }
+
+    #[test]
+    fn the_checked_in_configuration_is_valid() {
+        assert!(settings().validate().is_ok());
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let mut settings = settings();
+        settings.application.base_url = "127.0.0.1:8000".into();
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.email_client.authorization_token = Secret::new("".into());
+        settings.database.port = 0;
+
+        let error = settings.validate().unwrap_err();
+
+        let keys: Vec<_> = error.0.iter().map(|problem| problem.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "application.base_url",
+                "database.port",
+                "email_client.sender_email",
+                "email_client.timeout_milliseconds",
+                "email_client.authorization_token",
+            ]
+        );
+        let report = error.to_string();
+        assert!(report.starts_with("The configuration has 5 problem(s):"));
+        assert!(report.contains("\n  - database.port: must be between 1 and 65535"));
+    }
+
+    #[test]
+    fn secrets_may_only_be_left_out_locally() {
+        let mut settings = settings();
+        settings.database.password = Secret::new("".into());
+        settings.email_client.postmark_webhook.password = Secret::new(" ".into());
+        assert!(problem_keys(&settings).is_empty());
+
+        settings.environment = Environment::Production;
+
+        assert_eq!(
+            problem_keys(&settings),
+            [
+                "database.password",
+                "email_client.postmark_webhook.password"
+            ]
+        );
+    }
+
+    #[test]
+    fn production_secrets_are_not_checked_in() {
+        let settings = read_configuration(Environment::Production).unwrap();
+
+        let keys = problem_keys(&settings);
+
+        for key in [
+            "application.hmac_secret",
+            "database.password",
+            "email_client.authorization_token",
+            "email_client.postmark_webhook.password",
+        ] {
+            assert!(keys.iter().any(|k| k == key), "{} was accepted", key);
+        }
+    }
+
+    #[test]
+    fn short_hmac_secrets_are_rejected() {
+        let mut settings = settings();
+        settings.application.hmac_secret = Secret::new("a".repeat(63));
+        assert_eq!(problem_keys(&settings), ["application.hmac_secret"]);
+
+        settings.application.hmac_secret = Secret::new("a".repeat(64));
+        assert!(problem_keys(&settings).is_empty());
+    }
+
+    #[test]
+    fn the_application_may_listen_on_any_free_port() {
+        let mut settings = settings();
+        settings.application.port = 0;
+
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn feed_sources_need_a_unique_name_and_a_url() {
+        let mut settings = settings();
+        let source = |name: &str, url: &str| super::FeedSource {
+            name: name.into(),
+            url: url.into(),
+            mode: Default::default(),
+            digest: false,
+            digest_title: None,
+        };
+        settings.feeds.sources = vec![
+            source("blog", "https://blog.example.com/rss.xml"),
+            source("blog", "ftp://blog.example.com/rss.xml"),
+        ];
+
+        assert_eq!(
+            problem_keys(&settings),
+            ["feeds.sources[1].name", "feeds.sources[1].url"]
+        );
+    }
+}
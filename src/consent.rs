@@ -0,0 +1,156 @@
+use actix_web::http::header;
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Everything that can change what a subscriber agreed to.
+#[derive(Debug, Clone, Copy)]
+pub enum ConsentEventType {
+    Signup,
+    Confirm,
+    Unsubscribe,
+    PreferenceChange,
+    /// Added by an administrator, with consent collected elsewhere.
+    Import,
+}
+
+impl ConsentEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentEventType::Signup => "signup",
+            ConsentEventType::Confirm => "confirm",
+            ConsentEventType::Unsubscribe => "unsubscribe",
+            ConsentEventType::PreferenceChange => "preference_change",
+            ConsentEventType::Import => "import",
+        }
+    }
+}
+
+/// Where a consent event came from, as seen by the HTTP request that
+/// triggered it.
+#[derive(Debug, Default)]
+pub struct ConsentContext {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: Option<String>,
+}
+
+impl ConsentContext {
+    /// `source` is the form identifier submitted by the client, if any;
+    /// we fall back to the `Referer` header otherwise.
+    pub fn from_request(request: &HttpRequest, source: Option<String>) -> Self {
+        let header_value = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        Self {
+            ip_address: client_ip_address(request),
+            user_agent: header_value(header::USER_AGENT),
+            source: source
+                .filter(|s| !s.trim().is_empty())
+                .or_else(|| header_value(header::REFERER)),
+        }
+    }
+}
+
+/// Whether the client's address can be taken from the `Forwarded` and
+/// `X-Forwarded-For` headers. Only when a proxy in front of us sets them:
+/// anybody else can write whatever they like in there.
+#[derive(Clone, Copy)]
+pub struct ClientAddressPolicy {
+    pub trust_forwarded_headers: bool,
+}
+
+/// The address of the client: the peer of the connection, unless we are
+/// configured to trust the proxy in front of us to report it.
+pub fn client_ip_address(request: &HttpRequest) -> Option<String> {
+    let trust_forwarded_headers = request
+        .app_data::<web::Data<ClientAddressPolicy>>()
+        .is_some_and(|policy| policy.trust_forwarded_headers);
+    if !trust_forwarded_headers {
+        return request.peer_addr().map(|peer| peer.ip().to_string());
+    }
+    request.connection_info().realip_remote_addr().map(|addr| {
+        match addr.parse::<std::net::SocketAddr>() {
+            Ok(socket) => socket.ip().to_string(),
+            Err(_) => addr.to_owned(),
+        }
+    })
+}
+
+#[derive(serde::Serialize)]
+pub struct ConsentEventRecord {
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: Option<String>,
+    pub privacy_policy_version: String,
+    pub wording: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Record a consent event",
+    skip(transaction, context, privacy_policy_version, wording)
+)]
+pub async fn record_consent_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    event_type: ConsentEventType,
+    context: &ConsentContext,
+    privacy_policy_version: &str,
+    wording: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (
+            consent_event_id,
+            subscription_id,
+            event_type,
+            ip_address,
+            user_agent,
+            source,
+            privacy_policy_version,
+            wording,
+            occurred_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        event_type.as_str(),
+        context.ip_address,
+        context.user_agent,
+        context.source,
+        privacy_policy_version,
+        wording,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get consent history", skip(pool))]
+pub async fn get_consent_history(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ConsentEventRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentEventRecord,
+        r#"
+        SELECT event_type, ip_address, user_agent, source,
+            privacy_policy_version, wording, occurred_at
+        FROM consent_events
+        WHERE subscription_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+}
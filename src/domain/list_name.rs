@@ -0,0 +1,51 @@
+/// The name of a mailing list, such as `rust`, that signup forms add
+/// subscribers to and segments select them by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListName(String);
+
+impl ListName {
+    pub fn parse(s: String) -> Result<ListName, String> {
+        let name = s.trim();
+        let is_valid = !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if is_valid {
+            Ok(Self(name.to_owned()))
+        } else {
+            Err(format!(
+                "{} is not a valid list name: use up to 64 lowercase letters, digits, `-` and `_`.",
+                s
+            ))
+        }
+    }
+}
+
+impl AsRef<str> for ListName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::ListName;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn slugs_are_accepted() {
+        let list = ListName::parse(" rust-weekly_2 ".to_string());
+        assert_ok!(&list);
+        assert_eq!(list.unwrap().as_ref(), "rust-weekly_2");
+    }
+
+    #[test]
+    fn empty_long_and_fancy_names_are_rejected() {
+        assert_err!(ListName::parse("".to_string()));
+        assert_err!(ListName::parse("a".repeat(65)));
+        assert_err!(ListName::parse("Rust".to_string()));
+        assert_err!(ListName::parse("rust list".to_string()));
+        assert_err!(ListName::parse("rust'; --".to_string()));
+    }
+}
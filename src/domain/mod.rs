@@ -1,8 +1,14 @@
 mod subscriber_name;
 mod subscriber_email;
 mod new_subscriber;
+mod subscriber_timezone;
+mod list_name;
+mod subscription_status;
 
 pub use subscriber_name::SubscriberName;
 pub use new_subscriber::NewSubscriber;
 
-pub use subscriber_email::SubscriberEmail;
\ No newline at end of file
+pub use subscriber_email::SubscriberEmail;
+pub use subscriber_timezone::SubscriberTimezone;
+pub use list_name::ListName;
+pub use subscription_status::SubscriptionStatus;
@@ -4,10 +4,18 @@
 
 use crate::domain::subscriber_name::SubscriberName;
 use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_timezone::SubscriberTimezone;
+use crate::domain::list_name::ListName;
+use crate::attributes::Attributes;
 
 
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub timezone: Option<SubscriberTimezone>,
+    /// The list the signup form subscribes people to, if any.
+    pub list: Option<ListName>,
+    /// Checked against the attribute schema before the subscriber is stored.
+    pub attributes: Attributes,
 }
\ No newline at end of file
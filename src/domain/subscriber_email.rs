@@ -1,8 +1,9 @@
 
 
+use crate::telemetry::RedactedEmail;
 use validator::validate_email;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -10,7 +11,7 @@ impl SubscriberEmail {
        if validate_email(&s) {
             Ok(Self(s))
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            Err(format!("{} is not a valid subscriber email.", RedactedEmail(&s)))
         }
     }
 }
@@ -21,6 +22,13 @@ impl AsRef<str> for SubscriberEmail {
     }
 }
 
+/// Masked, so that logging a value by mistake does not leak the address.
+impl std::fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubscriberEmail({})", RedactedEmail(&self.0))
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -1,11 +1,6 @@
+use crate::telemetry::RedactedName;
 use unicode_segmentation::UnicodeSegmentation;
 
-
-pub struct NewSubscriber {
-	pub email: String,
-	pub name: SubscriberName,
-}
-#[derive(Debug)]
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -21,7 +16,7 @@ impl SubscriberName {
 			.any(|g| forbidden_characters.contains(&g));
 
 		if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-			Err(format!("{} is not a valid subscriber name.", s))	
+			Err(format!("{} is not a valid subscriber name.", RedactedName(&s)))	
 		} else {
 			Ok(Self(s))
 		}
@@ -39,6 +34,13 @@ impl SubscriberName {
 	}
 }
 
+/// Redacted, so that logging a value by mistake does not leak the name.
+impl std::fmt::Debug for SubscriberName {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "SubscriberName({})", RedactedName(&self.0))
+	}
+}
+
 impl AsRef<str> for SubscriberName {
 	fn as_ref(&self) -> &str {
 		&self.0
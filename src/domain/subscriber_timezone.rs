@@ -0,0 +1,41 @@
+use chrono_tz::Tz;
+
+/// An IANA timezone, such as `Europe/Rome`, used to deliver issues at a
+/// local time of day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriberTimezone(Tz);
+
+impl SubscriberTimezone {
+    pub fn parse(s: String) -> Result<SubscriberTimezone, String> {
+        s.trim()
+            .parse::<Tz>()
+            .map(Self)
+            .map_err(|_| format!("{} is not a valid timezone.", s))
+    }
+}
+
+impl AsRef<str> for SubscriberTimezone {
+    fn as_ref(&self) -> &str {
+        self.0.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTimezone;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn iana_names_are_accepted() {
+        let timezone = SubscriberTimezone::parse("America/New_York".to_string());
+        assert_ok!(&timezone);
+        assert_eq!(timezone.unwrap().as_ref(), "America/New_York");
+    }
+
+    #[test]
+    fn offsets_and_unknown_names_are_rejected() {
+        assert_err!(SubscriberTimezone::parse("".to_string()));
+        assert_err!(SubscriberTimezone::parse("+02:00".to_string()));
+        assert_err!(SubscriberTimezone::parse("Europe/Atlantis".to_string()));
+    }
+}
@@ -0,0 +1,68 @@
+/// Where a subscriber stands, as stored in `subscriptions.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+impl SubscriptionStatus {
+    pub const ALL: [SubscriptionStatus; 5] = [
+        SubscriptionStatus::PendingConfirmation,
+        SubscriptionStatus::Confirmed,
+        SubscriptionStatus::Unsubscribed,
+        SubscriptionStatus::Bounced,
+        SubscriptionStatus::Complained,
+    ];
+
+    /// Case is ignored.
+    pub fn parse(s: &str) -> Result<SubscriptionStatus, String> {
+        let status = s.trim().to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == status)
+            .ok_or_else(|| format!("{} is not a valid subscription status.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+        }
+    }
+}
+
+impl AsRef<str> for SubscriptionStatus {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriptionStatus;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn every_status_is_accepted_whatever_its_case() {
+        for status in SubscriptionStatus::ALL {
+            assert_ok_eq!(SubscriptionStatus::parse(status.as_str()), status);
+            assert_ok_eq!(
+                SubscriptionStatus::parse(&status.as_str().to_uppercase()),
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_are_rejected() {
+        assert_err!(SubscriptionStatus::parse(""));
+        assert_err!(SubscriptionStatus::parse("subscribed"));
+        assert_err!(SubscriptionStatus::parse("confirmed'; --"));
+    }
+}
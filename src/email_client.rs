@@ -1,130 +0,0 @@
-use crate::domain::SubscriberEmail;
-
-use actix_web::{cookie::time, http};
-use reqwest::{Client};
-use secrecy::{Secret, ExposeSecret};
-
-#[derive(serde::Serialize)]
-#[serde(rename_all = "PascalCase")]
-struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    html_body: &'a str,
-    text_body: &'a str,
-}
-// #[derive(Clone)]
-pub struct EmailClient {
-    http_client: Client,
-    base_url: String,
-    sender: SubscriberEmail,
-    authorization_token: Secret<String>,
-}
-
-impl EmailClient {
-    pub fn new(
-        base_url: String,
-        sender: SubscriberEmail,
-        authorization_token: Secret<String>,
-        timeout: std::time::Duration
-    ) -> Self {
-        let http_client = Client::builder()
-            .timeout(timeout);
-        Self {
-            http_client: Client::new(),
-            base_url,
-            sender,
-            authorization_token,
-        }
-    }
-    pub async fn send_email(
-        &self,
-        recipient: SubscriberEmail,
-        subject: &str,
-        html_content: &str,
-        text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject: subject,
-            html_body: html_content,
-            text_body: text_content,
-        };
-        let builder = self.http_client
-            .post(&url)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .json(&request_body);
-        Ok(())
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
-    use fake::faker::internet::en::SafeEmail;
-    use fake::faker::lorem::en::{Paragraph, Sentence};
-    use fake::rand::rngs::mock;
-    use fake::{Fake, Faker};
-    use wiremock::matchers::{any, header_exists};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
-    use secrecy::Secret;
-    use claims::assert_err;
-    
-    #[tokio::test]
-    async fn send_email_fires_a_request_to_base_url(){
-        let mock_server = MockServer::start().await;
-        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-        let email_client = EmailClient::new(
-            mock_server.uri(), 
-            sender.clone(),
-            Secret::new(Faker.fake()),
-            std::time::Duration::from_millis(200)
-        );
-
-        Mock::given(header_exists("X-Postmark-Server-Token"))
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-        let subject: String = Sentence(1..2).fake();
-        let content: String = Paragraph(1..10).fake();
-
-        let _ = email_client
-            .send_email(subscriber_email, &subject, &content, &content)
-            .await;
-    }
-
-    #[tokio::test]
-    async fn send_email_fails_if_the_server_returns_500(){
-        let mock_server = MockServer::start().await;
-        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-        let email_client = EmailClient::new(
-            mock_server.uri(), 
-            sender.clone(),
-            Secret::new(Faker.fake()),
-             std::time::Duration::from_millis(200)
-        );
-
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
-        let subject: String = Sentence(1..2).fake();
-        let content: String = Paragraph(1..10).fake();
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-        let outcome = email_client
-            .send_email(subscriber_email, &subject, &content, &content)
-            .await;
-
-        assert_err!(outcome);
-    }
-}
\ No newline at end of file
@@ -0,0 +1,23 @@
+use crate::email_message::EmailMessage;
+
+use super::EmailError;
+use std::path::PathBuf;
+
+pub(super) struct FileSpoolTransport {
+    directory: PathBuf,
+}
+
+impl FileSpoolTransport {
+    pub(super) fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Write the email to `<message id>.eml`, ready to be opened by a mail
+    /// client.
+    pub(super) async fn send(&self, message: &EmailMessage, raw: &[u8]) -> Result<(), EmailError> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let path = self.directory.join(format!("{}.eml", message.message_id()));
+        tokio::fs::write(path, raw).await?;
+        Ok(())
+    }
+}
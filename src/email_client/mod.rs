@@ -0,0 +1,304 @@
+mod dkim;
+mod file_spool;
+mod postmark;
+mod smtp;
+
+use crate::configuration::SmtpSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_message::EmailMessage;
+use crate::routes::error_chain_fmt;
+
+pub use dkim::DkimSigner;
+use file_spool::FileSpoolTransport;
+use postmark::PostmarkTransport;
+use secrecy::Secret;
+use smtp::SmtpTransport;
+use std::path::PathBuf;
+
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    #[error("{0}")]
+    InvalidMessage(String),
+    #[error("Postmark rejected the email.")]
+    Postmark(#[from] reqwest::Error),
+    #[error("The SMTP server rejected the email.")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to sign the email with DKIM.")]
+    Dkim(#[from] rsa::signature::Error),
+    #[error("Failed to write the email to the spool directory.")]
+    Spool(#[from] std::io::Error),
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailError {
+    /// Whether sending the same email again later may succeed: the provider
+    /// or the relay was unavailable, rather than refusing the email.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::InvalidMessage(_) | Self::Dkim(_) => false,
+            Self::Postmark(e) => match e.status() {
+                Some(status) => {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                None => true,
+            },
+            Self::Smtp(e) => !e.is_permanent(),
+            Self::Spool(_) => true,
+        }
+    }
+}
+
+enum Transport {
+    Postmark(PostmarkTransport),
+    Smtp(SmtpTransport),
+    FileSpool(FileSpoolTransport),
+}
+
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Transport,
+    dkim_signer: Option<DkimSigner>,
+}
+
+impl EmailClient {
+    /// A client sending through Postmark's HTTP API.
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration
+    ) -> Self {
+        Self {
+            sender,
+            transport: Transport::Postmark(PostmarkTransport::new(
+                base_url,
+                authorization_token,
+                timeout,
+            )),
+            dkim_signer: None,
+        }
+    }
+
+    /// A client relaying emails to an SMTP server.
+    pub fn smtp(
+        sender: SubscriberEmail,
+        settings: &SmtpSettings,
+        timeout: std::time::Duration,
+    ) -> Result<Self, EmailError> {
+        Ok(Self {
+            sender,
+            transport: Transport::Smtp(SmtpTransport::new(settings, timeout)?),
+            dkim_signer: None,
+        })
+    }
+
+    /// A client writing every email to `directory` as an `.eml` file,
+    /// for local development.
+    pub fn file_spool(sender: SubscriberEmail, directory: PathBuf) -> Self {
+        Self {
+            sender,
+            transport: Transport::FileSpool(FileSpoolTransport::new(directory)),
+            dkim_signer: None,
+        }
+    }
+
+    /// Sign every email with DKIM before handing it to the transport.
+    pub fn with_dkim(self, signer: DkimSigner) -> Self {
+        Self {
+            dkim_signer: Some(signer),
+            ..self
+        }
+    }
+
+    pub fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    pub async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        match &self.transport {
+            Transport::Postmark(transport) => transport.send(message).await,
+            Transport::Smtp(transport) => transport.send(message, &self.serialize(message)?).await,
+            Transport::FileSpool(transport) => {
+                transport.send(message, &self.serialize(message)?).await
+            }
+        }
+    }
+
+    /// The message as it goes over the wire, signed when DKIM is enabled.
+    fn serialize(&self, message: &EmailMessage) -> Result<Vec<u8>, EmailError> {
+        let raw = message.to_rfc5322();
+        match &self.dkim_signer {
+            Some(signer) => Ok(signer.sign(&raw)?),
+            None => Ok(raw),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), EmailError> {
+        let message = EmailMessage::builder(self.sender.clone(), subject)
+            .to(recipient)
+            .html_body(html_content)
+            .text_body(text_content)
+            .build()
+            .map_err(EmailError::InvalidMessage)?;
+        self.send(&message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailClient;
+    use crate::email_message::{Attachment, EmailMessage};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{any, header_exists};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+    use secrecy::Secret;
+    use claims::{assert_err, assert_ok};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            base_url,
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200)
+        )
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url(){
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let subscriber_email = email();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+
+        let _ = email_client
+            .send_email(subscriber_email, &subject, &content, &content)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500(){
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let subscriber_email = email();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let outcome = email_client
+            .send_email(subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn postmark_receives_recipients_headers_and_attachments() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let message = EmailMessage::builder(email_client.sender().clone(), "Hello")
+            .to(email())
+            .to(email())
+            .cc(email())
+            .bcc(email())
+            .reply_to(email())
+            .html_body(r#"<img src="cid:logo">"#)
+            .header("X-Campaign", "spring")
+            .inline_image("logo", Attachment::new("logo.png", "image/png", vec![1, 2, 3]))
+            .attachment(Attachment::new("notes.txt", "text/plain", b"notes".to_vec()))
+            .build()
+            .unwrap();
+
+        assert_ok!(email_client.send(&message).await);
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["To"].as_str().unwrap().split(", ").count(), 2);
+        assert!(body["Cc"].is_string());
+        assert!(body["Bcc"].is_string());
+        assert!(body["ReplyTo"].is_string());
+        assert!(body.get("TextBody").is_none());
+        assert_eq!(body["Headers"][0]["Name"], "X-Campaign");
+        assert_eq!(body["Headers"][0]["Value"], "spring");
+        let attachments = body["Attachments"].as_array().unwrap();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0]["Name"], "logo.png");
+        assert_eq!(attachments[0]["Content"], "AQID");
+        assert_eq!(attachments[0]["ContentType"], "image/png");
+        assert_eq!(attachments[0]["ContentID"], "cid:logo");
+        assert!(attachments[1].get("ContentID").is_none());
+    }
+
+    #[tokio::test]
+    async fn the_file_spool_writes_one_eml_file_per_email() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = EmailClient::file_spool(email(), directory.clone());
+        let recipient = email();
+
+        assert_ok!(
+            email_client
+                .send_email(recipient.clone(), "Hello", "<p>Hi!</p>", "Hi!")
+                .await
+        );
+
+        let files: Vec<_> = std::fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        assert_eq!(path.extension().unwrap(), "eml");
+        let raw = std::fs::read_to_string(path).unwrap();
+        assert!(raw.contains(&format!("To: {}\r\n", recipient.as_ref())));
+        assert!(raw.contains("Subject: Hello\r\n"));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::email_message::mime::{format_mailbox, format_mailbox_list};
+use crate::email_message::{Attachment, EmailMessage, Mailbox};
+use crate::request_id::{current_request_id, REQUEST_ID_HEADER};
+
+use super::EmailError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: String,
+    to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bcc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+    subject: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html_body: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_body: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<PostmarkAttachment<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment<'a> {
+    name: &'a str,
+    /// Base64-encoded.
+    content: String,
+    content_type: &'a str,
+    /// `cid:<content id>` for inline images.
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+impl<'a> From<&'a EmailMessage> for SendEmailRequest<'a> {
+    fn from(message: &'a EmailMessage) -> Self {
+        let list = |mailboxes: &[Mailbox]| {
+            (!mailboxes.is_empty()).then(|| format_mailbox_list(mailboxes))
+        };
+        Self {
+            from: format_mailbox(&message.from),
+            to: format_mailbox_list(&message.to),
+            cc: list(&message.cc),
+            bcc: list(&message.bcc),
+            reply_to: message.reply_to.as_ref().map(format_mailbox),
+            subject: &message.subject,
+            html_body: message.html_body.as_deref(),
+            text_body: message.text_body.as_deref(),
+            headers: message
+                .headers
+                .iter()
+                .map(|(name, value)| PostmarkHeader { name, value })
+                .collect(),
+            attachments: message.attachments.iter().map(PostmarkAttachment::from).collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a Attachment> for PostmarkAttachment<'a> {
+    fn from(attachment: &'a Attachment) -> Self {
+        Self {
+            name: &attachment.filename,
+            content: STANDARD.encode(&attachment.content),
+            content_type: &attachment.content_type,
+            content_id: attachment
+                .content_id
+                .as_ref()
+                .map(|content_id| format!("cid:{}", content_id)),
+        }
+    }
+}
+
+pub(super) struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    pub(super) fn new(
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+        }
+    }
+
+    pub(super) async fn send(&self, message: &EmailMessage) -> Result<(), EmailError> {
+        let url = format!("{}/email", self.base_url);
+        let mut request = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&SendEmailRequest::from(message));
+        if let Some(request_id) = current_request_id() {
+            request = request.header(REQUEST_ID_HEADER, request_id.as_str());
+        }
+        request
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use crate::configuration::{SmtpSecurity, SmtpSettings};
+use crate::email_message::EmailMessage;
+
+use super::EmailError;
+use lettre::address::{Address, Envelope};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::ExposeSecret;
+
+pub(super) struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub(super) fn new(
+        settings: &SmtpSettings,
+        timeout: std::time::Duration,
+    ) -> Result<Self, EmailError> {
+        let mut builder = match settings.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?
+            }
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            }
+        }
+        .port(settings.port)
+        .timeout(Some(timeout));
+        if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().clone(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+
+    pub(super) async fn send(&self, message: &EmailMessage, raw: &[u8]) -> Result<(), EmailError> {
+        let address = |email: &str| {
+            email
+                .parse::<Address>()
+                .map_err(|e| EmailError::InvalidMessage(format!("{}: {}", email, e)))
+        };
+        let recipients = message
+            .recipients()
+            .map(|mailbox| address(mailbox.email.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let envelope = Envelope::new(Some(address(message.from.email.as_ref())?), recipients)
+            .map_err(|e| EmailError::InvalidMessage(e.to_string()))?;
+        self.transport
+            .send_raw(&envelope, raw)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,442 @@
+//! RFC 5322 / MIME serialization of an [`EmailMessage`].
+//!
+//! Text bodies are quoted-printable, attachments base64. Header and body
+//! lines stay within the limits of RFC 5322 so that nothing has to be
+//! re-encoded (and DKIM signatures broken) along the way.
+
+use super::{Attachment, EmailMessage, Mailbox};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Recommended maximum line length, CRLF excluded.
+const MAX_LINE_LENGTH: usize = 78;
+/// Maximum length of a quoted-printable or base64 line.
+const MAX_ENCODED_LINE_LENGTH: usize = 76;
+/// Bytes of UTF-8 that fit in one `=?utf-8?B?...?=` encoded word of at most
+/// 75 characters.
+const ENCODED_WORD_BYTES: usize = 45;
+
+enum Part {
+    Single {
+        headers: Vec<(&'static str, String)>,
+        body: String,
+    },
+    Multipart {
+        subtype: &'static str,
+        parts: Vec<Part>,
+    },
+}
+
+impl Part {
+    fn write(&self, out: &mut String) {
+        match self {
+            Part::Single { headers, body } => {
+                for (name, value) in headers {
+                    write_header(out, name, value);
+                }
+                out.push_str("\r\n");
+                out.push_str(body);
+            }
+            Part::Multipart { subtype, parts } => {
+                // `=_` never shows up in quoted-printable or base64 output.
+                let boundary = format!("=_{}", Uuid::new_v4().simple());
+                write_header(
+                    out,
+                    "Content-Type",
+                    &format!("multipart/{}; boundary=\"{}\"", subtype, boundary),
+                );
+                out.push_str("\r\n");
+                for part in parts {
+                    out.push_str(&format!("--{}\r\n", boundary));
+                    part.write(out);
+                    out.push_str("\r\n");
+                }
+                out.push_str(&format!("--{}--\r\n", boundary));
+            }
+        }
+    }
+}
+
+pub(super) fn format_message(message: &EmailMessage, date: DateTime<Utc>) -> Vec<u8> {
+    let mut out = String::new();
+    write_header(&mut out, "Date", &date.to_rfc2822());
+    write_header(&mut out, "Message-ID", &format!("<{}>", message.message_id));
+    write_header(&mut out, "From", &format_mailbox(&message.from));
+    if !message.to.is_empty() {
+        write_header(&mut out, "To", &format_mailbox_list(&message.to));
+    }
+    if !message.cc.is_empty() {
+        write_header(&mut out, "Cc", &format_mailbox_list(&message.cc));
+    }
+    if let Some(reply_to) = &message.reply_to {
+        write_header(&mut out, "Reply-To", &format_mailbox(reply_to));
+    }
+    write_header(&mut out, "Subject", &encode_unstructured(&message.subject));
+    for (name, value) in &message.headers {
+        write_header(&mut out, name, &encode_unstructured(value));
+    }
+    write_header(&mut out, "MIME-Version", "1.0");
+    body(message).write(&mut out);
+    out.into_bytes()
+}
+
+/// multipart/mixed (attachments)
+/// └── multipart/related (inline images)
+///     └── multipart/alternative (text and HTML)
+///
+/// Each level only exists when it has something to hold.
+fn body(message: &EmailMessage) -> Part {
+    let mut alternatives = Vec::new();
+    if let Some(text) = &message.text_body {
+        alternatives.push(text_part("plain", text));
+    }
+    if let Some(html) = &message.html_body {
+        alternatives.push(text_part("html", html));
+    }
+    let mut body = if alternatives.len() == 1 {
+        alternatives.remove(0)
+    } else {
+        Part::Multipart {
+            subtype: "alternative",
+            parts: alternatives,
+        }
+    };
+
+    let (inline, attached): (Vec<_>, Vec<_>) = message
+        .attachments
+        .iter()
+        .partition(|attachment| attachment.content_id.is_some());
+    if !inline.is_empty() {
+        let mut parts = vec![body];
+        parts.extend(inline.into_iter().map(attachment_part));
+        body = Part::Multipart {
+            subtype: "related",
+            parts,
+        };
+    }
+    if !attached.is_empty() {
+        let mut parts = vec![body];
+        parts.extend(attached.into_iter().map(attachment_part));
+        body = Part::Multipart {
+            subtype: "mixed",
+            parts,
+        };
+    }
+    body
+}
+
+fn text_part(subtype: &str, content: &str) -> Part {
+    Part::Single {
+        headers: vec![
+            ("Content-Type", format!("text/{}; charset=utf-8", subtype)),
+            ("Content-Transfer-Encoding", "quoted-printable".into()),
+        ],
+        body: quoted_printable(content),
+    }
+}
+
+fn attachment_part(attachment: &Attachment) -> Part {
+    let disposition = if attachment.content_id.is_some() {
+        "inline"
+    } else {
+        "attachment"
+    };
+    let mut headers = vec![
+        (
+            "Content-Type",
+            format!(
+                "{}; {}",
+                attachment.content_type,
+                format_parameter("name", &attachment.filename)
+            ),
+        ),
+        ("Content-Transfer-Encoding", "base64".into()),
+        (
+            "Content-Disposition",
+            format!(
+                "{}; {}",
+                disposition,
+                format_parameter("filename", &attachment.filename)
+            ),
+        ),
+    ];
+    if let Some(content_id) = &attachment.content_id {
+        headers.push(("Content-ID", format!("<{}>", content_id)));
+    }
+    Part::Single {
+        headers,
+        body: base64_lines(&attachment.content),
+    }
+}
+
+/// Write `name: value`, folding at spaces to keep lines short.
+fn write_header(out: &mut String, name: &str, value: &str) {
+    out.push_str(name);
+    out.push(':');
+    let mut line_length = name.len() + 1;
+    let mut is_first_word = true;
+    for word in value.split(' ') {
+        if !is_first_word && line_length + 1 + word.len() > MAX_LINE_LENGTH {
+            out.push_str("\r\n");
+            line_length = 0;
+        }
+        out.push(' ');
+        out.push_str(word);
+        line_length += 1 + word.len();
+        is_first_word = false;
+    }
+    out.push_str("\r\n");
+}
+
+pub(crate) fn format_mailbox(mailbox: &Mailbox) -> String {
+    match &mailbox.name {
+        None => mailbox.email.as_ref().to_owned(),
+        Some(name) if name.is_ascii() => {
+            format!("{} <{}>", quoted_string(name), mailbox.email.as_ref())
+        }
+        Some(name) => format!("{} <{}>", encoded_words(name), mailbox.email.as_ref()),
+    }
+}
+
+pub(crate) fn format_mailbox_list(mailboxes: &[Mailbox]) -> String {
+    mailboxes
+        .iter()
+        .map(format_mailbox)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn quoted_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Free text headers (Subject and friends) only need encoding when they
+/// contain something other than printable ASCII.
+fn encode_unstructured(value: &str) -> String {
+    let is_plain = value.bytes().all(|b| b == b' ' || b.is_ascii_graphic());
+    if is_plain && !value.contains("=?") {
+        value.to_owned()
+    } else {
+        encoded_words(value)
+    }
+}
+
+/// RFC 2047 encoded words, split on character boundaries. The whitespace
+/// between two encoded words is dropped when decoding.
+fn encoded_words(value: &str) -> String {
+    let mut words = Vec::new();
+    let mut chunk_start = 0;
+    let mut chunk_end = 0;
+    for (index, c) in value.char_indices() {
+        if index + c.len_utf8() - chunk_start > ENCODED_WORD_BYTES {
+            words.push(&value[chunk_start..chunk_end]);
+            chunk_start = chunk_end;
+        }
+        chunk_end = index + c.len_utf8();
+    }
+    words.push(&value[chunk_start..]);
+    words
+        .into_iter()
+        .map(|word| format!("=?utf-8?B?{}?=", STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A `name="value"` parameter, using RFC 2231 for non-ASCII values.
+fn format_parameter(name: &str, value: &str) -> String {
+    if value.is_ascii() {
+        return format!("{}={}", name, quoted_string(value));
+    }
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("{}*=utf-8''{}", name, encoded)
+}
+
+fn quoted_printable(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    for (line_index, line) in content.split('\n').enumerate() {
+        if line_index > 0 {
+            out.push_str("\r\n");
+        }
+        let line = line.strip_suffix('\r').unwrap_or(line).as_bytes();
+        let mut line_length = 0;
+        for (index, &byte) in line.iter().enumerate() {
+            let is_last = index + 1 == line.len();
+            let encoded = match byte {
+                // Trailing whitespace would be stripped in transit.
+                b' ' | b'\t' if !is_last => (byte as char).to_string(),
+                b'!'..=b'~' if byte != b'=' => (byte as char).to_string(),
+                _ => format!("={:02X}", byte),
+            };
+            // Leave room for the `=` of a soft line break.
+            if line_length + encoded.len() > MAX_ENCODED_LINE_LENGTH - 1 {
+                out.push_str("=\r\n");
+                line_length = 0;
+            }
+            out.push_str(&encoded);
+            line_length += encoded.len();
+        }
+    }
+    out
+}
+
+fn base64_lines(content: &[u8]) -> String {
+    STANDARD
+        .encode(content)
+        .as_bytes()
+        .chunks(MAX_ENCODED_LINE_LENGTH)
+        .map(|line| std::str::from_utf8(line).expect("base64 is ASCII"))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_message::{Attachment, EmailMessage, Mailbox};
+    use mail_parser::{MessageParser, MimeHeaders};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.into()).unwrap()
+    }
+
+    #[test]
+    fn a_plain_text_message_is_a_single_part() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hello")
+            .to(email("ursula@example.com"))
+            .text_body("Hi there!")
+            .build()
+            .unwrap();
+
+        let raw = String::from_utf8(message.to_rfc5322()).unwrap();
+
+        assert!(raw.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(!raw.contains("multipart/"));
+        let parsed = MessageParser::default().parse(&raw).unwrap();
+        assert_eq!(parsed.subject(), Some("Hello"));
+        assert_eq!(parsed.body_text(0).unwrap(), "Hi there!");
+        assert_eq!(parsed.message_id(), Some(message.message_id()));
+    }
+
+    #[test]
+    fn recipients_and_reply_to_are_written_but_bcc_is_not() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hello")
+            .to(email("ursula@example.com"))
+            .to(Mailbox::new("Le Guin, Ursula", email("le_guin@example.com")))
+            .cc(email("editor@example.com"))
+            .bcc(email("archive@example.com"))
+            .reply_to(email("replies@example.com"))
+            .text_body("Hi there!")
+            .build()
+            .unwrap();
+
+        let raw = message.to_rfc5322();
+
+        assert!(!String::from_utf8_lossy(&raw).contains("archive@example.com"));
+        let parsed = MessageParser::default().parse(&raw).unwrap();
+        let to: Vec<_> = parsed.to().unwrap().iter().collect();
+        assert_eq!(to.len(), 2);
+        assert_eq!(to[1].name(), Some("Le Guin, Ursula"));
+        assert_eq!(to[1].address(), Some("le_guin@example.com"));
+        let cc = parsed.cc().unwrap().first().unwrap();
+        assert_eq!(cc.address(), Some("editor@example.com"));
+        let reply_to = parsed.reply_to().unwrap().first().unwrap();
+        assert_eq!(reply_to.address(), Some("replies@example.com"));
+        assert_eq!(message.recipients().count(), 4);
+    }
+
+    #[test]
+    fn attachments_and_inline_images_survive_a_round_trip() {
+        let image: Vec<u8> = (0..=255).collect();
+        let message = EmailMessage::builder(email("sender@example.com"), "Hello")
+            .to(email("ursula@example.com"))
+            .text_body("Hi there!")
+            .html_body(r#"<p>Hi there!</p><img src="cid:logo@example.com">"#)
+            .inline_image(
+                "logo@example.com",
+                Attachment::new("logo.png", "image/png", image.clone()),
+            )
+            .attachment(Attachment::new(
+                "notes.txt",
+                "text/plain",
+                b"Some notes".to_vec(),
+            ))
+            .build()
+            .unwrap();
+
+        let raw = message.to_rfc5322();
+
+        let parsed = MessageParser::default().parse(&raw).unwrap();
+        assert_eq!(parsed.body_text(0).unwrap(), "Hi there!");
+        assert!(parsed.body_html(0).unwrap().contains("cid:logo@example.com"));
+        let attachments: Vec<_> = parsed.attachments().collect();
+        assert_eq!(attachments.len(), 2);
+        let logo = attachments
+            .iter()
+            .find(|a| a.attachment_name() == Some("logo.png"))
+            .unwrap();
+        assert_eq!(logo.content_id(), Some("logo@example.com"));
+        assert_eq!(logo.contents(), image.as_slice());
+        let notes = attachments
+            .iter()
+            .find(|a| a.attachment_name() == Some("notes.txt"))
+            .unwrap();
+        assert_eq!(notes.contents(), b"Some notes");
+    }
+
+    #[test]
+    fn non_ascii_text_is_encoded() {
+        let subject = "Ünïcödé newsletter — issue №1, with a subject long enough to need several encoded words";
+        let message = EmailMessage::builder(
+            Mailbox::new("Zoë", email("sender@example.com")),
+            subject,
+        )
+        .to(email("ursula@example.com"))
+        .text_body("Grüße\nand a very long line that goes on and on and on and on and on and on and on and on")
+        .attachment(Attachment::new("résumé.pdf", "application/pdf", vec![0]))
+        .build()
+        .unwrap();
+
+        let raw = message.to_rfc5322();
+
+        assert!(raw.is_ascii());
+        let parsed = MessageParser::default().parse(&raw).unwrap();
+        assert_eq!(parsed.subject(), Some(subject));
+        assert_eq!(parsed.from().unwrap().first().unwrap().name(), Some("Zoë"));
+        assert_eq!(
+            parsed.body_text(0).unwrap(),
+            "Grüße\r\nand a very long line that goes on and on and on and on and on and on and on and on"
+        );
+        assert_eq!(
+            parsed.attachments().next().unwrap().attachment_name(),
+            Some("résumé.pdf")
+        );
+    }
+
+    #[test]
+    fn lines_never_exceed_the_rfc_5322_limit() {
+        let message = EmailMessage::builder(email("sender@example.com"), "word ".repeat(40))
+            .to(email("ursula@example.com"))
+            .header("X-Campaign", "spring ".repeat(30))
+            .text_body("=".repeat(500))
+            .html_body(format!("<p>{}</p>", "é".repeat(300)))
+            .attachment(Attachment::new("data.bin", "application/octet-stream", vec![7; 1000]))
+            .build()
+            .unwrap();
+
+        let raw = String::from_utf8(message.to_rfc5322()).unwrap();
+
+        for line in raw.split("\r\n") {
+            assert!(line.len() <= 78, "Line too long: {}", line);
+        }
+        assert!(!raw.replace("\r\n", "").contains('\n'));
+    }
+}
@@ -0,0 +1,299 @@
+pub(crate) mod mime;
+
+use crate::domain::SubscriberEmail;
+use uuid::Uuid;
+
+/// Header fields the serializer writes itself; setting them as custom
+/// headers would produce a message with duplicate or conflicting fields.
+const RESERVED_HEADERS: &[&str] = &[
+    "bcc",
+    "cc",
+    "content-disposition",
+    "content-id",
+    "content-transfer-encoding",
+    "content-type",
+    "date",
+    "from",
+    "message-id",
+    "mime-version",
+    "reply-to",
+    "subject",
+    "to",
+];
+
+/// An address, optionally with the display name shown by mail clients.
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    pub name: Option<String>,
+    pub email: SubscriberEmail,
+}
+
+impl Mailbox {
+    pub fn new(name: impl Into<String>, email: SubscriberEmail) -> Self {
+        Self {
+            name: Some(name.into()),
+            email,
+        }
+    }
+}
+
+impl From<SubscriberEmail> for Mailbox {
+    fn from(email: SubscriberEmail) -> Self {
+        Self { name: None, email }
+    }
+}
+
+/// A file carried by an email. Inline attachments have a `content_id`, which
+/// the HTML body refers to as `cid:<content_id>`.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    pub fn new(
+        filename: impl Into<String>,
+        content_type: impl Into<String>,
+        content: Vec<u8>,
+    ) -> Self {
+        Self {
+            filename: filename.into(),
+            content_type: content_type.into(),
+            content,
+            content_id: None,
+        }
+    }
+}
+
+/// An email, independent of the transport that will deliver it.
+///
+/// Built through [`EmailMessage::builder`], which checks that the message
+/// can be serialized safely.
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub(crate) message_id: String,
+    pub(crate) from: Mailbox,
+    pub(crate) to: Vec<Mailbox>,
+    pub(crate) cc: Vec<Mailbox>,
+    pub(crate) bcc: Vec<Mailbox>,
+    pub(crate) reply_to: Option<Mailbox>,
+    pub(crate) subject: String,
+    pub(crate) html_body: Option<String>,
+    pub(crate) text_body: Option<String>,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) attachments: Vec<Attachment>,
+}
+
+impl EmailMessage {
+    pub fn builder(from: impl Into<Mailbox>, subject: impl Into<String>) -> EmailMessageBuilder {
+        let from = from.into();
+        let domain = from
+            .email
+            .as_ref()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_owned())
+            .unwrap_or_else(|| "localhost".into());
+        EmailMessageBuilder {
+            message: EmailMessage {
+                message_id: format!("{}@{}", Uuid::new_v4().simple(), domain),
+                from,
+                to: vec![],
+                cc: vec![],
+                bcc: vec![],
+                reply_to: None,
+                subject: subject.into(),
+                html_body: None,
+                text_body: None,
+                headers: vec![],
+                attachments: vec![],
+            },
+        }
+    }
+
+    /// The `Message-ID` of the email, without the angle brackets.
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    /// Every address the email must be delivered to, Bcc included.
+    pub fn recipients(&self) -> impl Iterator<Item = &Mailbox> {
+        self.to.iter().chain(&self.cc).chain(&self.bcc)
+    }
+
+    /// Serialize the email as an RFC 5322 message with MIME bodies, the
+    /// format expected by SMTP servers and `.eml` files.
+    ///
+    /// Bcc recipients are left out: they only exist in the envelope.
+    pub fn to_rfc5322(&self) -> Vec<u8> {
+        mime::format_message(self, chrono::Utc::now())
+    }
+}
+
+pub struct EmailMessageBuilder {
+    message: EmailMessage,
+}
+
+impl EmailMessageBuilder {
+    pub fn to(mut self, mailbox: impl Into<Mailbox>) -> Self {
+        self.message.to.push(mailbox.into());
+        self
+    }
+
+    pub fn cc(mut self, mailbox: impl Into<Mailbox>) -> Self {
+        self.message.cc.push(mailbox.into());
+        self
+    }
+
+    pub fn bcc(mut self, mailbox: impl Into<Mailbox>) -> Self {
+        self.message.bcc.push(mailbox.into());
+        self
+    }
+
+    pub fn reply_to(mut self, mailbox: impl Into<Mailbox>) -> Self {
+        self.message.reply_to = Some(mailbox.into());
+        self
+    }
+
+    pub fn html_body(mut self, body: impl Into<String>) -> Self {
+        self.message.html_body = Some(body.into());
+        self
+    }
+
+    pub fn text_body(mut self, body: impl Into<String>) -> Self {
+        self.message.text_body = Some(body.into());
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.message.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.message.attachments.push(attachment);
+        self
+    }
+
+    /// Attach an image the HTML body can embed with `<img src="cid:...">`.
+    pub fn inline_image(mut self, content_id: impl Into<String>, mut image: Attachment) -> Self {
+        image.content_id = Some(content_id.into());
+        self.message.attachments.push(image);
+        self
+    }
+
+    pub fn build(self) -> Result<EmailMessage, String> {
+        let message = self.message;
+        if message.recipients().next().is_none() {
+            return Err("An email needs at least one recipient.".into());
+        }
+        if message.html_body.is_none() && message.text_body.is_none() {
+            return Err("An email needs an HTML or a plain-text body.".into());
+        }
+        if has_line_break(&message.subject) {
+            return Err("The subject cannot contain line breaks.".into());
+        }
+        let names = message
+            .recipients()
+            .chain(std::iter::once(&message.from))
+            .chain(&message.reply_to)
+            .filter_map(|mailbox| mailbox.name.as_deref());
+        for name in names {
+            if has_line_break(name) {
+                return Err(format!("`{}` is not a valid display name.", name.trim()));
+            }
+        }
+        for (name, value) in &message.headers {
+            let is_field_name = !name.is_empty()
+                && name.bytes().all(|b| b.is_ascii_graphic() && b != b':');
+            if !is_field_name || RESERVED_HEADERS.contains(&name.to_lowercase().as_str()) {
+                return Err(format!("`{}` cannot be set as a custom header.", name));
+            }
+            if has_line_break(value) {
+                return Err(format!("The value of the `{}` header contains a line break.", name));
+            }
+        }
+        for attachment in &message.attachments {
+            if has_line_break(&attachment.filename) || has_line_break(&attachment.content_type) {
+                return Err(format!("`{}` is not a valid attachment.", attachment.filename));
+            }
+            if let Some(content_id) = &attachment.content_id {
+                let is_valid = !content_id.is_empty()
+                    && content_id
+                        .bytes()
+                        .all(|b| b.is_ascii_graphic() && b != b'<' && b != b'>');
+                if !is_valid {
+                    return Err(format!("`{}` is not a valid content id.", content_id));
+                }
+                if message.html_body.is_none() {
+                    return Err("Inline images need an HTML body to refer to them.".into());
+                }
+            }
+        }
+        Ok(message)
+    }
+}
+
+fn has_line_break(s: &str) -> bool {
+    s.contains(['\r', '\n'])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_message::{Attachment, EmailMessage};
+    use claims::{assert_err, assert_ok};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.into()).unwrap()
+    }
+
+    #[test]
+    fn a_message_needs_a_recipient() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hi")
+            .text_body("Hello")
+            .build();
+        assert_err!(message);
+    }
+
+    #[test]
+    fn a_message_with_only_bcc_recipients_is_accepted() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hi")
+            .bcc(email("ursula@example.com"))
+            .text_body("Hello")
+            .build();
+        assert_ok!(message);
+    }
+
+    #[test]
+    fn header_values_cannot_inject_further_headers() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hi")
+            .to(email("ursula@example.com"))
+            .text_body("Hello")
+            .header("X-Campaign", "spring\r\nBcc: victim@example.com")
+            .build();
+        assert_err!(message);
+    }
+
+    #[test]
+    fn headers_written_by_the_serializer_cannot_be_overridden() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hi")
+            .to(email("ursula@example.com"))
+            .text_body("Hello")
+            .header("content-type", "text/html")
+            .build();
+        assert_err!(message);
+    }
+
+    #[test]
+    fn inline_images_need_an_html_body() {
+        let message = EmailMessage::builder(email("sender@example.com"), "Hi")
+            .to(email("ursula@example.com"))
+            .text_body("Hello")
+            .inline_image("logo", Attachment::new("logo.png", "image/png", vec![1, 2, 3]))
+            .build();
+        assert_err!(message);
+    }
+}
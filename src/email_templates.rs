@@ -0,0 +1,397 @@
+use anyhow::Context;
+use std::path::Path;
+use tera::Tera;
+
+use crate::attributes::Attributes;
+use crate::merge_tags::{fill_merge_tags, protect_merge_tags, Format};
+
+/// Line width used when deriving a plain-text body from the HTML one.
+const TEXT_WIDTH: usize = 78;
+
+pub struct RenderedEmail {
+    pub html_body: String,
+    pub text_body: String,
+}
+
+/// Email templates loaded from disk.
+///
+/// Each email is a `emails/<name>.html` template, usually extending a layout
+/// in `layouts/` and including partials from `partials/`. An optional
+/// `emails/<name>.txt` template provides the plain-text alternative; when it
+/// is missing, the text body is derived from the rendered HTML.
+///
+/// Emails to subscribers may use merge tags, such as
+/// `{{ attributes.company | default: "there" }}`, see `crate::merge_tags`.
+///
+/// The few public web pages we serve live next to them, as
+/// `pages/<name>.html`, and so do the Markdown templates of the issues we
+/// generate from feeds, as `feeds/<name>.md`.
+#[derive(Debug)]
+pub struct EmailTemplates {
+    tera: Tera,
+}
+
+impl EmailTemplates {
+    /// Load every template under `directory` and render each email once with
+    /// placeholders for the values its caller passes, so that a broken
+    /// template fails at startup rather than when somebody signs up.
+    pub fn load(directory: &Path) -> Result<Self, anyhow::Error> {
+        let mut sources = Vec::new();
+        read_templates(directory, directory, &mut sources)?;
+        let mut tera = Tera::default();
+        tera.add_raw_templates(sources)
+            .with_context(|| format!("Failed to parse the templates in {}.", directory.display()))?;
+        tera.set_escape_fn(escape_html);
+        let templates = Self { tera };
+        templates.check()?;
+        Ok(templates)
+    }
+
+    /// Values are HTML-escaped in the HTML body, not in the text one.
+    ///
+    /// Merge tags are left for the caller to fill in, as issues are rendered
+    /// once for all their recipients.
+    pub fn render(
+        &self,
+        email: &str,
+        context: &impl serde::Serialize,
+    ) -> Result<RenderedEmail, tera::Error> {
+        self.render_email(email, context, None)
+    }
+
+    /// Like `render`, filling in merge tags with the recipient's attributes.
+    pub fn render_for(
+        &self,
+        email: &str,
+        context: &impl serde::Serialize,
+        attributes: &Attributes,
+    ) -> Result<RenderedEmail, tera::Error> {
+        self.render_email(email, context, Some(attributes))
+    }
+
+    fn render_email(
+        &self,
+        email: &str,
+        context: &impl serde::Serialize,
+        attributes: Option<&Attributes>,
+    ) -> Result<RenderedEmail, tera::Error> {
+        let fill = |content: String, format| match attributes {
+            Some(attributes) => fill_merge_tags(&content, attributes, format),
+            None => content,
+        };
+        let context = tera::Context::from_serialize(context)?;
+        let html_body = self.tera.render(&format!("emails/{}.html", email), &context)?;
+        let html_body = fill(html_body, Format::Html);
+        let text_template = format!("emails/{}.txt", email);
+        let text_body = if self.tera.get_template_names().any(|name| name == text_template) {
+            fill(self.tera.render(&text_template, &context)?, Format::Text)
+        } else {
+            // Only wrap the prose: footnote links must stay on one line,
+            // otherwise they stop working once copied out of the email.
+            html2text::config::plain()
+                .max_wrap_width(TEXT_WIDTH)
+                .string_from_read(html_body.as_bytes(), usize::MAX / 2)
+                .map_err(|e| tera::Error::msg(e.to_string()))?
+        };
+        Ok(RenderedEmail {
+            html_body,
+            text_body,
+        })
+    }
+
+    /// Render one of the `pages/` templates, HTML-escaping values.
+    pub fn render_page(
+        &self,
+        page: &str,
+        context: &impl serde::Serialize,
+    ) -> Result<String, tera::Error> {
+        let context = tera::Context::from_serialize(context)?;
+        self.tera.render(&format!("pages/{}.html", page), &context)
+    }
+
+    /// Render one of the `feeds/` templates to the Markdown source of an
+    /// issue. Nothing is escaped: the Markdown is sanitised once rendered.
+    pub fn render_feed_issue(
+        &self,
+        template: &str,
+        context: &impl serde::Serialize,
+    ) -> Result<String, tera::Error> {
+        let context = tera::Context::from_serialize(context)?;
+        self.tera.render(&format!("feeds/{}.md", template), &context)
+    }
+
+    fn check(&self) -> Result<(), anyhow::Error> {
+        for email in self.template_names("emails/", ".html") {
+            self.render(&email, &sample_context("emails", &email)?)
+                .with_context(|| format!("Failed to render the `{}` email template.", email))?;
+        }
+        for page in self.template_names("pages/", ".html") {
+            self.render_page(&page, &sample_context("pages", &page)?)
+                .with_context(|| format!("Failed to render the `{}` page template.", page))?;
+        }
+        for template in self.template_names("feeds/", ".md") {
+            self.render_feed_issue(&template, &sample_context("feeds", &template)?)
+                .with_context(|| format!("Failed to render the `{}` feed template.", template))?;
+        }
+        Ok(())
+    }
+
+    fn template_names(&self, prefix: &str, suffix: &str) -> Vec<String> {
+        self.tera
+            .get_template_names()
+            .filter_map(|name| name.strip_prefix(prefix)?.strip_suffix(suffix))
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+/// Read every file under `directory`, naming each after its path relative to
+/// `root`, e.g. `emails/confirmation.html`.
+fn read_templates(
+    root: &Path,
+    directory: &Path,
+    sources: &mut Vec<(String, String)>,
+) -> Result<(), anyhow::Error> {
+    let entries = std::fs::read_dir(directory)
+        .with_context(|| format!("Failed to read the templates in {}.", directory.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_templates(root, &path, sources)?;
+            continue;
+        }
+        let name = path
+            .strip_prefix(root)?
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()
+            .context("The name of a template is not valid UTF-8.")?
+            .join("/");
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the `{}` template.", name))?;
+        let source = protect_merge_tags(&source)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid merge tag in the `{}` template.", name))?;
+        sources.push((name, source));
+    }
+    Ok(())
+}
+
+/// Tera's default escaping also encodes `/`, which mangles every link we
+/// put in an `href`. Quoted attribute values and text only need these five.
+pub(crate) fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The variables the caller of `directory/template` passes to it, with
+/// placeholder values. Each template gets only its own, so that one that
+/// refers to a variable its caller never sets fails the check.
+fn sample_context(directory: &str, template: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let context = match (directory, template) {
+        ("emails", "confirmation") => serde_json::json!({
+            "subscriber_name": "Ursula",
+            "confirmation_link": "https://example.com/subscriptions/confirm?subscription_token=token",
+        }),
+        ("emails", "data_access") => serde_json::json!({
+            "access_link": "https://example.com/me/data?token=token",
+            "erase_link": "https://example.com/me/data/erase?token=token",
+            "expires_in_hours": 24,
+        }),
+        ("emails", "admin_invitation") => serde_json::json!({
+            "invitation_link": "https://example.com/invitations/accept?token=token",
+            "role": "editor",
+            "expires_in_hours": 72,
+        }),
+        ("emails", "password_reset") => serde_json::json!({
+            "reset_link": "https://example.com/password_reset/confirm?token=token",
+            "expires_in_minutes": 60,
+        }),
+        ("emails", "newsletter_issue") => serde_json::json!({
+            "title": "Issue #1",
+            "content": "<p>Hello!</p>",
+            "unsubscribe_link": "https://example.com/subscriptions/unsubscribe?subscription_token=token",
+        }),
+        ("pages", "archive") => serde_json::json!({
+            "archive_title": "Zero To Production",
+            "archive_description": "Every issue of the newsletter so far.",
+            "issues": [{
+                "title": "Issue #1",
+                "link": "https://example.com/archive/issue-1",
+                "published_at": "2025-09-23T09:00:00Z",
+                "published_on": "23 September 2025",
+            }],
+            "newer_page_link": "https://example.com/archive?page=1",
+            "older_page_link": "https://example.com/archive?page=3",
+            "rss_feed_link": "https://example.com/archive/feed.xml",
+            "atom_feed_link": "https://example.com/archive/feed.xml?format=atom",
+        }),
+        ("feeds", "item") => serde_json::json!({
+            "feed_name": "blog",
+            "item": {
+                "title": "A new post",
+                "link": "https://example.com/blog/a-new-post",
+                "description": "<p>What the post is about.</p>",
+            },
+        }),
+        ("feeds", "digest") => serde_json::json!({
+            "feed_name": "blog",
+            "items": [{
+                "title": "A new post",
+                "link": "https://example.com/blog/a-new-post",
+                "description": "<p>What the post is about.</p>",
+            }],
+        }),
+        _ => anyhow::bail!(
+            "Nothing is ever rendered with the `{}/{}` template.",
+            directory,
+            template
+        ),
+    };
+    Ok(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attributes::Attributes;
+    use crate::email_templates::EmailTemplates;
+    use claims::{assert_err, assert_ok};
+    use std::path::PathBuf;
+
+    fn template_directory(files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        for (name, content) in files {
+            let path = directory.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn the_bundled_templates_are_valid() {
+        assert_ok!(EmailTemplates::load(std::path::Path::new("templates")));
+    }
+
+    #[test]
+    fn values_are_escaped_in_the_html_body_only() {
+        let directory = template_directory(&[
+            ("emails/confirmation.html", "<p>Hi {{ subscriber_name }}</p>"),
+            ("emails/confirmation.txt", "Hi {{ subscriber_name }}"),
+        ]);
+        let templates = EmailTemplates::load(&directory).unwrap();
+
+        let email = templates
+            .render("confirmation", &serde_json::json!({ "subscriber_name": "<b>Ursula</b>" }))
+            .unwrap();
+
+        assert_eq!(email.html_body, "<p>Hi &lt;b&gt;Ursula&lt;/b&gt;</p>");
+        assert_eq!(email.text_body, "Hi <b>Ursula</b>");
+    }
+
+    #[test]
+    fn a_text_body_is_derived_when_there_is_no_text_template() {
+        let directory = template_directory(&[(
+            "emails/confirmation.html",
+            r#"<p>Hi! <a href="{{ confirmation_link }}">Confirm</a></p>"#,
+        )]);
+        let templates = EmailTemplates::load(&directory).unwrap();
+
+        let email = templates
+            .render(
+                "confirmation",
+                &serde_json::json!({
+                    "confirmation_link": "https://example.com/subscriptions/confirm?subscription_token=a-rather-long-token-value"
+                }),
+            )
+            .unwrap();
+
+        assert!(!email.text_body.contains("<a"));
+        assert!(email.text_body.contains("Confirm"));
+        assert!(email.text_body.contains(
+            "https://example.com/subscriptions/confirm?subscription_token=a-rather-long-token-value"
+        ));
+    }
+
+    #[test]
+    fn a_template_with_a_syntax_error_fails_to_load() {
+        let directory = template_directory(&[("emails/confirmation.html", "{% if %}")]);
+        assert_err!(EmailTemplates::load(&directory));
+    }
+
+    #[test]
+    fn a_template_referring_to_an_unknown_variable_fails_to_load() {
+        let directory =
+            template_directory(&[("emails/confirmation.html", "{{ subscriber_nmae }}")]);
+        assert_err!(EmailTemplates::load(&directory));
+    }
+
+    #[test]
+    fn a_template_including_a_missing_partial_fails_to_load() {
+        let directory = template_directory(&[(
+            "emails/confirmation.html",
+            r#"{% include "partials/missing.html" %}"#,
+        )]);
+        assert_err!(EmailTemplates::load(&directory));
+    }
+
+    #[test]
+    fn merge_tags_are_filled_in_for_the_recipient() {
+        let directory = template_directory(&[
+            (
+                "emails/confirmation.html",
+                r#"<p>{{ subscriber_name }} of {{ attributes.company | default: "nowhere" }}</p>"#,
+            ),
+            (
+                "emails/confirmation.txt",
+                r#"{{ subscriber_name }} of {{ attributes.company | default: "nowhere" }}"#,
+            ),
+        ]);
+        let templates = EmailTemplates::load(&directory).unwrap();
+        let context = serde_json::json!({ "subscriber_name": "Ursula" });
+        let attributes = serde_json::json!({ "company": "Acme & Co" });
+
+        let email = templates
+            .render_for("confirmation", &context, attributes.as_object().unwrap())
+            .unwrap();
+        let without_attributes = templates
+            .render_for("confirmation", &context, &Attributes::new())
+            .unwrap();
+
+        assert_eq!(email.html_body, "<p>Ursula of Acme &amp; Co</p>");
+        assert_eq!(email.text_body, "Ursula of Acme & Co");
+        assert_eq!(without_attributes.text_body, "Ursula of nowhere");
+    }
+
+    #[test]
+    fn a_template_with_a_malformed_merge_tag_fails_to_load() {
+        let directory = template_directory(&[(
+            "emails/confirmation.html",
+            "{{ attributes.company | upcase }}",
+        )]);
+        assert_err!(EmailTemplates::load(&directory));
+    }
+
+    #[test]
+    fn a_template_referring_to_a_variable_its_caller_does_not_pass_fails_to_load() {
+        let directory =
+            template_directory(&[("emails/confirmation.html", "{{ unsubscribe_link }}")]);
+        assert_err!(EmailTemplates::load(&directory));
+    }
+
+    #[test]
+    fn a_template_nothing_renders_fails_to_load() {
+        let directory = template_directory(&[("emails/welcome.html", "<p>Welcome!</p>")]);
+        assert_err!(EmailTemplates::load(&directory));
+    }
+}
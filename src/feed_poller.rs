@@ -0,0 +1,448 @@
+//! Turn the items of an external RSS feed (such as the blog's) into
+//! newsletter issues.
+//!
+//! Every item is remembered by its GUID, so it only makes it into one issue
+//! however often the feed is polled. Items already in the feed the first
+//! time we poll it are only remembered: nobody wants the whole back catalogue
+//! in their inbox.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use sqlx::{Connection, PgPool, Postgres, Transaction};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::configuration::{DeliverySettings, FeedMode, FeedSettings, FeedSource, Settings};
+use crate::email_templates::EmailTemplates;
+use crate::issue_scheduler::start_sending;
+use crate::startup::get_connection_pool;
+
+/// Feeds in digest mode get (at most) one issue this often.
+const DIGEST_INTERVAL_DAYS: i64 = 7;
+
+pub struct FeedClient {
+    http_client: Client,
+}
+
+impl FeedClient {
+    pub fn new(timeout: std::time::Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self { http_client }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn fetch(&self, url: &str) -> Result<Vec<FeedItem>, anyhow::Error> {
+        let body = self
+            .http_client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to fetch the feed.")?
+            .error_for_status()
+            .context("The feed server returned an error.")?
+            .bytes()
+            .await
+            .context("Failed to read the feed.")?;
+        let channel =
+            rss::Channel::read_from(&body[..]).context("The feed is not valid RSS.")?;
+        Ok(channel.items().iter().filter_map(FeedItem::from_rss).collect())
+    }
+}
+
+/// The parts of a feed item that end up in an issue.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct FeedItem {
+    #[serde(skip)]
+    guid: String,
+    title: String,
+    link: Option<String>,
+    description: Option<String>,
+    #[serde(skip)]
+    published_at: Option<DateTime<Utc>>,
+}
+
+impl FeedItem {
+    /// Items with neither a GUID nor a link cannot be told apart reliably,
+    /// so they are skipped.
+    fn from_rss(item: &rss::Item) -> Option<Self> {
+        let link = item.link().map(str::trim).filter(|l| !l.is_empty());
+        let guid = item
+            .guid()
+            .map(|g| g.value().trim())
+            .filter(|g| !g.is_empty())
+            .or(link)?;
+        // Issue titles are email subjects: one line, never empty.
+        let title = item
+            .title()
+            .map(|t| t.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| "New post".into());
+        Some(Self {
+            guid: guid.to_owned(),
+            title,
+            link: link.map(str::to_owned),
+            description: item.description().map(str::to_owned),
+            published_at: item
+                .pub_date()
+                .and_then(|d| DateTime::parse_from_rfc2822(d).ok())
+                .map(|d| d.with_timezone(&Utc)),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PollOutcome {
+    /// Items we had not seen before. None are new on the first poll.
+    pub new_items: usize,
+    /// The issues created by this poll.
+    pub issues: Vec<Uuid>,
+    /// Items that could not be turned into an issue, and were set aside.
+    pub failed_items: usize,
+}
+
+pub async fn run_feed_poller_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let templates = EmailTemplates::load(Path::new(
+        &configuration.application.templates_directory,
+    ))?;
+    let client = configuration.feeds.client();
+    poller_loop(
+        connection_pool,
+        client,
+        templates,
+        configuration.delivery,
+        configuration.feeds,
+    )
+    .await
+}
+
+async fn poller_loop(
+    pool: PgPool,
+    client: FeedClient,
+    templates: EmailTemplates,
+    delivery: DeliverySettings,
+    settings: FeedSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        for source in &settings.sources {
+            // A broken feed must not hold up the others; the error has
+            // already been logged.
+            let _ = poll_feed(&pool, &client, &templates, &delivery, source, Utc::now()).await;
+        }
+        tokio::time::sleep(settings.poll_interval()).await;
+    }
+}
+
+/// Fetch `source`, remember its new items and create the issues that are
+/// due as of `now`.
+///
+/// The feed's row is locked for the whole poll, so that several instances
+/// of the application polling at once do not create the same issue twice.
+/// Items that cannot be turned into an issue are marked as failed, with the
+/// reason, and the rest of the poll goes ahead without them.
+#[tracing::instrument(
+    skip(pool, client, templates, delivery, source),
+    fields(feed_name = %source.name),
+    err
+)]
+pub async fn poll_feed(
+    pool: &PgPool,
+    client: &FeedClient,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+    source: &FeedSource,
+    now: DateTime<Utc>,
+) -> Result<PollOutcome, anyhow::Error> {
+    let items = client.fetch(&source.url).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let first_poll = sqlx::query!(
+        r#"
+        INSERT INTO feeds (feed_name, first_polled_at, last_polled_at)
+        VALUES ($1, $2, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        source.name,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to register the feed.")?
+    .rows_affected()
+        == 1;
+    // Also locks the feed until the poll is over.
+    let feed = sqlx::query!(
+        r#"
+        UPDATE feeds SET last_polled_at = $2
+        WHERE feed_name = $1
+        RETURNING first_polled_at, last_digest_at
+        "#,
+        source.name,
+        now,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to lock the feed.")?;
+    let mut new_items = Vec::new();
+    // Feeds list their most recent items first.
+    for item in items.into_iter().rev() {
+        let status = if first_poll { "seen" } else { "pending" };
+        if store_item(&mut transaction, &source.name, &item, status, now).await? && !first_poll {
+            new_items.push(item);
+        }
+    }
+    let mut outcome = PollOutcome {
+        new_items: new_items.len(),
+        issues: Vec::new(),
+        failed_items: 0,
+    };
+    if source.digest {
+        let last_digest_at = feed.last_digest_at.unwrap_or(feed.first_polled_at);
+        if last_digest_at + chrono::Duration::days(DIGEST_INTERVAL_DAYS) <= now {
+            let pending = get_pending_items(&mut transaction, &source.name).await?;
+            if !pending.is_empty() {
+                let title = source
+                    .digest_title
+                    .clone()
+                    .unwrap_or_else(|| format!("This week on {}", source.name));
+                let digest = issue_items(
+                    &mut transaction,
+                    templates,
+                    delivery,
+                    source,
+                    "digest",
+                    &title,
+                    &serde_json::json!({ "feed_name": source.name, "items": pending }),
+                    &pending,
+                )
+                .await?;
+                match digest {
+                    Some(issue_id) => outcome.issues.push(issue_id),
+                    None => outcome.failed_items += pending.len(),
+                }
+            }
+            sqlx::query!(
+                "UPDATE feeds SET last_digest_at = $2 WHERE feed_name = $1",
+                source.name,
+                now,
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to record the digest.")?;
+        }
+    } else {
+        for item in new_items {
+            let issue = issue_items(
+                &mut transaction,
+                templates,
+                delivery,
+                source,
+                "item",
+                &item.title,
+                &serde_json::json!({ "feed_name": source.name, "item": item }),
+                std::slice::from_ref(&item),
+            )
+            .await?;
+            match issue {
+                Some(issue_id) => outcome.issues.push(issue_id),
+                None => outcome.failed_items += 1,
+            }
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the poll.")?;
+    Ok(outcome)
+}
+
+/// Returns whether the item is new.
+#[tracing::instrument(skip(transaction, item), fields(guid = %item.guid))]
+async fn store_item(
+    transaction: &mut Transaction<'_, Postgres>,
+    feed_name: &str,
+    item: &FeedItem,
+    status: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO feed_items (
+            feed_name,
+            guid,
+            title,
+            link,
+            description,
+            published_at,
+            first_seen_at,
+            status
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT DO NOTHING
+        "#,
+        feed_name,
+        item.guid,
+        item.title,
+        item.link,
+        item.description,
+        item.published_at,
+        now,
+        status,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the feed item.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_pending_items(
+    transaction: &mut Transaction<'_, Postgres>,
+    feed_name: &str,
+) -> Result<Vec<FeedItem>, anyhow::Error> {
+    let items = sqlx::query_as!(
+        FeedItem,
+        r#"
+        SELECT guid, title, link, description, published_at
+        FROM feed_items
+        WHERE feed_name = $1 AND status = 'pending'
+        ORDER BY published_at NULLS LAST, first_seen_at, guid
+        "#,
+        feed_name,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to fetch the items waiting for the digest.")?;
+    Ok(items)
+}
+
+/// Turn `items` into an issue rendered from the `template` feed template,
+/// in a savepoint of its own.
+///
+/// Returns `None` if that failed: the savepoint is rolled back and the
+/// items are marked as failed, so that they are not tried again.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(transaction, templates, delivery, source, context, items))]
+async fn issue_items(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+    source: &FeedSource,
+    template: &str,
+    title: &str,
+    context: &serde_json::Value,
+    items: &[FeedItem],
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut savepoint = transaction
+        .begin()
+        .await
+        .context("Failed to create a savepoint.")?;
+    let issued = async {
+        let markdown = templates
+            .render_feed_issue(template, context)
+            .context("Failed to render the feed issue.")?;
+        create_issue(&mut savepoint, templates, delivery, source, title, &markdown, items).await
+    }
+    .await;
+    match issued {
+        Ok(issue_id) => {
+            savepoint
+                .commit()
+                .await
+                .context("Failed to release the savepoint.")?;
+            Ok(Some(issue_id))
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to turn feed items into an issue",
+            );
+            savepoint
+                .rollback()
+                .await
+                .context("Failed to roll back to the savepoint.")?;
+            mark_items_failed(transaction, &source.name, items, &e).await?;
+            Ok(None)
+        }
+    }
+}
+
+#[tracing::instrument(skip(transaction, items, error))]
+async fn mark_items_failed(
+    transaction: &mut Transaction<'_, Postgres>,
+    feed_name: &str,
+    items: &[FeedItem],
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    let guids: Vec<String> = items.iter().map(|item| item.guid.clone()).collect();
+    sqlx::query!(
+        r#"
+        UPDATE feed_items SET status = 'failed', failure_reason = $3
+        WHERE feed_name = $1 AND guid = ANY($2)
+        "#,
+        feed_name,
+        &guids,
+        format!("{:#}", error),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark the feed items as failed.")?;
+    Ok(())
+}
+
+/// Store an issue made of `items`, and send it if the feed says so.
+#[tracing::instrument(skip(transaction, templates, delivery, source, markdown, items))]
+async fn create_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+    source: &FeedSource,
+    title: &str,
+    markdown: &str,
+    items: &[FeedItem],
+) -> Result<Uuid, anyhow::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (newsletter_issue_id, title, markdown_content, created_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        newsletter_issue_id,
+        title,
+        markdown,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the newsletter issue.")?;
+    let guids: Vec<String> = items.iter().map(|item| item.guid.clone()).collect();
+    sqlx::query!(
+        r#"
+        UPDATE feed_items SET status = 'issued', newsletter_issue_id = $3
+        WHERE feed_name = $1 AND guid = ANY($2)
+        "#,
+        source.name,
+        &guids,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to link the feed items to their issue.")?;
+    if source.mode == FeedMode::Publish {
+        start_sending(
+            transaction,
+            templates,
+            delivery,
+            newsletter_issue_id,
+            title,
+            markdown,
+            None,
+        )
+        .await?;
+    }
+    Ok(newsletter_issue_id)
+}
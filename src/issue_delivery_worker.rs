@@ -0,0 +1,526 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgListener;
+use sqlx::types::Json;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::attributes::Attributes;
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_message::EmailMessage;
+use crate::issue_rendering::personalise;
+use crate::merge_tags::{fill_merge_tags, Format};
+use crate::startup::get_connection_pool;
+use crate::tracking::Tracker;
+
+/// Notified whenever deliveries are queued, so that idle workers wake up.
+pub const DELIVERY_QUEUE_CHANNEL: &str = "issue_delivery_queue";
+
+/// The longest an idle worker sleeps without checking the queue, in case a
+/// notification got lost along with a dropped connection.
+const MAX_IDLE_TIME: Duration = Duration::from_secs(60);
+
+/// The shortest an idle worker sleeps, so that it does not spin on a
+/// delivery another worker is claiming.
+const MIN_IDLE_TIME: Duration = Duration::from_millis(500);
+
+/// How long a claimed delivery is hidden from other workers: longer than a
+/// send takes to succeed or time out. Should the worker die in the meantime,
+/// the delivery is attempted again once the claim expires.
+const CLAIM_DURATION_SECONDS: i64 = 300;
+
+/// Attempts at a delivery that keeps failing for a transient reason, after
+/// which it is recorded as failed.
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+
+const FIRST_RETRY_DELAY_SECONDS: i64 = 60;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    /// No delivery is due right now.
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let tracker = Tracker::new(
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret,
+    );
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        tracker,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    tracker: Tracker,
+) -> Result<(), anyhow::Error> {
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(DELIVERY_QUEUE_CHANNEL).await?;
+    loop {
+        match try_execute_task(&pool, &email_client, &base_url, &tracker).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                // Sleep until the next delivery is due, or until new ones get queued.
+                let next_delivery_at = get_next_delivery_time(&pool).await.unwrap_or(None);
+                tokio::select! {
+                    _ = tokio::time::sleep(idle_time(next_delivery_at, Utc::now())) => {}
+                    notification = listener.recv() => {
+                        if notification.is_err() {
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_id=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    tracker: &Tracker,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some(task) = claim_task(pool).await? else {
+        mark_sent_issues(pool).await?;
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.issue_id))
+        .record("subscriber_id", display(task.subscriber_id));
+
+    let outcome = match deliver(pool, email_client, base_url, tracker, &task).await {
+        Ok(outcome) => outcome,
+        Err(DeliveryError::Transient(e)) if task.attempts + 1 < MAX_DELIVERY_ATTEMPTS => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Retrying later.",
+            );
+            retry_task(pool, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        Err(DeliveryError::Transient(e) | DeliveryError::Permanent(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Skipping.",
+            );
+            DeliveryOutcome::Failed
+        }
+    };
+    complete_task(pool, &task, outcome).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Why an attempt at a delivery failed.
+enum DeliveryError {
+    /// Worth another attempt later, e.g. the email provider is down.
+    Transient(anyhow::Error),
+    /// Would fail in the same way every time, e.g. an invalid address.
+    Permanent(anyhow::Error),
+}
+
+async fn deliver(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    tracker: &Tracker,
+    task: &Task,
+) -> Result<DeliveryOutcome, DeliveryError> {
+    let recipient = get_recipient(pool, task.subscriber_id)
+        .await
+        .map_err(DeliveryError::Transient)?;
+    // The subscriber left (or bounced) after the issue was published.
+    let Some(recipient) = recipient else {
+        return Ok(DeliveryOutcome::Skipped);
+    };
+    let email = SubscriberEmail::parse(recipient.email).map_err(|e| {
+        DeliveryError::Permanent(anyhow::anyhow!(
+            "The stored contact details of the subscriber are invalid: {}",
+            e
+        ))
+    })?;
+    let issue = get_issue(pool, task.issue_id, task.variant).await?;
+    let unsubscribe_link = format!(
+        "{}/subscriptions/unsubscribe?subscription_token={}",
+        base_url, recipient.subscription_token
+    );
+    let attributes = &recipient.attributes.0;
+    // Merge tags first, so that links built from attributes get tracked too.
+    let html_content = fill_merge_tags(&issue.html_content, attributes, Format::Html);
+    let html_content = if issue.tracking_enabled {
+        tracker.track_html(&html_content, task.issue_id, task.subscriber_id)
+    } else {
+        html_content
+    };
+    let text_content = fill_merge_tags(&issue.text_content, attributes, Format::Text);
+    let message = EmailMessage::builder(email_client.sender().clone(), issue.title)
+        .to(email)
+        .html_body(personalise(&html_content, &unsubscribe_link))
+        .text_body(personalise(&text_content, &unsubscribe_link))
+        // Lets mail clients offer their own unsubscribe button.
+        .header("List-Unsubscribe", format!("<{}>", unsubscribe_link))
+        .build()
+        .map_err(|e| DeliveryError::Permanent(anyhow::Error::msg(e)))?;
+    email_client.send(&message).await.map_err(|e| {
+        if e.is_transient() {
+            DeliveryError::Transient(e.into())
+        } else {
+            DeliveryError::Permanent(e.into())
+        }
+    })?;
+    Ok(DeliveryOutcome::Delivered)
+}
+
+/// How long to wait after the `attempts`-th failed attempt: a minute, then
+/// twice as long every time.
+fn retry_delay(attempts: i16) -> chrono::Duration {
+    let doublings = (attempts.max(1) - 1).min(10) as u32;
+    chrono::Duration::seconds(FIRST_RETRY_DELAY_SECONDS << doublings)
+}
+
+/// Never less than `MIN_IDLE_TIME`: a delivery that is due, yet could not be
+/// claimed, is being claimed by another worker right now.
+pub(crate) fn idle_time(next_delivery_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Duration {
+    match next_delivery_at {
+        Some(t) => (t - now)
+            .to_std()
+            .unwrap_or_default()
+            .clamp(MIN_IDLE_TIME, MAX_IDLE_TIME),
+        None => MAX_IDLE_TIME,
+    }
+}
+
+/// When the next delivery nobody else is busy with is due.
+#[tracing::instrument(skip_all)]
+async fn get_next_delivery_time(pool: &PgPool) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT not_before
+        FROM issue_delivery_queue
+        ORDER BY not_before
+        FOR SHARE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(r.map(|r| r.not_before))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DeliveryOutcome {
+    Delivered,
+    Failed,
+    Skipped,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Delivered => "delivered",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    /// The A/B test variant the subscriber gets, if any.
+    variant: Option<i16>,
+    /// Earlier attempts, that failed for a transient reason.
+    attempts: i16,
+}
+
+/// Claim a due delivery for `CLAIM_DURATION_SECONDS`, rather than locking
+/// it: no connection is tied up, and no transaction kept open, while the
+/// email is being sent.
+#[tracing::instrument(skip_all)]
+async fn claim_task(pool: &PgPool) -> Result<Option<Task>, anyhow::Error> {
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        UPDATE issue_delivery_queue q
+        SET not_before = now() + make_interval(secs => $1)
+        FROM (
+            SELECT newsletter_issue_id, subscriber_id
+            FROM issue_delivery_queue
+            WHERE not_before <= now()
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        ) due
+        WHERE q.newsletter_issue_id = due.newsletter_issue_id
+            AND q.subscriber_id = due.subscriber_id
+        RETURNING
+            q.newsletter_issue_id AS "issue_id!",
+            q.subscriber_id AS "subscriber_id!",
+            q.variant,
+            q.attempts AS "attempts!"
+        "#,
+        CLAIM_DURATION_SECONDS as f64,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(task)
+}
+
+/// Put a delivery back in the queue, to be attempted again after a delay.
+#[tracing::instrument(skip_all)]
+async fn retry_task(pool: &PgPool, task: &Task) -> Result<(), anyhow::Error> {
+    let attempts = task.attempts + 1;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET attempts = $3, not_before = $4
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.issue_id,
+        task.subscriber_id,
+        attempts,
+        Utc::now() + retry_delay(attempts),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record the outcome of a delivery and take it off the queue, at once.
+#[tracing::instrument(skip_all)]
+async fn complete_task(
+    pool: &PgPool,
+    task: &Task,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    record_delivery(
+        &mut transaction,
+        task.issue_id,
+        task.subscriber_id,
+        task.variant,
+        outcome,
+    )
+    .await?;
+    delete_task(transaction, task.issue_id, task.subscriber_id).await
+}
+
+/// Issues whose queue has drained are done sending, unless they are waiting
+/// for the winner of an A/B test.
+///
+/// Tasks still held by another worker are visible here, so an issue is only
+/// marked as sent once its last task has been committed.
+#[tracing::instrument(skip_all)]
+async fn mark_sent_issues(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = 'sent'
+        WHERE i.status = 'sending'
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_ab_tests t
+                WHERE t.newsletter_issue_id = i.newsletter_issue_id
+                    AND t.winning_variant IS NULL
+            )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    variant: Option<i16>,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id,
+            subscriber_id,
+            variant,
+            outcome,
+            attempted_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        issue_id,
+        subscriber_id,
+        variant,
+        outcome.as_str(),
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+struct Recipient {
+    email: String,
+    subscription_token: String,
+    attributes: Json<Attributes>,
+}
+
+/// Returns `None` if the subscriber is no longer confirmed.
+#[tracing::instrument(skip_all)]
+async fn get_recipient(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let recipient = sqlx::query_as!(
+        Recipient,
+        r#"
+        SELECT
+            s.email,
+            t.subscription_token,
+            s.attributes AS "attributes: Json<Attributes>"
+        FROM subscriptions s
+        JOIN subscriptions_tokens t ON t.subscription_id = s.id
+        WHERE s.id = $1 AND s.status = 'confirmed'
+        LIMIT 1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(recipient)
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    tracking_enabled: bool,
+}
+
+/// Recipients of an A/B test are always tracked: the winner is chosen
+/// from their opens and clicks.
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    variant: Option<i16>,
+) -> Result<NewsletterIssue, DeliveryError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(v.subject, i.title) AS "title!",
+            COALESCE(v.text_content, i.text_content) AS text_content,
+            COALESCE(v.html_content, i.html_content) AS html_content,
+            i.tracking_enabled OR v.variant IS NOT NULL AS "tracking_enabled!"
+        FROM newsletter_issues i
+        LEFT JOIN issue_variants v
+            ON v.newsletter_issue_id = i.newsletter_issue_id AND v.variant = $2
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        issue_id,
+        variant,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the issue.")
+    .map_err(DeliveryError::Transient)?;
+    let (Some(text_content), Some(html_content)) = (issue.text_content, issue.html_content) else {
+        return Err(DeliveryError::Permanent(anyhow::anyhow!(
+            "A queued issue has not been rendered."
+        )));
+    };
+    Ok(NewsletterIssue {
+        title: issue.title,
+        text_content,
+        html_content,
+        tracking_enabled: issue.tracking_enabled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{idle_time, retry_delay, MAX_IDLE_TIME, MIN_IDLE_TIME};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn idle_workers_sleep_until_the_next_delivery_is_due() {
+        let now = Utc::now();
+        assert_eq!(
+            idle_time(Some(now + Duration::seconds(5)), now),
+            std::time::Duration::from_secs(5)
+        );
+        assert_eq!(idle_time(Some(now - Duration::seconds(5)), now), MIN_IDLE_TIME);
+    }
+
+    #[test]
+    fn idle_workers_wake_up_regularly_even_without_deliveries() {
+        let now = Utc::now();
+        assert_eq!(idle_time(None, now), MAX_IDLE_TIME);
+        assert_eq!(idle_time(Some(now + Duration::days(1)), now), MAX_IDLE_TIME);
+    }
+
+    #[test]
+    fn failed_deliveries_are_retried_with_exponential_backoff() {
+        let delays: Vec<_> = [1, 2, 3, 4]
+            .into_iter()
+            .map(|attempts| retry_delay(attempts).num_seconds())
+            .collect();
+
+        assert_eq!(delays, vec![60, 120, 240, 480]);
+    }
+}
@@ -0,0 +1,163 @@
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::attributes::Attributes;
+use crate::email_templates::{EmailTemplates, RenderedEmail};
+use crate::merge_tags::{fill_merge_tags, Format};
+
+/// Stands in for the recipient's own unsubscribe link in a stored issue.
+/// It is filled in when the issue is sent to each subscriber.
+pub const UNSUBSCRIBE_LINK_PLACEHOLDER: &str = "{{ unsubscribe_link }}";
+
+/// Render Markdown to HTML, dropping anything that has no business in an
+/// email (scripts, event handlers, iframes, ...).
+pub fn markdown_to_html(markdown: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    let parser = Parser::new_ext(markdown, options);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+    ammonia::clean(&unsafe_html)
+}
+
+/// Render an issue to the HTML and plain-text bodies that get stored with it.
+///
+/// The HTML is wrapped in the email layout; the text version is derived from
+/// it, with links turned into footnote-style references.
+pub fn render_issue(
+    templates: &EmailTemplates,
+    title: &str,
+    markdown: &str,
+) -> Result<RenderedEmail, tera::Error> {
+    templates.render(
+        "newsletter_issue",
+        &serde_json::json!({
+            "title": title,
+            "content": markdown_to_html(markdown),
+            "unsubscribe_link": UNSUBSCRIBE_LINK_PLACEHOLDER,
+        }),
+    )
+}
+
+/// Render an issue for the public archive: the same layout as the email,
+/// minus the unsubscribe link. Tracking is only ever added per recipient,
+/// so there is none to strip here. Merge tags get their defaults, as there
+/// is no recipient to take attributes from.
+pub fn render_web_issue(
+    templates: &EmailTemplates,
+    title: &str,
+    markdown: &str,
+) -> Result<String, tera::Error> {
+    templates
+        .render(
+            "newsletter_issue",
+            &serde_json::json!({
+                "title": title,
+                "content": markdown_to_html(markdown),
+                "unsubscribe_link": "",
+            }),
+        )
+        .map(|rendered| fill_merge_tags(&rendered.html_body, &Attributes::new(), Format::Html))
+}
+
+/// Fill in the merge tags of a rendered issue as if for a recipient with no
+/// attributes, e.g. for a preview.
+pub fn fill_default_merge_tags(rendered: RenderedEmail) -> RenderedEmail {
+    RenderedEmail {
+        html_body: fill_merge_tags(&rendered.html_body, &Attributes::new(), Format::Html),
+        text_body: fill_merge_tags(&rendered.text_body, &Attributes::new(), Format::Text),
+    }
+}
+
+/// Turn a title into the readable part of an archive URL, e.g.
+/// `What's new in 2.0?` into `what-s-new-in-2-0`.
+pub fn slugify(title: &str) -> String {
+    title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Fill in the per-recipient parts of a stored issue.
+pub fn personalise(content: &str, unsubscribe_link: &str) -> String {
+    content.replace(UNSUBSCRIBE_LINK_PLACEHOLDER, unsubscribe_link)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_templates::EmailTemplates;
+    use crate::issue_rendering::{
+        markdown_to_html, personalise, render_issue, render_web_issue, slugify,
+    };
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(std::path::Path::new("templates")).unwrap()
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html = markdown_to_html("# Hello\n\nSome *emphasis* and a [link](https://example.com).");
+
+        assert!(html.contains("<h1>Hello</h1>"));
+        assert!(html.contains("<em>emphasis</em>"));
+        assert!(html.contains(r#"href="https://example.com""#));
+    }
+
+    #[test]
+    fn unsafe_html_is_stripped() {
+        let html = markdown_to_html(
+            "<script>alert(1)</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"alert(1)\">x</a>",
+        );
+
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onclick"));
+    }
+
+    #[test]
+    fn the_text_version_uses_footnote_references_for_links() {
+        let issue = render_issue(
+            &templates(),
+            "Issue #1",
+            "Read [the announcement](https://example.com/announcement).",
+        )
+        .unwrap();
+
+        assert!(issue.text_body.contains("[the announcement][1]"));
+        assert!(issue.text_body.contains("[1]: https://example.com/announcement"));
+        assert!(!issue.text_body.contains("<a"));
+    }
+
+    #[test]
+    fn the_unsubscribe_link_is_filled_in_per_recipient() {
+        let issue = render_issue(&templates(), "Issue #1", "Hello").unwrap();
+        let link = "https://example.com/subscriptions/unsubscribe?subscription_token=abc";
+
+        let html = personalise(&issue.html_body, link);
+        let text = personalise(&issue.text_body, link);
+
+        assert!(html.contains(&format!(r#"href="{}""#, link)));
+        assert!(text.contains(link));
+        assert!(!html.contains("{{"));
+        assert!(!text.contains("{{"));
+    }
+
+    #[test]
+    fn the_web_version_has_no_unsubscribe_link() {
+        let html = render_web_issue(&templates(), "Issue #1", "Hello").unwrap();
+
+        assert!(html.contains("Hello"));
+        assert!(!html.contains("Unsubscribe"));
+        assert!(!html.contains("{{"));
+    }
+
+    #[test]
+    fn slugs_keep_only_lowercase_letters_and_digits() {
+        assert_eq!(slugify("What's new in 2.0?"), "what-s-new-in-2-0");
+        assert_eq!(slugify("  Issue #1 -- Hello  "), "issue-1-hello");
+        assert_eq!(slugify("Café ☕"), "caf");
+        assert_eq!(slugify("???"), "");
+    }
+}
@@ -0,0 +1,465 @@
+use anyhow::Context;
+use chrono::{NaiveDateTime, Utc};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::ab_testing::{assign_variants, choose_winner, get_variant_results, WinnerMetric};
+use crate::configuration::{DeliverySettings, Settings};
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::DELIVERY_QUEUE_CHANNEL;
+use crate::issue_rendering::{render_issue, render_web_issue, slugify};
+use crate::segments::get_audience;
+use crate::startup::get_connection_pool;
+
+pub enum SchedulerOutcome {
+    IssueStarted,
+    /// A due issue could not be started, and was marked as `failed`.
+    IssueFailed,
+    WinnerChosen,
+    NothingDue,
+}
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let templates = EmailTemplates::load(Path::new(
+        &configuration.application.templates_directory,
+    ))?;
+    scheduler_loop(connection_pool, templates, configuration.delivery).await
+}
+
+async fn scheduler_loop(
+    pool: PgPool,
+    templates: EmailTemplates,
+    delivery: DeliverySettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        let started = try_start_due_issue(&pool, &templates, &delivery).await;
+        let decided = try_decide_ab_test(&pool, &templates, &delivery).await;
+        match (started, decided) {
+            (Ok(SchedulerOutcome::NothingDue), Ok(SchedulerOutcome::NothingDue)) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            (Err(_), _) | (_, Err(_)) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Start sending the next scheduled issue that is due, if any.
+///
+/// The issue is locked with `SKIP LOCKED` and leaves the `scheduled` state
+/// in the same transaction, so that it is picked up exactly once even when
+/// several instances of the application are running.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty), err)]
+pub async fn try_start_due_issue(
+    pool: &PgPool,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+) -> Result<SchedulerOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, markdown_content, local_delivery_time
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_at <= $1
+        ORDER BY scheduled_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        Utc::now(),
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(issue) = issue else {
+        return Ok(SchedulerOutcome::NothingDue);
+    };
+    Span::current().record("newsletter_issue_id", display(issue.newsletter_issue_id));
+    let started = start_sending(
+        &mut transaction,
+        templates,
+        delivery,
+        issue.newsletter_issue_id,
+        &issue.title,
+        &issue.markdown_content,
+        issue.local_delivery_time,
+    )
+    .await;
+    if let Err(e) = started {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to start sending a scheduled issue",
+        );
+        // It would fail in the same way on every tick: set it aside instead.
+        transaction.rollback().await?;
+        mark_issue_failed(pool, issue.newsletter_issue_id, &e).await?;
+        return Ok(SchedulerOutcome::IssueFailed);
+    }
+    transaction.commit().await?;
+    Ok(SchedulerOutcome::IssueStarted)
+}
+
+#[tracing::instrument(skip(pool, error))]
+async fn mark_issue_failed(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'failed', failure_reason = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id,
+        format!("{:#}", error),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark the newsletter issue as failed.")?;
+    Ok(())
+}
+
+/// Render the issue, freeze the rendering (for email and for the archive)
+/// and queue a delivery to its audience: every confirmed subscriber, or
+/// those in its segment.
+///
+/// With a `local_delivery_time`, each delivery waits for that time of day in
+/// the subscriber's own timezone; otherwise everything goes out right away.
+/// Issues with an A/B test only go to the test samples for now.
+/// The caller must hold a lock on the issue. The issue is marked as `sent`
+/// by the delivery worker once its queue has drained.
+#[tracing::instrument(skip(transaction, templates, delivery, title, markdown_content))]
+pub async fn start_sending(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+    newsletter_issue_id: Uuid,
+    title: &str,
+    markdown_content: &str,
+    local_delivery_time: Option<NaiveDateTime>,
+) -> Result<(), anyhow::Error> {
+    let rendered = render_issue(templates, title, markdown_content)
+        .context("Failed to render the newsletter issue.")?;
+    let web_html = render_web_issue(templates, title, markdown_content)
+        .context("Failed to render the web version of the newsletter issue.")?;
+    let slug = choose_slug(transaction, newsletter_issue_id, title).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            html_content = $2,
+            text_content = $3,
+            web_html_content = $4,
+            slug = $5,
+            published_at = $6,
+            local_delivery_time = $7,
+            status = 'sending',
+            failure_reason = NULL
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        rendered.html_body,
+        rendered.text_body,
+        web_html,
+        slug,
+        Utc::now(),
+        local_delivery_time,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the rendered newsletter issue.")?;
+    let ab_test = sqlx::query!(
+        "SELECT sample_fraction FROM issue_ab_tests WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch the A/B test of the newsletter issue.")?;
+    match ab_test {
+        Some(ab_test) => {
+            queue_ab_test_samples(
+                transaction,
+                templates,
+                delivery,
+                newsletter_issue_id,
+                title,
+                markdown_content,
+                local_delivery_time,
+                ab_test.sample_fraction,
+            )
+            .await?
+        }
+        None => {
+            let audience = get_audience(transaction, newsletter_issue_id).await?;
+            sqlx::query!(
+                r#"
+                INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, not_before)
+                SELECT
+                    $1,
+                    s.id,
+                    COALESCE($3::timestamp AT TIME ZONE COALESCE(s.timezone, $4), now())
+                FROM subscriptions s
+                WHERE s.id = ANY($2)
+                "#,
+                newsletter_issue_id,
+                &audience,
+                local_delivery_time,
+                delivery.default_timezone.name(),
+            )
+            .execute(&mut **transaction)
+            .await
+            .context("Failed to enqueue delivery tasks.")?;
+        }
+    }
+    // Wake up idle delivery workers once the transaction commits.
+    sqlx::query!("SELECT pg_notify($1, '')", DELIVERY_QUEUE_CHANNEL)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to notify the delivery workers.")?;
+    Ok(())
+}
+
+/// The archive URL of an issue: its slugified title, made unique with the
+/// start of the issue id if another issue already uses it.
+#[tracing::instrument(skip(transaction))]
+async fn choose_slug(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    title: &str,
+) -> Result<String, anyhow::Error> {
+    let id_prefix = &newsletter_issue_id.simple().to_string()[..8];
+    let slug = slugify(title);
+    if slug.is_empty() {
+        return Ok(id_prefix.to_owned());
+    }
+    let taken = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM newsletter_issues WHERE slug = $1) AS "taken!""#,
+        slug,
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to check whether the slug is taken.")?
+    .taken;
+    Ok(if taken {
+        format!("{}-{}", slug, id_prefix)
+    } else {
+        slug
+    })
+}
+
+/// Render every variant and queue each of them for its random sample.
+///
+/// The wait before choosing the winner starts once the last sample
+/// delivery is due.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(transaction, templates, delivery, title, markdown_content))]
+async fn queue_ab_test_samples(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+    newsletter_issue_id: Uuid,
+    title: &str,
+    markdown_content: &str,
+    local_delivery_time: Option<NaiveDateTime>,
+    sample_fraction: f64,
+) -> Result<(), anyhow::Error> {
+    let variants = sqlx::query!(
+        r#"
+        SELECT variant, subject, markdown_content
+        FROM issue_variants
+        WHERE newsletter_issue_id = $1
+        ORDER BY variant
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to fetch the variants of the newsletter issue.")?;
+    for variant in &variants {
+        let rendered = render_issue(
+            templates,
+            variant.subject.as_deref().unwrap_or(title),
+            variant.markdown_content.as_deref().unwrap_or(markdown_content),
+        )
+        .context("Failed to render a variant of the newsletter issue.")?;
+        sqlx::query!(
+            r#"
+            UPDATE issue_variants SET html_content = $3, text_content = $4
+            WHERE newsletter_issue_id = $1 AND variant = $2
+            "#,
+            newsletter_issue_id,
+            variant.variant,
+            rendered.html_body,
+            rendered.text_body,
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to store a rendered variant.")?;
+    }
+
+    let audience = get_audience(transaction, newsletter_issue_id).await?;
+    let (subscriber_ids, assigned_variants): (Vec<Uuid>, Vec<i16>) = assign_variants(
+        audience,
+        variants.len(),
+        sample_fraction,
+        &mut StdRng::from_entropy(),
+    )
+    .into_iter()
+    .unzip();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, not_before, variant)
+        SELECT
+            $1,
+            s.id,
+            COALESCE($4::timestamp AT TIME ZONE COALESCE(s.timezone, $5), now()),
+            sample.variant
+        FROM UNNEST($2::uuid[], $3::int2[]) AS sample (subscriber_id, variant)
+        JOIN subscriptions s ON s.id = sample.subscriber_id
+        "#,
+        newsletter_issue_id,
+        &subscriber_ids,
+        &assigned_variants,
+        local_delivery_time,
+        delivery.default_timezone.name(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to enqueue the A/B test samples.")?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_ab_tests t
+        SET decide_at = make_interval(mins => t.wait_minutes) + COALESCE(
+            (SELECT MAX(not_before) FROM issue_delivery_queue q
+             WHERE q.newsletter_issue_id = t.newsletter_issue_id),
+            now()
+        )
+        WHERE t.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to set the end of the A/B test.")?;
+    Ok(())
+}
+
+/// Choose the winner of the next A/B test whose wait is over, and queue it
+/// for everybody in the audience who has not received a variant.
+///
+/// The web version of the issue, in the archive and its feeds, becomes
+/// that of the winner.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty), err)]
+pub async fn try_decide_ab_test(
+    pool: &PgPool,
+    templates: &EmailTemplates,
+    delivery: &DeliverySettings,
+) -> Result<SchedulerOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let ab_test = sqlx::query!(
+        r#"
+        SELECT
+            t.newsletter_issue_id,
+            t.metric,
+            i.title,
+            i.markdown_content,
+            i.local_delivery_time
+        FROM issue_ab_tests t
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE t.winning_variant IS NULL AND t.decide_at <= $1
+        ORDER BY t.decide_at
+        FOR UPDATE OF t
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        Utc::now(),
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(ab_test) = ab_test else {
+        return Ok(SchedulerOutcome::NothingDue);
+    };
+    let newsletter_issue_id = ab_test.newsletter_issue_id;
+    Span::current().record("newsletter_issue_id", display(newsletter_issue_id));
+    let metric = WinnerMetric::try_from(ab_test.metric).map_err(anyhow::Error::msg)?;
+    let results = get_variant_results(&mut *transaction, newsletter_issue_id).await?;
+    let winner = choose_winner(&results, metric).context("The A/B test has no variants.")?;
+    tracing::info!(winning_variant = winner, "Chose the winner of an A/B test.");
+
+    sqlx::query!(
+        r#"
+        UPDATE issue_ab_tests SET winning_variant = $2, decided_at = $3
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        winner,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let variant = sqlx::query!(
+        r#"
+        SELECT subject, markdown_content FROM issue_variants
+        WHERE newsletter_issue_id = $1 AND variant = $2
+        "#,
+        newsletter_issue_id,
+        winner,
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let web_html = render_web_issue(
+        templates,
+        variant.subject.as_deref().unwrap_or(&ab_test.title),
+        variant.markdown_content.as_deref().unwrap_or(&ab_test.markdown_content),
+    )
+    .context("Failed to render the web version of the winning variant.")?;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET web_html_content = $2 WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+        web_html,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let audience = get_audience(&mut transaction, newsletter_issue_id).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, not_before, variant)
+        SELECT
+            $1,
+            s.id,
+            COALESCE($3::timestamp AT TIME ZONE COALESCE(s.timezone, $4), now()),
+            $2
+        FROM subscriptions s
+        WHERE s.id = ANY($5)
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = $1 AND d.subscriber_id = s.id
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = $1 AND q.subscriber_id = s.id
+            )
+        "#,
+        newsletter_issue_id,
+        winner,
+        ab_test.local_delivery_time,
+        delivery.default_timezone.name(),
+        &audience,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!("SELECT pg_notify($1, '')", DELIVERY_QUEUE_CHANNEL)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(SchedulerOutcome::WinnerChosen)
+}
@@ -1,8 +1,27 @@
-
+pub mod ab_testing;
+pub mod attributes;
+pub mod audit;
+pub mod authentication;
+pub mod clock;
 pub mod configuration;
+pub mod consent;
 
 pub mod domain;
 pub mod routes;
+pub mod segments;
+pub mod session_state;
 pub mod startup;
 pub mod telemetry;
-pub mod email_client;
\ No newline at end of file
+pub mod tracking;
+pub mod email_client;
+pub mod email_message;
+pub mod email_templates;
+pub mod feed_poller;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod issue_rendering;
+pub mod merge_tags;
+pub mod outbound_webhooks;
+pub mod password_reset_worker;
+pub mod request_id;
+pub mod utils;
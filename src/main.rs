@@ -1,27 +1,112 @@
+use secrecy::Secret;
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
+use zero2prod::authentication::create_first_owner;
 use zero2prod::configuration::get_configuration;
-use zero2prod::startup::{build, Application};
-use zero2prod::telemetry::{get_subscribe, init_subscriber};
+use zero2prod::feed_poller::run_feed_poller_until_stopped;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::issue_scheduler::run_scheduler_until_stopped;
+use zero2prod::outbound_webhooks::run_webhook_dispatcher_until_stopped;
+use zero2prod::password_reset_worker::run_password_reset_worker_until_stopped;
+use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::telemetry::{get_subscribe, init_subscriber, set_pii_redaction};
 
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error>{
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // `--check-config` validates the configuration and exits, e.g. before a deploy.
+    let check_config_only = args.iter().any(|arg| arg == "--check-config");
+    // `--create-owner <username>` creates the first admin of a new instance,
+    // with a password read from stdin.
+    let new_owner = args
+        .iter()
+        .position(|arg| arg == "--create-owner")
+        .map(|i| args.get(i + 1).cloned().unwrap_or_default());
+    let configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Failed to load the configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = configuration.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if check_config_only {
+        println!("The configuration is valid.");
+        return Ok(());
+    }
+    if let Some(username) = new_owner {
+        let pool = get_connection_pool(&configuration.database);
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        let password = Secret::new(password.trim_end_matches(['\r', '\n']).to_owned());
+        match create_first_owner(&username, password, &pool).await {
+            Ok(user_id) => println!("Created the owner `{}` ({}).", username.trim(), user_id),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
-    let subscriber = get_subscribe(
+    let (subscriber, log_filter) = get_subscribe(
         "zero2prod".into(), 
         "info".into(),
         std::io::stdout
     );
-    init_subscriber(subscriber);
+    init_subscriber(subscriber, log_filter);
 
-    let configuration = get_configuration().expect("msg==========");
-    // let server = build(configuration)
-    //     .await
-    //     .expect("Failed to build server");
-    // server.await?;
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    set_pii_redaction(configuration.telemetry.redact_pii);
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+    let webhook_task = tokio::spawn(run_webhook_dispatcher_until_stopped(configuration.clone()));
+    let password_reset_task =
+        tokio::spawn(run_password_reset_worker_until_stopped(configuration.clone()));
+    let feed_poller_task = tokio::spawn(run_feed_poller_until_stopped(configuration));
+
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = scheduler_task => report_exit("Scheduler", o),
+        o = feed_poller_task => report_exit("Feed poller", o),
+        o = webhook_task => report_exit("Webhook dispatcher", o),
+        o = password_reset_task => report_exit("Password reset worker", o),
+    };
 
     Ok(())
 }
 
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>
+) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
+
 //export https_proxy=http://127.0.0.1:7890 http_proxy=http://127.0.0.1:7890 all_proxy=socks5://127.0.0.1:7890
@@ -0,0 +1,275 @@
+//! Merge tags fill in subscriber attributes per recipient, e.g.
+//! `Hi {{ attributes.first_name | default: "there" }}!`.
+//!
+//! Issues are rendered once, when they are published, so merge tags go
+//! through Markdown and the email layout untouched and are only filled in
+//! by the delivery worker. Other `{{ ... }}` spans, such as the unsubscribe
+//! link placeholder or code samples, are left alone.
+//!
+//! Email templates take merge tags too: Tera is told to leave them be, and
+//! they are filled in once the template is rendered.
+
+use serde_json::Value;
+
+use crate::attributes::{AttributeSchema, Attributes};
+use crate::email_templates::escape_html;
+
+const PREFIX: &str = "attributes.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Values are HTML-escaped, and tags may contain HTML entities.
+    Html,
+    Text,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct MergeTag {
+    attribute: String,
+    default: Option<String>,
+}
+
+/// The `{{ ... }}` spans of `content` that start with `attributes.`, with
+/// their byte range and inner text.
+fn find_tags(content: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut tags = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = content[offset..].find("{{").map(|i| offset + i) {
+        let Some(end) = content[start + 2..].find("}}").map(|i| start + 2 + i) else {
+            break;
+        };
+        let inner = &content[start + 2..end];
+        if inner.trim_start().starts_with(PREFIX) {
+            tags.push((start..end + 2, inner));
+        }
+        offset = end + 2;
+    }
+    tags
+}
+
+fn decode_entities(input: &str) -> String {
+    input
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Parse the inside of a tag: `attributes.<name>`, optionally followed by
+/// `| default: "<text>"`. Line breaks (from text wrapping) count as spaces.
+fn parse_tag(inner: &str) -> Result<MergeTag, String> {
+    let inner = inner.split_whitespace().collect::<Vec<_>>().join(" ");
+    let (attribute, filter) = match inner.split_once('|') {
+        Some((attribute, filter)) => (attribute.trim(), Some(filter.trim())),
+        None => (inner.trim(), None),
+    };
+    let invalid = || format!("`{{{{ {} }}}}` is not a valid merge tag.", inner);
+    let attribute = attribute.strip_prefix(PREFIX).ok_or_else(invalid)?;
+    if attribute.is_empty()
+        || !attribute
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(invalid());
+    }
+    let default = match filter {
+        None => None,
+        Some(filter) => {
+            let value = filter
+                .strip_prefix("default")
+                .map(str::trim_start)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .map(str::trim)
+                .ok_or_else(invalid)?;
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'');
+            match quote {
+                Some(quote) if value.len() >= 2 && value.ends_with(quote) => {
+                    Some(value[1..value.len() - 1].to_owned())
+                }
+                _ => return Err(invalid()),
+            }
+        }
+    };
+    Ok(MergeTag {
+        attribute: attribute.to_owned(),
+        default,
+    })
+}
+
+/// Check the merge tags of an issue before it is saved, so that typos are
+/// caught before anything is sent.
+pub fn check_merge_tags(markdown: &str, schema: &AttributeSchema) -> Result<(), String> {
+    for (_, inner) in find_tags(markdown) {
+        let tag = parse_tag(inner)?;
+        if schema.get(&tag.attribute).is_none() {
+            return Err(format!(
+                "`{}` in a merge tag is not a known attribute.",
+                tag.attribute
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Wrap the merge tags of a Tera template in `raw` blocks, so that Tera
+/// outputs them as they are instead of failing to parse them.
+pub(crate) fn protect_merge_tags(template: &str) -> Result<String, String> {
+    let mut protected = String::with_capacity(template.len());
+    let mut last = 0;
+    for (range, inner) in find_tags(template) {
+        parse_tag(inner)?;
+        protected.push_str(&template[last..range.start]);
+        protected.push_str("{% raw %}");
+        protected.push_str(&template[range.clone()]);
+        protected.push_str("{% endraw %}");
+        last = range.end;
+    }
+    protected.push_str(&template[last..]);
+    Ok(protected)
+}
+
+fn display(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", f as i64),
+            _ => n.to_string(),
+        }),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Replace the merge tags of `content` with the recipient's attributes.
+/// Missing attributes fall back to the tag's default, or to nothing.
+/// Malformed tags are left as they are.
+pub fn fill_merge_tags(content: &str, attributes: &Attributes, format: Format) -> String {
+    let mut filled = String::with_capacity(content.len());
+    let mut last = 0;
+    for (range, inner) in find_tags(content) {
+        let tag = match format {
+            Format::Html => parse_tag(&decode_entities(inner)),
+            Format::Text => parse_tag(inner),
+        };
+        let Ok(tag) = tag else {
+            continue;
+        };
+        let value = attributes
+            .get(&tag.attribute)
+            .and_then(display)
+            .or(tag.default)
+            .unwrap_or_default();
+        filled.push_str(&content[last..range.start]);
+        match format {
+            Format::Html => filled.push_str(&escape_html(&value)),
+            Format::Text => filled.push_str(&value),
+        }
+        last = range.end;
+    }
+    filled.push_str(&content[last..]);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attributes::{AttributeDefinition, AttributeSchema, AttributeType, Attributes};
+    use crate::issue_rendering::markdown_to_html;
+    use crate::merge_tags::{check_merge_tags, fill_merge_tags, protect_merge_tags, Format};
+    use claims::{assert_err, assert_ok};
+
+    fn attributes(value: serde_json::Value) -> Attributes {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn tags_are_replaced_by_attributes_or_their_default() {
+        let content = r#"Hi {{ attributes.company | default: "there" }}, you have
+{{attributes.seats}} seats{{ attributes.plan }}. {{ unsubscribe_link }}"#;
+
+        let filled = fill_merge_tags(
+            content,
+            &attributes(serde_json::json!({ "seats": 12.0 })),
+            Format::Text,
+        );
+
+        assert_eq!(
+            filled,
+            "Hi there, you have\n12 seats. {{ unsubscribe_link }}"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let html = markdown_to_html(r#"Hi {{ attributes.company | default: 'you & yours' }}!"#);
+
+        let with_value = fill_merge_tags(
+            &html,
+            &attributes(serde_json::json!({ "company": "<b>Acme</b>" })),
+            Format::Html,
+        );
+        let with_default = fill_merge_tags(&html, &Attributes::new(), Format::Html);
+
+        assert!(
+            with_value.contains("<p>Hi &lt;b&gt;Acme&lt;/b&gt;!</p>"),
+            "{}",
+            with_value
+        );
+        assert!(
+            with_default.contains("<p>Hi you &amp; yours!</p>"),
+            "{}",
+            with_default
+        );
+    }
+
+    #[test]
+    fn tags_survive_line_wrapping() {
+        let filled = fill_merge_tags(
+            "Hi {{ attributes.company |\ndefault: \"there\" }}",
+            &Attributes::new(),
+            Format::Text,
+        );
+
+        assert_eq!(filled, "Hi there");
+    }
+
+    #[test]
+    fn unknown_attributes_and_malformed_tags_are_reported() {
+        let schema = AttributeSchema::new(vec![AttributeDefinition {
+            name: "company".into(),
+            attribute_type: AttributeType::String,
+            required: false,
+        }]);
+
+        assert_ok!(check_merge_tags(
+            r#"{{ attributes.company | default: "there" }} {{ unsubscribe_link }}"#,
+            &schema
+        ));
+        assert_err!(check_merge_tags("{{ attributes.plan }}", &schema));
+        assert_err!(check_merge_tags("{{ attributes. }}", &schema));
+        assert_err!(check_merge_tags(
+            "{{ attributes.company | upcase }}",
+            &schema
+        ));
+        assert_err!(check_merge_tags(
+            "{{ attributes.company | default: there }}",
+            &schema
+        ));
+    }
+
+    #[test]
+    fn tags_in_templates_are_kept_out_of_tera() {
+        let template = r#"Hi {{ attributes.company | default: "there" }}, {{ name }}"#;
+
+        let protected = protect_merge_tags(template).unwrap();
+
+        assert_eq!(
+            protected,
+            r#"Hi {% raw %}{{ attributes.company | default: "there" }}{% endraw %}, {{ name }}"#
+        );
+        assert_err!(protect_merge_tags("{{ attributes.company | upcase }}"));
+    }
+}
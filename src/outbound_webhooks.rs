@@ -0,0 +1,429 @@
+//! Tell external systems (such as our CRM) when subscribers sign up,
+//! confirm, unsubscribe or bounce.
+//!
+//! Events are queued in the same transaction as the status change they
+//! describe, one delivery per interested endpoint, and a background task
+//! sends them. Failed deliveries are retried with exponential backoff.
+//!
+//! Each request carries a `Webhook-Signature: t=<unix time>,v1=<hex>`
+//! header, where `v1` is the HMAC-SHA256 of `<unix time>.<body>` under the
+//! endpoint's secret. Receivers should recompute it and reject timestamps
+//! that are too old, so that captured requests cannot be replayed.
+//! Deliveries caused by a request to us carry its `X-Request-Id`.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::Sha256;
+use sqlx::postgres::PgListener;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::issue_delivery_worker::{idle_time, ExecutionOutcome};
+use crate::request_id::{current_request_id, REQUEST_ID_HEADER};
+use crate::startup::get_connection_pool;
+
+/// Notified whenever deliveries are queued, so that the dispatcher wakes up.
+pub const WEBHOOK_QUEUE_CHANNEL: &str = "webhook_delivery_queue";
+
+/// The version of the payload schema, sent in every payload and in the
+/// `Webhook-Version` header. It changes when fields are renamed, removed
+/// or change meaning; new fields can appear without notice.
+pub const PAYLOAD_VERSION: u32 = 1;
+
+pub const SIGNATURE_HEADER: &str = "Webhook-Signature";
+
+const FIRST_RETRY_DELAY_SECONDS: i64 = 30;
+const MAX_RETRY_DELAY_SECONDS: i64 = 6 * 60 * 60;
+
+/// How much longer than the request timeout a claimed delivery is hidden
+/// from other dispatchers, so that the outcome can be recorded in time.
+/// Should the dispatcher die in the meantime, the delivery is attempted
+/// again once the claim expires.
+const CLAIM_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEventType {
+    #[serde(rename = "subscriber.created")]
+    SubscriberCreated,
+    #[serde(rename = "subscriber.confirmed")]
+    SubscriberConfirmed,
+    /// Also sent for spam complaints: either way, they are gone.
+    #[serde(rename = "subscriber.unsubscribed")]
+    SubscriberUnsubscribed,
+    #[serde(rename = "subscriber.bounced")]
+    SubscriberBounced,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::SubscriberCreated => "subscriber.created",
+            WebhookEventType::SubscriberConfirmed => "subscriber.confirmed",
+            WebhookEventType::SubscriberUnsubscribed => "subscriber.unsubscribed",
+            WebhookEventType::SubscriberBounced => "subscriber.bounced",
+        }
+    }
+}
+
+/// Queue `event_type` about `subscriber_id` for every endpoint that asked
+/// for it. The payload describes the subscriber as of this transaction.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_webhook_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event_type: WebhookEventType,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let endpoints = sqlx::query!(
+        "SELECT endpoint_id FROM webhook_endpoints WHERE $1 = ANY(event_types)",
+        event_type.as_str(),
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to fetch the webhook endpoints.")?;
+    if endpoints.is_empty() {
+        return Ok(());
+    }
+    let subscriber = sqlx::query!(
+        "SELECT email, name, status FROM subscriptions WHERE id = $1",
+        subscriber_id,
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to fetch the subscriber.")?;
+    let now = Utc::now();
+    let request_id = current_request_id().map(|id| id.to_string());
+    let payload = serde_json::json!({
+        // The same for every endpoint, so that receivers can deduplicate.
+        "id": Uuid::new_v4(),
+        "type": event_type.as_str(),
+        "version": PAYLOAD_VERSION,
+        "created_at": now,
+        "data": {
+            "subscriber": {
+                "id": subscriber_id,
+                "email": subscriber.email,
+                "name": subscriber.name,
+                "status": subscriber.status,
+            },
+        },
+    });
+    for endpoint in endpoints {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_deliveries (
+                delivery_id,
+                endpoint_id,
+                event_type,
+                subscriber_id,
+                payload,
+                status,
+                next_attempt_at,
+                created_at,
+                request_id
+            )
+            VALUES ($1, $2, $3, $4, $5, 'pending', $6, $6, $7)
+            "#,
+            Uuid::new_v4(),
+            endpoint.endpoint_id,
+            event_type.as_str(),
+            subscriber_id,
+            payload,
+            now,
+            request_id,
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to queue the webhook delivery.")?;
+    }
+    sqlx::query!("SELECT pg_notify($1, '')", WEBHOOK_QUEUE_CHANNEL)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to notify the webhook dispatcher.")?;
+    Ok(())
+}
+
+/// The value of the signature header for `body`, sent at `timestamp`.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// How long to wait after the `attempts`-th failed attempt: 30 seconds,
+/// then twice as long every time, up to 6 hours.
+fn retry_delay(attempts: i16) -> chrono::Duration {
+    let doublings = (attempts.max(1) - 1).min(20) as u32;
+    let seconds = (FIRST_RETRY_DELAY_SECONDS << doublings).min(MAX_RETRY_DELAY_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+pub struct WebhookClient {
+    http_client: Client,
+    timeout: Duration,
+}
+
+impl WebhookClient {
+    pub fn new(timeout: Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            timeout,
+        }
+    }
+
+    /// How long a delivery is claimed for: longer than a request can take.
+    fn claim_duration(&self) -> Duration {
+        self.timeout + CLAIM_MARGIN
+    }
+
+    /// Returns the status of the response, whatever it is.
+    async fn post(
+        &self,
+        url: &str,
+        delivery_id: Uuid,
+        event_type: &str,
+        request_id: Option<&str>,
+        signature: String,
+        body: Vec<u8>,
+    ) -> Result<u16, reqwest::Error> {
+        let mut request = self
+            .http_client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("Webhook-Id", delivery_id.to_string())
+            .header("Webhook-Event", event_type)
+            .header("Webhook-Version", PAYLOAD_VERSION.to_string())
+            .header(SIGNATURE_HEADER, signature);
+        if let Some(request_id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, request_id);
+        }
+        let response = request.body(body).send().await?;
+        Ok(response.status().as_u16())
+    }
+}
+
+pub async fn run_webhook_dispatcher_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let client = configuration.webhooks.client();
+    dispatcher_loop(connection_pool, client, configuration.webhooks.max_attempts).await
+}
+
+async fn dispatcher_loop(
+    pool: PgPool,
+    client: WebhookClient,
+    max_attempts: i16,
+) -> Result<(), anyhow::Error> {
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(WEBHOOK_QUEUE_CHANNEL).await?;
+    loop {
+        match try_deliver_webhook(&pool, &client, max_attempts).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                let next_attempt_at = get_next_attempt_time(&pool).await.unwrap_or(None);
+                tokio::select! {
+                    _ = tokio::time::sleep(idle_time(next_attempt_at, Utc::now())) => {}
+                    notification = listener.recv() => {
+                        if notification.is_err() {
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_next_attempt_time(pool: &PgPool) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let r = sqlx::query!(
+        "SELECT MIN(next_attempt_at) AS next_attempt_at FROM webhook_deliveries WHERE status = 'pending'"
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(r.next_attempt_at)
+}
+
+/// Attempt the next due delivery, if any, and record how it went.
+///
+/// The delivery is claimed rather than locked while its request is in
+/// flight, so that several dispatchers never send it twice at once and a
+/// slow receiver ties up neither a connection nor an open transaction.
+#[tracing::instrument(
+    skip_all,
+    fields(delivery_id = tracing::field::Empty, event_type = tracing::field::Empty),
+    err
+)]
+pub async fn try_deliver_webhook(
+    pool: &PgPool,
+    client: &WebhookClient,
+    max_attempts: i16,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some(delivery) = claim_delivery(pool, client.claim_duration()).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    tracing::Span::current()
+        .record("delivery_id", tracing::field::display(delivery.delivery_id))
+        .record("event_type", tracing::field::display(&delivery.event_type));
+
+    let body = serde_json::to_vec(&delivery.payload)?;
+    let attempted_at = Utc::now();
+    let signature = sign_payload(&delivery.secret, attempted_at.timestamp(), &body);
+    let started = Instant::now();
+    let response = client
+        .post(
+            &delivery.url,
+            delivery.delivery_id,
+            &delivery.event_type,
+            delivery.request_id.as_deref(),
+            signature,
+            body,
+        )
+        .await;
+    let duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    let (response_status, error) = match response {
+        Ok(status) if (200..300).contains(&status) => (Some(status), None),
+        Ok(status) => (
+            Some(status),
+            Some(format!("The endpoint answered {}.", status)),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let attempt = delivery.attempts + 1;
+    let status = match &error {
+        None => "delivered",
+        Some(_) if attempt >= max_attempts => "failed",
+        Some(_) => "pending",
+    };
+    if let Some(error) = &error {
+        tracing::warn!(attempt, error = %error, "Failed to deliver a webhook.");
+    }
+
+    let mut transaction = pool.begin().await?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = $2, attempts = $3, next_attempt_at = $4
+        WHERE delivery_id = $1
+        "#,
+        delivery.delivery_id,
+        status,
+        attempt,
+        Utc::now() + retry_delay(attempt),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // The endpoint was deleted, along with its deliveries, in the meantime.
+    if updated.rows_affected() == 0 {
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_delivery_attempts (
+            delivery_id, attempt, attempted_at, response_status, error, duration_ms
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        delivery.delivery_id,
+        attempt,
+        attempted_at,
+        response_status.map(|s| s as i16),
+        error,
+        duration_ms,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct ClaimedDelivery {
+    delivery_id: Uuid,
+    event_type: String,
+    payload: serde_json::Value,
+    /// Earlier, failed, attempts.
+    attempts: i16,
+    request_id: Option<String>,
+    url: String,
+    secret: String,
+}
+
+/// Claim the next due delivery for `claim_duration`, by pushing its next
+/// attempt back, and return it along with where it goes.
+#[tracing::instrument(skip_all)]
+async fn claim_delivery(
+    pool: &PgPool,
+    claim_duration: Duration,
+) -> Result<Option<ClaimedDelivery>, anyhow::Error> {
+    let delivery = sqlx::query_as!(
+        ClaimedDelivery,
+        r#"
+        UPDATE webhook_deliveries d
+        SET next_attempt_at = now() + make_interval(secs => $1)
+        FROM (
+            SELECT delivery_id
+            FROM webhook_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= now()
+            ORDER BY next_attempt_at
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        ) due, webhook_endpoints e
+        WHERE d.delivery_id = due.delivery_id AND e.endpoint_id = d.endpoint_id
+        RETURNING
+            d.delivery_id AS "delivery_id!",
+            d.event_type AS "event_type!",
+            d.payload AS "payload!",
+            d.attempts AS "attempts!",
+            d.request_id,
+            e.url AS "url!",
+            e.secret AS "secret!"
+        "#,
+        claim_duration.as_secs_f64(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to claim a webhook delivery.")?;
+    Ok(delivery)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outbound_webhooks::{retry_delay, sign_payload};
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_a_limit() {
+        let delays: Vec<_> = [1, 2, 3, 10, 11, 100]
+            .into_iter()
+            .map(|attempts| retry_delay(attempts).num_seconds())
+            .collect();
+
+        assert_eq!(delays, vec![30, 60, 120, 15360, 21600, 21600]);
+    }
+
+    #[test]
+    fn signatures_cover_the_timestamp_and_the_body() {
+        let signature = sign_payload("secret", 1700000000, b"{}");
+
+        assert!(signature.starts_with("t=1700000000,v1="));
+        assert_eq!(signature.len(), "t=1700000000,v1=".len() + 64);
+        assert_ne!(signature, sign_payload("secret", 1700000001, b"{}"));
+        assert_ne!(signature, sign_payload("secret", 1700000000, b"[]"));
+        assert_ne!(signature, sign_payload("other secret", 1700000000, b"{}"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Send the password reset links requested through `/password_reset`.
+//!
+//! The request handler only queues the requests, whether or not the address
+//! is an admin's: neither how long it takes to answer nor a failure to send
+//! can then give away who the admins are.
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::{idle_time, ExecutionOutcome};
+use crate::startup::get_connection_pool;
+use crate::utils::{generate_token, hash_token};
+
+/// Notified whenever a request is queued, so that the worker wakes up.
+pub const PASSWORD_RESET_QUEUE_CHANNEL: &str = "password_reset_queue";
+
+const PASSWORD_RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+/// Requests that waited longer than this are not sent anymore: whoever
+/// asked has given up, or asked again.
+const MAX_SEND_DELAY_MINUTES: i32 = 15;
+
+/// How long requests are kept, to throttle the next ones.
+const RETENTION_HOURS: i32 = 24;
+
+pub async fn run_password_reset_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let templates =
+        EmailTemplates::load(Path::new(&configuration.application.templates_directory))?;
+    worker_loop(
+        connection_pool,
+        email_client,
+        templates,
+        configuration.application.base_url,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    templates: EmailTemplates,
+    base_url: String,
+) -> Result<(), anyhow::Error> {
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(PASSWORD_RESET_QUEUE_CHANNEL).await?;
+    loop {
+        match try_send_password_reset_email(&pool, &email_client, &templates, &base_url).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(idle_time(None, Utc::now())) => {}
+                    notification = listener.recv() => {
+                        if notification.is_err() {
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Email a reset link for the oldest request from an admin's address.
+///
+/// The request is marked as processed before anything is sent, so that a
+/// failure is reported once rather than retried.
+#[tracing::instrument(skip_all, fields(user_id = tracing::field::Empty), err)]
+pub async fn try_send_password_reset_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let request = sqlx::query!(
+        r#"
+        UPDATE password_reset_requests SET processed_at = now()
+        WHERE password_reset_request_id = (
+            SELECT password_reset_request_id
+            FROM password_reset_requests
+            WHERE user_id IS NOT NULL
+                AND processed_at IS NULL
+                AND requested_at > now() - make_interval(mins => $1)
+            ORDER BY requested_at
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        )
+        RETURNING user_id AS "user_id!", email
+        "#,
+        MAX_SEND_DELAY_MINUTES,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to claim a password reset request.")?;
+    let Some(request) = request else {
+        delete_old_requests(pool).await?;
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("user_id", display(request.user_id));
+    let recipient = SubscriberEmail::parse(request.email).map_err(anyhow::Error::msg)?;
+    let token = generate_token();
+    store_password_reset_token(pool, request.user_id, &token)
+        .await
+        .context("Failed to store the password reset token.")?;
+    send_password_reset_email(email_client, templates, recipient, base_url, &token)
+        .await
+        .context("Failed to send the password reset email.")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_old_requests(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM password_reset_requests
+        WHERE requested_at < now() - make_interval(hours => $1)
+        "#,
+        RETENTION_HOURS,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete the old password reset requests.")?;
+    Ok(())
+}
+
+async fn store_password_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_token(token),
+        user_id,
+        now,
+        now + chrono::Duration::minutes(PASSWORD_RESET_TOKEN_TTL_MINUTES),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    recipient: SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let email = templates.render(
+        "password_reset",
+        &serde_json::json!({
+            "reset_link": format!("{}/password_reset/confirm?token={}", base_url, token),
+            "expires_in_minutes": PASSWORD_RESET_TOKEN_TTL_MINUTES,
+        }),
+    )?;
+    email_client
+        .send_email(
+            recipient,
+            "Reset your password",
+            &email.html_body,
+            &email.text_body,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! One id per request, shared by the logs, the response and the calls we
+//! make on its behalf, so that a report from a user, a line in our logs
+//! and a message at Postmark can be tied together.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use uuid::Uuid;
+
+use crate::consent::client_ip_address;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longer ids are replaced rather than logged.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Keep an inbound id if it is safe to log and to send on.
+    fn parse(s: &str) -> Option<Self> {
+        let valid = (1..=MAX_REQUEST_ID_LENGTH).contains(&s.len())
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        valid.then(|| Self(s.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Whether the `X-Request-Id` of incoming requests can be kept. Only when a
+/// proxy in front of us sets it, or strips it from what clients send.
+#[derive(Clone, Copy)]
+pub struct RequestIdPolicy {
+    pub trust_header: bool,
+}
+
+fn choose_request_id(header: Option<&str>, trust_header: bool) -> RequestId {
+    header
+        .filter(|_| trust_header)
+        .and_then(RequestId::parse)
+        .unwrap_or_else(RequestId::generate)
+}
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// The id of the request being handled, if any: background workers have
+/// none.
+pub fn current_request_id() -> Option<RequestId> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Assign the request its id and return it in the response. Wraps every
+/// other middleware, so that `TracingLogger` finds the id for the root span
+/// and the outgoing calls made while handling the request find it too.
+pub async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let trust_header = req
+        .app_data::<web::Data<RequestIdPolicy>>()
+        .is_some_and(|policy| policy.trust_header);
+    let header = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let request_id = choose_request_id(header, trust_header);
+    req.extensions_mut().insert(request_id.clone());
+    // No clone of the request may outlive this point: routing needs to be
+    // its only owner.
+    match CURRENT_REQUEST_ID
+        .scope(request_id.clone(), next.call(req))
+        .await
+    {
+        Ok(response) => {
+            let mut response = response.map_into_boxed_body();
+            insert_request_id_header(response.headers_mut(), &request_id);
+            Ok(response)
+        }
+        // Turned into a response further out, so hand over one that
+        // already carries the id.
+        Err(e) => {
+            let mut response = e.error_response();
+            insert_request_id_header(response.headers_mut(), &request_id);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+fn insert_request_id_header(headers: &mut HeaderMap, request_id: &RequestId) {
+    headers.insert(
+        HeaderName::from_static(REQUEST_ID_HEADER),
+        HeaderValue::from_str(request_id.as_str()).expect("Request ids are valid header values"),
+    );
+}
+
+/// The root span of `TracingLogger`, with our request id rather than its
+/// own.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.to_string())
+            .unwrap_or_default();
+        let user_agent = request
+            .headers()
+            .get("User-Agent")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("")
+            .to_owned();
+        let client_ip = client_ip_address(request.request()).unwrap_or_default();
+        tracing::info_span!(
+            "HTTP request",
+            http.method = %request.method(),
+            http.route = %request.match_pattern().unwrap_or_default(),
+            http.target = %request.uri(),
+            http.client_ip = %client_ip,
+            http.user_agent = %user_agent,
+            http.status_code = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+            request_id = %request_id,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+        )
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose_request_id, RequestId};
+
+    #[test]
+    fn trusted_ids_are_kept() {
+        let id = choose_request_id(Some("edge-4f1c:0001"), true);
+
+        assert_eq!(id.as_str(), "edge-4f1c:0001");
+    }
+
+    #[test]
+    fn untrusted_ids_are_replaced() {
+        let id = choose_request_id(Some("edge-4f1c:0001"), false);
+
+        assert_ne!(id.as_str(), "edge-4f1c:0001");
+        assert!(uuid::Uuid::parse_str(id.as_str()).is_ok());
+    }
+
+    #[test]
+    fn ids_that_are_unsafe_to_log_are_replaced() {
+        let too_long = "a".repeat(129);
+        for header in ["", "two words", "line\nbreak", "ünïcode", too_long.as_str()] {
+            assert!(RequestId::parse(header).is_none(), "{:?} was kept", header);
+            assert_ne!(choose_request_id(Some(header), true).as_str(), header);
+        }
+    }
+
+    #[test]
+    fn missing_ids_are_generated() {
+        let first = choose_request_id(None, true);
+        let second = choose_request_id(None, true);
+
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,208 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::ab_testing::{WinnerMetric, MAX_VARIANTS};
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::utils::{e400, e500};
+use super::issues::{check_content_merge_tags, get_issue_for_update};
+
+#[derive(serde::Deserialize)]
+pub struct AbTestRequest {
+    variants: Vec<VariantDraft>,
+    /// The share of the audience that receives each variant, e.g. `0.1`.
+    sample_fraction: f64,
+    /// How long to wait for opens and clicks before choosing the winner.
+    wait_minutes: i32,
+    metric: WinnerMetric,
+}
+
+/// Leave out the subject or the content to use the issue's own.
+#[derive(serde::Deserialize)]
+pub struct VariantDraft {
+    subject: Option<String>,
+    content: Option<String>,
+}
+
+impl AbTestRequest {
+    fn validate(&self) -> Result<(), String> {
+        if !(2..=MAX_VARIANTS).contains(&self.variants.len()) {
+            return Err(format!(
+                "An A/B test needs between 2 and {} variants.",
+                MAX_VARIANTS
+            ));
+        }
+        for variant in &self.variants {
+            if let Some(subject) = &variant.subject {
+                if subject.trim().is_empty() || subject.contains(['\r', '\n']) {
+                    return Err("The subject of a variant must be a single, non-empty line.".into());
+                }
+            }
+            if variant.content.as_ref().is_some_and(|c| c.trim().is_empty()) {
+                return Err("The content of a variant cannot be empty.".into());
+            }
+        }
+        let sampled = self.sample_fraction * self.variants.len() as f64;
+        if !(self.sample_fraction > 0.0 && sampled <= 1.0) {
+            return Err("The samples of all variants must fit in the audience.".into());
+        }
+        if self.wait_minutes <= 0 {
+            return Err("The wait before choosing a winner must be positive.".into());
+        }
+        Ok(())
+    }
+
+    fn audit_state(&self) -> serde_json::Value {
+        let variants: Vec<_> = self
+            .variants
+            .iter()
+            .map(|v| serde_json::json!({ "subject": v.subject, "content": v.content }))
+            .collect();
+        serde_json::json!({
+            "variants": variants,
+            "sample_fraction": self.sample_fraction,
+            "wait_minutes": self.wait_minutes,
+            "metric": self.metric.as_str(),
+        })
+    }
+}
+
+/// Set up (or replace) the A/B test of an issue that has not been sent yet.
+#[tracing::instrument(
+    name = "Configure the A/B test of a newsletter issue",
+    skip(request, pool, http_request)
+)]
+pub async fn configure_ab_test(
+    newsletter_issue_id: web::Path<Uuid>,
+    request: web::Json<AbTestRequest>,
+    pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    request.validate().map_err(e400)?;
+    for content in request.variants.iter().filter_map(|v| v.content.as_deref()) {
+        check_content_merge_tags(&pool, content).await?;
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if issue.has_started_sending() {
+        return Ok(HttpResponse::Conflict().body("The issue has already been published."));
+    }
+    delete_ab_test(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to delete the previous A/B test.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_ab_tests (newsletter_issue_id, sample_fraction, wait_minutes, metric)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        newsletter_issue_id,
+        request.sample_fraction,
+        request.wait_minutes,
+        request.metric.as_str(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the A/B test.")
+    .map_err(e500)?;
+    for (variant, draft) in request.variants.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_variants (newsletter_issue_id, variant, subject, markdown_content)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            newsletter_issue_id,
+            variant as i16,
+            draft.subject,
+            draft.content,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a variant.")
+        .map_err(e500)?;
+    }
+    let event = AuditEvent::new("ab_test.configure", "newsletter_issue", newsletter_issue_id)
+        .after(request.audit_state());
+    record_audit_event(&mut *transaction, &http_request, event)
+        .await
+        .context("Failed to record the A/B test in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the A/B test.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "Remove the A/B test of a newsletter issue",
+    skip(pool, request)
+)]
+pub async fn remove_ab_test(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if issue.has_started_sending() {
+        return Ok(HttpResponse::Conflict().body("The issue has already been published."));
+    }
+    delete_ab_test(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to delete the A/B test.")
+        .map_err(e500)?;
+    let event = AuditEvent::new("ab_test.remove", "newsletter_issue", newsletter_issue_id);
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the removal in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the removal of the A/B test.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn delete_ab_test(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM issue_variants WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM issue_ab_tests WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
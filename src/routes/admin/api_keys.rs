@@ -0,0 +1,165 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::authentication::{NewApiKey, Scope, StoredApiKey, UserId};
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize)]
+pub struct ApiKeyDraft {
+    /// Tells people what the key is for, e.g. `CRM sync`.
+    name: String,
+    scopes: Vec<Scope>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyDraft {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("The name of an API key cannot be empty.".into());
+        }
+        if self.scopes.is_empty() {
+            return Err("An API key needs at least one scope.".into());
+        }
+        if self.expires_at.is_some_and(|e| e <= Utc::now()) {
+            return Err("An API key cannot expire in the past.".into());
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "List API keys", skip(pool))]
+pub async fn list_api_keys(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let keys = sqlx::query_as!(
+        StoredApiKey,
+        r#"
+        SELECT
+            api_key_id,
+            name,
+            prefix,
+            scopes,
+            created_at,
+            expires_at,
+            last_used_at,
+            revoked_at
+        FROM api_keys
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the API keys.")
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// The key itself is only ever shown in the response to this request.
+#[tracing::instrument(
+    name = "Create an API key",
+    skip(draft, pool, request),
+    fields(user_id = %*user_id)
+)]
+pub async fn create_api_key(
+    draft: web::Json<ApiKeyDraft>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    draft.validate().map_err(e400)?;
+    let new_key = NewApiKey::generate();
+    let api_key_id = Uuid::new_v4();
+    let mut scopes: Vec<&str> = draft.scopes.iter().map(|s| s.as_str()).collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO api_keys (
+            api_key_id, name, prefix, key_hash, scopes, created_by, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        api_key_id,
+        draft.name.trim(),
+        new_key.prefix,
+        new_key.key_hash,
+        &scopes as &[&str],
+        **user_id,
+        Utc::now(),
+        draft.expires_at,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the API key.")
+    .map_err(e500)?;
+    let event = AuditEvent::new("api_key.create", "api_key", api_key_id).after(serde_json::json!({
+        "name": draft.name.trim(),
+        "prefix": new_key.prefix,
+        "scopes": scopes,
+        "expires_at": draft.expires_at,
+    }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the new API key in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new API key.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "api_key_id": api_key_id,
+        "prefix": new_key.prefix,
+        "key": new_key.key,
+    })))
+}
+
+/// Revoked keys stay listed, so that their use can still be traced.
+#[tracing::instrument(name = "Revoke an API key", skip(pool, request))]
+pub async fn revoke_api_key(
+    api_key_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let api_key_id = api_key_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let key = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = COALESCE(revoked_at, $2)
+        WHERE api_key_id = $1
+        RETURNING revoked_at AS "revoked_at!"
+        "#,
+        api_key_id,
+        Utc::now(),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to revoke the API key.")
+    .map_err(e500)?;
+    let Some(key) = key else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let event = AuditEvent::new("api_key.revoke", "api_key", api_key_id)
+        .after(serde_json::json!({ "revoked_at": key.revoked_at }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the revocation in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the revocation.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,152 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::attributes::{get_attribute_schema, validate_attribute_name, AttributeType};
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize)]
+pub struct AttributeDefinitionDraft {
+    #[serde(rename = "type")]
+    attribute_type: AttributeType,
+    /// Only checked when attributes are written: existing subscribers keep
+    /// what they have.
+    #[serde(default)]
+    required: bool,
+}
+
+#[tracing::instrument(name = "List attribute definitions", skip(pool))]
+pub async fn list_attributes(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let schema = get_attribute_schema(pool.get_ref())
+        .await
+        .context("Failed to fetch the attribute schema.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(schema.definitions()))
+}
+
+/// Create or change the definition of an attribute. The type of an
+/// attribute cannot change while subscribers have a value for it.
+#[tracing::instrument(name = "Define an attribute", skip(draft, pool, request))]
+pub async fn define_attribute(
+    name: web::Path<String>,
+    draft: web::Json<AttributeDefinitionDraft>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = name.into_inner();
+    validate_attribute_name(&name).map_err(e400)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let current = sqlx::query!(
+        "SELECT attribute_type, required FROM attribute_definitions WHERE name = $1 FOR UPDATE",
+        name,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the attribute definition.")
+    .map_err(e500)?;
+    let changes_type = current
+        .as_ref()
+        .is_some_and(|c| c.attribute_type != draft.attribute_type.as_str());
+    if changes_type {
+        let in_use = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE attributes ? $1) AS "in_use!""#,
+            name,
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to check whether the attribute is in use.")
+        .map_err(e500)?
+        .in_use;
+        if in_use {
+            return Ok(HttpResponse::Conflict()
+                .body("Subscribers have a value for this attribute: its type cannot change."));
+        }
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO attribute_definitions (name, attribute_type, required, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO UPDATE
+        SET attribute_type = EXCLUDED.attribute_type, required = EXCLUDED.required
+        "#,
+        name,
+        draft.attribute_type.as_str(),
+        draft.required,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the attribute definition.")
+    .map_err(e500)?;
+    let mut event = AuditEvent::new("attribute.define", "attribute", &name).after(
+        serde_json::json!({ "type": draft.attribute_type.as_str(), "required": draft.required }),
+    );
+    if let Some(current) = current {
+        event = event.before(
+            serde_json::json!({ "type": current.attribute_type, "required": current.required }),
+        );
+    }
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the attribute definition in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the attribute definition.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Removing an attribute also removes its values from every subscriber.
+#[tracing::instrument(name = "Delete an attribute", skip(pool, request))]
+pub async fn delete_attribute(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = name.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let deleted = sqlx::query!(
+        "DELETE FROM attribute_definitions WHERE name = $1 RETURNING attribute_type, required",
+        name,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete the attribute definition.")
+    .map_err(e500)?;
+    let Some(deleted) = deleted else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    sqlx::query!(
+        "UPDATE subscriptions SET attributes = attributes - $1 WHERE attributes ? $1",
+        name,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the attribute from subscribers.")
+    .map_err(e500)?;
+    let event = AuditEvent::new("attribute.delete", "attribute", &name).before(
+        serde_json::json!({ "type": deleted.attribute_type, "required": deleted.required }),
+    );
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the deletion in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the deletion of the attribute.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e400, e500};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Every filter is optional; events come most recent first.
+#[derive(serde::Deserialize, Debug)]
+pub struct AuditLogQuery {
+    actor_user_id: Option<Uuid>,
+    actor_api_key_id: Option<Uuid>,
+    action: Option<String>,
+    target_type: Option<String>,
+    target_id: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    /// For the next page: the `audit_event_id` of the last event received.
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct AuditLogEntry {
+    audit_event_id: i64,
+    occurred_at: DateTime<Utc>,
+    actor_user_id: Option<Uuid>,
+    actor_api_key_id: Option<Uuid>,
+    action: String,
+    target_type: String,
+    target_id: String,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+    ip_address: Option<String>,
+    request_id: Option<String>,
+}
+
+#[tracing::instrument(name = "List audit events", skip(pool))]
+pub async fn list_audit_log(
+    query: web::Query<AuditLogQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(e400(format!(
+            "`limit` must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let events = sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT
+            audit_event_id,
+            occurred_at,
+            actor_user_id,
+            actor_api_key_id,
+            action,
+            target_type,
+            target_id,
+            before_state AS before,
+            after_state AS after,
+            ip_address,
+            request_id
+        FROM audit_log
+        WHERE ($1::UUID IS NULL OR actor_user_id = $1)
+            AND ($2::UUID IS NULL OR actor_api_key_id = $2)
+            AND ($3::TEXT IS NULL OR action = $3)
+            AND ($4::TEXT IS NULL OR target_type = $4)
+            AND ($5::TEXT IS NULL OR target_id = $5)
+            AND ($6::TIMESTAMPTZ IS NULL OR occurred_at >= $6)
+            AND ($7::TIMESTAMPTZ IS NULL OR occurred_at < $7)
+            AND ($8::BIGINT IS NULL OR audit_event_id < $8)
+        ORDER BY audit_event_id DESC
+        LIMIT $9
+        "#,
+        query.actor_user_id,
+        query.actor_api_key_id,
+        query.action,
+        query.target_type,
+        query.target_id,
+        query.since,
+        query.until,
+        query.before_id,
+        limit,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the audit log.")
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(events))
+}
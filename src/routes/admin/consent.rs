@@ -0,0 +1,31 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{Authorized, SubscribersRead};
+use crate::consent::get_consent_history;
+use crate::utils::e500;
+
+#[tracing::instrument(name = "Fetch the consent history of a subscriber", skip(pool))]
+pub async fn subscriber_consent_history(
+    _: Authorized<SubscribersRead>,
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let exists = sqlx::query!("SELECT id FROM subscriptions WHERE id = $1", subscriber_id)
+        .fetch_optional(pool.get_ref())
+        .await
+        .context("Failed to look up the subscriber.")
+        .map_err(e500)?
+        .is_some();
+    if !exists {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let history = get_consent_history(&pool, subscriber_id)
+        .await
+        .context("Failed to fetch the consent history.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(history))
+}
@@ -0,0 +1,133 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::authentication::{Role, UserId};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_templates::EmailTemplates;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e400, e500, generate_token, hash_token};
+
+const INVITATION_TTL_HOURS: i64 = 72;
+
+#[derive(serde::Deserialize)]
+pub struct InvitationRequest {
+    email: String,
+    role: Role,
+}
+
+/// Invite somebody to become an admin, with `role`. The invitation is sent
+/// by email; whoever follows the link picks a username and a password.
+#[tracing::instrument(
+    name = "Invite an admin",
+    skip(request, pool, email_client, templates, base_url, http_request),
+    fields(user_id = %*user_id)
+)]
+pub async fn invite_admin(
+    request: web::Json<InvitationRequest>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InvitationRequest { email, role } = request.into_inner();
+    let email = SubscriberEmail::parse(email).map_err(e400)?;
+    let existing = sqlx::query!("SELECT user_id FROM users WHERE email = $1", email.as_ref())
+        .fetch_optional(pool.get_ref())
+        .await
+        .context("Failed to look up the admins by email.")
+        .map_err(e500)?;
+    if existing.is_some() {
+        return Ok(
+            HttpResponse::Conflict().body("An admin with this email address already exists.")
+        );
+    }
+    let invitation_id = Uuid::new_v4();
+    let token = generate_token();
+    let expires_at = store_invitation(&pool, invitation_id, &email, role, &token, **user_id)
+        .await
+        .context("Failed to store the invitation.")
+        .map_err(e500)?;
+    let event = AuditEvent::new("admin.invite", "admin_invitation", invitation_id).after(
+        serde_json::json!({
+            "email": email.as_ref(),
+            "role": role.as_str(),
+            "expires_at": expires_at,
+        }),
+    );
+    record_audit_event(pool.get_ref(), &http_request, event)
+        .await
+        .context("Failed to record the invitation in the audit log.")
+        .map_err(e500)?;
+    send_invitation_email(&email_client, &templates, email, role, &base_url.0, &token)
+        .await
+        .context("Failed to send the invitation email.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "invitation_id": invitation_id,
+        "expires_at": expires_at,
+    })))
+}
+
+async fn store_invitation(
+    pool: &PgPool,
+    invitation_id: Uuid,
+    email: &SubscriberEmail,
+    role: Role,
+    token: &str,
+    invited_by: Uuid,
+) -> Result<DateTime<Utc>, sqlx::Error> {
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::hours(INVITATION_TTL_HOURS);
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_invitations (
+            invitation_id, email, role, token_hash, invited_by, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        invitation_id,
+        email.as_ref(),
+        role.as_str(),
+        hash_token(token),
+        invited_by,
+        now,
+        expires_at,
+    )
+    .execute(pool)
+    .await?;
+    Ok(expires_at)
+}
+
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    recipient: SubscriberEmail,
+    role: Role,
+    base_url: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let email = templates.render(
+        "admin_invitation",
+        &serde_json::json!({
+            "invitation_link": format!("{}/invitations/accept?token={}", base_url, token),
+            "role": role.as_str(),
+            "expires_in_hours": INVITATION_TTL_HOURS,
+        }),
+    )?;
+    email_client
+        .send_email(
+            recipient,
+            "Your invitation",
+            &email.html_body,
+            &email.text_body,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,801 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::offset::LocalResult;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::ab_testing::{get_variant_results, VariantResult};
+use crate::attributes::get_attribute_schema;
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::authentication::{Authorized, IssuesPublish, IssuesWrite};
+use crate::configuration::DeliverySettings;
+use crate::email_templates::EmailTemplates;
+use crate::issue_rendering::{fill_default_merge_tags, render_issue};
+use crate::issue_scheduler::start_sending;
+use crate::merge_tags::check_merge_tags;
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize)]
+pub struct IssueDraft {
+    title: String,
+    /// Markdown source of the issue.
+    content: String,
+    /// Whether to record opens and clicks of this issue.
+    #[serde(default)]
+    tracking: bool,
+    /// Keep the issue out of the public archive once published.
+    #[serde(default)]
+    private: bool,
+    /// Send the issue to this segment only, rather than to every confirmed
+    /// subscriber.
+    segment_id: Option<Uuid>,
+}
+
+impl IssueDraft {
+    fn validate(&self) -> Result<(), String> {
+        if self.title.trim().is_empty() {
+            return Err("The title of an issue cannot be empty.".into());
+        }
+        // The title becomes the subject of the email.
+        if self.title.contains(['\r', '\n']) {
+            return Err("The title of an issue cannot span several lines.".into());
+        }
+        if self.content.trim().is_empty() {
+            return Err("The content of an issue cannot be empty.".into());
+        }
+        Ok(())
+    }
+
+    fn audit_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "title": self.title,
+            "markdown_content": self.content,
+            "tracking_enabled": self.tracking,
+            "private": self.private,
+            "segment_id": self.segment_id,
+        })
+    }
+}
+
+/// Reject drafts addressed to a segment that does not exist.
+async fn check_segment(pool: &PgPool, segment_id: Option<Uuid>) -> Result<(), actix_web::Error> {
+    let Some(segment_id) = segment_id else {
+        return Ok(());
+    };
+    let exists = sqlx::query!("SELECT segment_id FROM segments WHERE segment_id = $1", segment_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up the segment.")
+        .map_err(e500)?
+        .is_some();
+    if !exists {
+        return Err(e400("The segment does not exist."));
+    }
+    Ok(())
+}
+
+/// Reject merge tags that are malformed or refer to unknown attributes.
+pub(super) async fn check_content_merge_tags(
+    pool: &PgPool,
+    markdown: &str,
+) -> Result<(), actix_web::Error> {
+    let schema = get_attribute_schema(pool)
+        .await
+        .context("Failed to fetch the attribute schema.")
+        .map_err(e500)?;
+    check_merge_tags(markdown, &schema).map_err(e400)
+}
+
+#[derive(serde::Serialize)]
+struct IssuePreview {
+    html_content: String,
+    text_content: String,
+}
+
+pub(super) struct StoredIssue {
+    title: String,
+    markdown_content: String,
+    status: String,
+    tracking_enabled: bool,
+    private: bool,
+    segment_id: Option<Uuid>,
+}
+
+impl StoredIssue {
+    /// Drafts, scheduled issues and those that failed to start can still be
+    /// edited, scheduled or sent.
+    pub(super) fn has_started_sending(&self) -> bool {
+        !matches!(self.status.as_str(), "draft" | "scheduled" | "failed")
+    }
+
+    fn audit_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "title": self.title,
+            "markdown_content": self.markdown_content,
+            "tracking_enabled": self.tracking_enabled,
+            "private": self.private,
+            "segment_id": self.segment_id,
+        })
+    }
+}
+
+#[tracing::instrument(name = "Create a newsletter issue", skip(draft, pool, request))]
+pub async fn create_issue(
+    _: Authorized<IssuesWrite>,
+    draft: web::Json<IssueDraft>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    draft.validate().map_err(e400)?;
+    check_segment(&pool, draft.segment_id).await?;
+    check_content_merge_tags(&pool, &draft.content).await?;
+    let newsletter_issue_id = Uuid::new_v4();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            markdown_content,
+            tracking_enabled,
+            private,
+            segment_id,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        newsletter_issue_id,
+        draft.title,
+        draft.content,
+        draft.tracking,
+        draft.private,
+        draft.segment_id,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the newsletter issue.")
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        "newsletter_issue.create",
+        "newsletter_issue",
+        newsletter_issue_id,
+    )
+    .after(draft.audit_state());
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the creation in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new newsletter issue.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "newsletter_issue_id": newsletter_issue_id
+    })))
+}
+
+#[tracing::instrument(name = "Update a newsletter issue", skip(draft, pool, request))]
+pub async fn update_issue(
+    _: Authorized<IssuesWrite>,
+    newsletter_issue_id: web::Path<Uuid>,
+    draft: web::Json<IssueDraft>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    draft.validate().map_err(e400)?;
+    check_segment(&pool, draft.segment_id).await?;
+    check_content_merge_tags(&pool, &draft.content).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if issue.has_started_sending() {
+        return Ok(HttpResponse::Conflict().body("Published issues cannot be edited."));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            markdown_content = $3,
+            tracking_enabled = $4,
+            private = $5,
+            segment_id = $6
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        draft.title,
+        draft.content,
+        draft.tracking,
+        draft.private,
+        draft.segment_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the newsletter issue.")
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        "newsletter_issue.update",
+        "newsletter_issue",
+        newsletter_issue_id,
+    )
+    .before(issue.audit_state())
+    .after(draft.audit_state());
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the update in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the update.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Preview a newsletter issue", skip(pool, templates))]
+pub async fn preview_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, markdown_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id.into_inner(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter issue.")
+    .map_err(e500)?;
+    let Some(issue) = issue else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let rendered = render_issue(&templates, &issue.title, &issue.markdown_content)
+        .map(fill_default_merge_tags)
+        .context("Failed to render the newsletter issue.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(IssuePreview {
+        html_content: rendered.html_body,
+        text_content: rendered.text_body,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(pool, templates, delivery, request)
+)]
+pub async fn publish_issue(
+    _: Authorized<IssuesPublish>,
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    delivery: web::Data<DeliverySettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if issue.has_started_sending() {
+        return Ok(HttpResponse::Conflict().body("The issue has already been published."));
+    }
+    start_sending(
+        &mut transaction,
+        &templates,
+        &delivery,
+        newsletter_issue_id,
+        &issue.title,
+        &issue.markdown_content,
+        None,
+    )
+    .await
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        "newsletter_issue.publish",
+        "newsletter_issue",
+        newsletter_issue_id,
+    )
+    .before(serde_json::json!({ "status": issue.status }))
+    .after(serde_json::json!({ "status": "publishing" }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the publication in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the publication.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleRequest {
+    /// Either an RFC 3339 timestamp, or a local date and time
+    /// (`2025-09-02T09:00`) to be read in `timezone`.
+    scheduled_at: String,
+    /// An IANA timezone name, such as `Europe/Rome`.
+    timezone: Option<String>,
+    /// Deliver at the local `scheduled_at` in each subscriber's own timezone.
+    #[serde(default)]
+    subscriber_local_time: bool,
+}
+
+/// When an issue starts going out, and the local time it is delivered at
+/// if that depends on the subscriber.
+#[derive(Debug)]
+struct Schedule {
+    scheduled_at: DateTime<Utc>,
+    local_delivery_time: Option<NaiveDateTime>,
+}
+
+/// No timezone is further ahead of UTC than `Pacific/Kiritimati`.
+const MAX_UTC_OFFSET_HOURS: i64 = 14;
+
+impl ScheduleRequest {
+    fn resolve(&self) -> Result<Schedule, String> {
+        if self.subscriber_local_time {
+            if self.timezone.is_some() {
+                return Err(
+                    "A timezone cannot be given when delivering in each subscriber's timezone."
+                        .into(),
+                );
+            }
+            let local = self.local_date_time()?;
+            // Start as soon as the earliest timezone reaches the local time.
+            return Ok(Schedule {
+                scheduled_at: local.and_utc() - chrono::Duration::hours(MAX_UTC_OFFSET_HOURS),
+                local_delivery_time: Some(local),
+            });
+        }
+        let Some(timezone) = &self.timezone else {
+            return DateTime::parse_from_rfc3339(&self.scheduled_at)
+                .map(|t| Schedule {
+                    scheduled_at: t.with_timezone(&Utc),
+                    local_delivery_time: None,
+                })
+                .map_err(|_| {
+                    "`scheduled_at` must be an RFC 3339 timestamp when no timezone is given."
+                        .to_string()
+                });
+        };
+        let timezone: Tz = timezone
+            .parse()
+            .map_err(|_| format!("`{}` is not a known timezone.", timezone))?;
+        let local = self.local_date_time()?;
+        let scheduled_at = match timezone.from_local_datetime(&local) {
+            LocalResult::Single(t) => t.with_timezone(&Utc),
+            // When the clocks go back, the first occurrence is the one people expect.
+            LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+            LocalResult::None => {
+                return Err(format!(
+                    "{} does not exist in {}: the clocks skip it.",
+                    local, timezone
+                ))
+            }
+        };
+        Ok(Schedule {
+            scheduled_at,
+            local_delivery_time: None,
+        })
+    }
+
+    fn local_date_time(&self) -> Result<NaiveDateTime, String> {
+        NaiveDateTime::parse_from_str(&self.scheduled_at, "%Y-%m-%dT%H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(&self.scheduled_at, "%Y-%m-%dT%H:%M"))
+            .map_err(|_| "`scheduled_at` must be a local date and time.".to_string())
+    }
+}
+
+#[tracing::instrument(
+    name = "Schedule a newsletter issue",
+    skip(request, pool, http_request)
+)]
+pub async fn schedule_issue(
+    _: Authorized<IssuesPublish>,
+    newsletter_issue_id: web::Path<Uuid>,
+    request: web::Json<ScheduleRequest>,
+    pool: web::Data<PgPool>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let schedule = request.resolve().map_err(e400)?;
+    if schedule.scheduled_at <= Utc::now() {
+        return Err(e400("Issues can only be scheduled in the future."));
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if issue.has_started_sending() {
+        return Ok(HttpResponse::Conflict().body("The issue has already been published."));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'scheduled',
+            scheduled_at = $2,
+            local_delivery_time = $3,
+            failure_reason = NULL
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        schedule.scheduled_at,
+        schedule.local_delivery_time,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to schedule the newsletter issue.")
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        "newsletter_issue.schedule",
+        "newsletter_issue",
+        newsletter_issue_id,
+    )
+    .before(serde_json::json!({ "status": issue.status }))
+    .after(serde_json::json!({
+        "status": "scheduled",
+        "scheduled_at": schedule.scheduled_at,
+        "local_delivery_time": schedule.local_delivery_time,
+    }));
+    record_audit_event(&mut *transaction, &http_request, event)
+        .await
+        .context("Failed to record the schedule in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the schedule.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "newsletter_issue_id": newsletter_issue_id,
+        "scheduled_at": schedule.scheduled_at,
+        "local_delivery_time": schedule.local_delivery_time,
+    })))
+}
+
+/// Turn a scheduled issue back into a draft.
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(pool, request))]
+pub async fn cancel_scheduled_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if issue.has_started_sending() {
+        return Ok(HttpResponse::Conflict().body("The issue is already being sent."));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'draft',
+            scheduled_at = NULL,
+            local_delivery_time = NULL,
+            failure_reason = NULL
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to cancel the schedule.")
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        "newsletter_issue.cancel_schedule",
+        "newsletter_issue",
+        newsletter_issue_id,
+    )
+    .before(serde_json::json!({ "status": issue.status }))
+    .after(serde_json::json!({ "status": "draft" }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the cancellation in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the cancellation.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct Visibility {
+    private: bool,
+}
+
+/// Hide an issue from the public archive, or bring it back. Unlike its
+/// content, this can change at any time, including after publication.
+#[tracing::instrument(
+    name = "Set the visibility of a newsletter issue",
+    skip(visibility, pool, request)
+)]
+pub async fn set_issue_visibility(
+    newsletter_issue_id: web::Path<Uuid>,
+    visibility: web::Json<Visibility>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let Some(issue) = get_issue_for_update(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            visibility_changed_at = CASE
+                WHEN private = $2 THEN visibility_changed_at
+                ELSE now()
+            END,
+            private = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        visibility.private,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the visibility of the newsletter issue.")
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        "newsletter_issue.set_visibility",
+        "newsletter_issue",
+        newsletter_issue_id,
+    )
+    .before(serde_json::json!({ "private": issue.private }))
+    .after(serde_json::json!({ "private": visibility.private }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the visibility change in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the visibility change.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Serialize)]
+struct IssueStats {
+    delivered: i64,
+    unique_opens: i64,
+    unique_clicks: i64,
+    top_links: Vec<LinkStats>,
+    /// Only reported for issues with an A/B test.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ab_test: Option<AbTestStats>,
+}
+
+#[derive(serde::Serialize)]
+struct AbTestStats {
+    winning_variant: Option<i16>,
+    variants: Vec<VariantResult>,
+}
+
+#[derive(serde::Serialize)]
+struct LinkStats {
+    url: String,
+    clicks: i64,
+    unique_clicks: i64,
+}
+
+#[tracing::instrument(name = "Get the statistics of a newsletter issue", skip(pool))]
+pub async fn issue_stats(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let stats = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM issue_deliveries d
+             WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND d.outcome = 'delivered') AS "delivered!",
+            (SELECT COUNT(DISTINCT o.subscriber_id) FROM issue_opens o
+             WHERE o.newsletter_issue_id = i.newsletter_issue_id) AS "unique_opens!",
+            (SELECT COUNT(DISTINCT c.subscriber_id) FROM issue_clicks c
+             WHERE c.newsletter_issue_id = i.newsletter_issue_id) AS "unique_clicks!"
+        FROM newsletter_issues i
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the statistics of the newsletter issue.")
+    .map_err(e500)?;
+    let Some(stats) = stats else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let top_links = sqlx::query_as!(
+        LinkStats,
+        r#"
+        SELECT
+            url,
+            COUNT(*) AS "clicks!",
+            COUNT(DISTINCT subscriber_id) AS "unique_clicks!"
+        FROM issue_clicks
+        WHERE newsletter_issue_id = $1
+        GROUP BY url
+        ORDER BY 2 DESC, url
+        LIMIT 10
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the most clicked links.")
+    .map_err(e500)?;
+    let ab_test = sqlx::query!(
+        "SELECT winning_variant FROM issue_ab_tests WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the A/B test of the newsletter issue.")
+    .map_err(e500)?;
+    let ab_test = match ab_test {
+        Some(ab_test) => Some(AbTestStats {
+            winning_variant: ab_test.winning_variant,
+            variants: get_variant_results(pool.get_ref(), newsletter_issue_id)
+                .await
+                .context("Failed to fetch the results of the A/B test.")
+                .map_err(e500)?,
+        }),
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(IssueStats {
+        delivered: stats.delivered,
+        unique_opens: stats.unique_opens,
+        unique_clicks: stats.unique_clicks,
+        top_links,
+        ab_test,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+pub(super) async fn get_issue_for_update(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<StoredIssue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        StoredIssue,
+        r#"
+        SELECT title, markdown_content, status, tracking_enabled, private, segment_id
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch the newsletter issue.")?;
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScheduleRequest;
+    use chrono::{TimeZone, Utc};
+    use claims::assert_err;
+
+    fn request(scheduled_at: &str, timezone: Option<&str>) -> ScheduleRequest {
+        ScheduleRequest {
+            scheduled_at: scheduled_at.into(),
+            timezone: timezone.map(Into::into),
+            subscriber_local_time: false,
+        }
+    }
+
+    #[test]
+    fn local_times_are_read_in_the_given_timezone() {
+        let scheduled_at = request("2025-09-02T09:00", Some("Europe/Rome"))
+            .resolve()
+            .unwrap()
+            .scheduled_at;
+        assert_eq!(scheduled_at, Utc.with_ymd_and_hms(2025, 9, 2, 7, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn timestamps_without_a_timezone_need_an_offset() {
+        let scheduled_at = request("2025-09-02T09:00:00-04:00", None)
+            .resolve()
+            .unwrap()
+            .scheduled_at;
+        assert_eq!(scheduled_at, Utc.with_ymd_and_hms(2025, 9, 2, 13, 0, 0).unwrap());
+        assert_err!(request("2025-09-02T09:00", None).resolve());
+    }
+
+    #[test]
+    fn times_skipped_by_daylight_saving_are_rejected() {
+        assert_err!(request("2025-03-30T02:30", Some("Europe/Rome")).resolve());
+        assert_err!(request("2025-09-02T09:00", Some("Mars/Olympus_Mons")).resolve());
+    }
+
+    #[test]
+    fn repeated_times_resolve_to_the_first_occurrence() {
+        let scheduled_at = request("2025-10-26T02:30", Some("Europe/Rome"))
+            .resolve()
+            .unwrap()
+            .scheduled_at;
+        assert_eq!(scheduled_at, Utc.with_ymd_and_hms(2025, 10, 26, 0, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn subscriber_local_times_start_in_the_earliest_timezone() {
+        let mut request = request("2025-09-02T09:00", None);
+        request.subscriber_local_time = true;
+
+        let schedule = request.resolve().unwrap();
+
+        assert_eq!(
+            schedule.scheduled_at,
+            Utc.with_ymd_and_hms(2025, 9, 1, 19, 0, 0).unwrap()
+        );
+        assert_eq!(
+            schedule.local_delivery_time.unwrap().to_string(),
+            "2025-09-02 09:00:00"
+        );
+        request.timezone = Some("Europe/Rome".into());
+        assert_err!(request.resolve());
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::time::Duration;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::telemetry::{global_log_filter, LogFilter, LogFilterError};
+use crate::utils::{e400, e500};
+
+/// Temporary filters last a day at most: debug logs are expensive.
+const MAX_DURATION_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(serde::Deserialize)]
+pub struct LogFilterChange {
+    /// In `RUST_LOG` syntax, e.g. `info,zero2prod::outbound_webhooks=debug`.
+    directive: String,
+    /// Go back to the current filter after this long. Without it, the
+    /// change lasts until the next one, or a restart.
+    duration_seconds: Option<u64>,
+}
+
+impl LogFilterChange {
+    fn duration(&self) -> Result<Option<Duration>, String> {
+        match self.duration_seconds {
+            None => Ok(None),
+            Some(seconds) if (1..=MAX_DURATION_SECONDS).contains(&seconds) => {
+                Ok(Some(Duration::from_secs(seconds)))
+            }
+            Some(_) => Err(format!(
+                "`duration_seconds` must be between 1 and {}.",
+                MAX_DURATION_SECONDS
+            )),
+        }
+    }
+}
+
+fn log_filter() -> Result<&'static LogFilter, actix_web::Error> {
+    global_log_filter().ok_or_else(|| e500("No log filter is installed."))
+}
+
+#[tracing::instrument(name = "Get the log filter")]
+pub async fn get_log_filter() -> Result<HttpResponse, actix_web::Error> {
+    let status = log_filter()?.status().map_err(e500)?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[tracing::instrument(name = "Set the log filter", skip(change, pool, request))]
+pub async fn set_log_filter(
+    change: web::Json<LogFilterChange>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let duration = change.duration().map_err(e400)?;
+    let log_filter = log_filter()?;
+    let previous = log_filter.status().map_err(e500)?;
+    let status = match log_filter.set(&change.directive, duration) {
+        Ok(status) => status,
+        Err(e @ LogFilterError::InvalidDirective(_)) => return Err(e400(e)),
+        Err(e) => return Err(e500(e)),
+    };
+    let event = AuditEvent::new("log_filter.set", "log_filter", "global")
+        .before(serde_json::json!({ "directive": previous.directive }))
+        .after(serde_json::json!({
+            "directive": status.directive,
+            "reverts_at": status.reverts_at,
+        }));
+    record_audit_event(pool.get_ref(), &request, event)
+        .await
+        .context("Failed to record the change in the audit log.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(status))
+}
@@ -0,0 +1,40 @@
+mod ab_tests;
+mod api_keys;
+mod attributes;
+mod audit_log;
+mod consent;
+mod dashboard;
+mod invitations;
+mod issues;
+mod log_filter;
+mod logout;
+mod password;
+mod segments;
+mod subscribers;
+mod totp;
+mod webhook_endpoints;
+
+pub use ab_tests::{configure_ab_test, remove_ab_test};
+pub use api_keys::{create_api_key, list_api_keys, revoke_api_key};
+pub use attributes::{define_attribute, delete_attribute, list_attributes};
+pub use audit_log::list_audit_log;
+pub use consent::subscriber_consent_history;
+pub use dashboard::admin_dashboard;
+pub use invitations::invite_admin;
+pub use issues::{
+    cancel_scheduled_issue, create_issue, issue_stats, preview_issue, publish_issue,
+    schedule_issue, set_issue_visibility, update_issue,
+};
+pub use log_filter::{get_log_filter, set_log_filter};
+pub use logout::log_out;
+pub use password::change_password;
+pub use segments::{
+    create_segment, delete_segment, list_segments, preview_segment, preview_segment_definition,
+    update_segment,
+};
+pub use subscribers::{import_subscribers, update_subscriber_attributes};
+pub use totp::{confirm_totp_enrolment, start_totp_enrolment};
+pub use webhook_endpoints::{
+    create_webhook_endpoint, delete_webhook_endpoint, list_webhook_endpoints,
+    webhook_delivery_log,
+};
@@ -0,0 +1,70 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::authentication::{
+    set_password, validate_credentials, validate_new_password, AuthError, Credentials, UserId,
+};
+use crate::session_state::TypedSession;
+use crate::utils::{e400, e500, see_other};
+
+use super::dashboard::get_username;
+
+#[derive(serde::Deserialize)]
+pub struct PasswordChangeForm {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Change the password of the logged-in user. Every session of theirs ends,
+/// this one included: they log in again with the new password.
+#[tracing::instrument(
+    name = "Change a password",
+    skip(form, pool, session, request),
+    fields(user_id = %*user_id)
+)]
+pub async fn change_password(
+    form: web::Form<PasswordChangeForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let PasswordChangeForm {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.into_inner();
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err(e400("The two new passwords do not match."));
+    }
+    validate_new_password(&new_password).map_err(e400)?;
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => Err(e400("The current password is incorrect.")),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+    set_password(user_id, new_password, &pool)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        pool.get_ref(),
+        &request,
+        AuditEvent::new("admin.change_password", "admin", user_id),
+    )
+    .await
+    .context("Failed to record the password change in the audit log.")
+    .map_err(e500)?;
+    session.log_out();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,274 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::segments::{measure, Segment};
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize)]
+pub struct SegmentDraft {
+    name: String,
+    /// Written in the segment language, see `crate::segments`.
+    definition: String,
+}
+
+impl SegmentDraft {
+    fn validate(&self) -> Result<Segment, String> {
+        if self.name.trim().is_empty() {
+            return Err("The name of a segment cannot be empty.".into());
+        }
+        Segment::parse(&self.definition).map_err(|e| e.to_string())
+    }
+
+    fn audit_state(&self) -> serde_json::Value {
+        serde_json::json!({ "name": self.name.trim(), "definition": self.definition })
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SegmentPreviewRequest {
+    definition: String,
+}
+
+#[derive(serde::Serialize)]
+struct StoredSegment {
+    segment_id: Uuid,
+    name: String,
+    definition: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List segments", skip(pool))]
+pub async fn list_segments(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let segments = sqlx::query_as!(
+        StoredSegment,
+        r#"
+        SELECT segment_id, name, definition, created_at, updated_at
+        FROM segments
+        ORDER BY name
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the segments.")
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(segments))
+}
+
+#[tracing::instrument(name = "Create a segment", skip(draft, pool, request))]
+pub async fn create_segment(
+    draft: web::Json<SegmentDraft>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    draft.validate().map_err(e400)?;
+    let segment_id = Uuid::new_v4();
+    let now = Utc::now();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO segments (segment_id, name, definition, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $4)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        segment_id,
+        draft.name.trim(),
+        draft.definition,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the segment.")
+    .map_err(e500)?
+    .rows_affected()
+        == 1;
+    if !created {
+        return Ok(HttpResponse::Conflict().body("Another segment already has this name."));
+    }
+    let event = AuditEvent::new("segment.create", "segment", segment_id).after(draft.audit_state());
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the creation in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new segment.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Created().json(serde_json::json!({ "segment_id": segment_id })))
+}
+
+/// Issues sent to the segment later on go to the new definition.
+#[tracing::instrument(name = "Update a segment", skip(draft, pool, request))]
+pub async fn update_segment(
+    segment_id: web::Path<Uuid>,
+    draft: web::Json<SegmentDraft>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    draft.validate().map_err(e400)?;
+    let segment_id = segment_id.into_inner();
+    let name_taken = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM segments WHERE name = $2 AND segment_id <> $1
+        ) AS "taken!"
+        "#,
+        segment_id,
+        draft.name.trim(),
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to check whether the name is taken.")
+    .map_err(e500)?
+    .taken;
+    if name_taken {
+        return Ok(HttpResponse::Conflict().body("Another segment already has this name."));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let previous = sqlx::query!(
+        "SELECT name, definition FROM segments WHERE segment_id = $1 FOR UPDATE",
+        segment_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the segment.")
+    .map_err(e500)?;
+    let Some(previous) = previous else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    sqlx::query!(
+        r#"
+        UPDATE segments SET name = $2, definition = $3, updated_at = $4
+        WHERE segment_id = $1
+        "#,
+        segment_id,
+        draft.name.trim(),
+        draft.definition,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the segment.")
+    .map_err(e500)?;
+    let event = AuditEvent::new("segment.update", "segment", segment_id)
+        .before(serde_json::json!({ "name": previous.name, "definition": previous.definition }))
+        .after(draft.audit_state());
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the update in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the update.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Segments that issues were (or will be) sent to are kept, so that we can
+/// tell who an issue was meant for.
+#[tracing::instrument(name = "Delete a segment", skip(pool, request))]
+pub async fn delete_segment(
+    segment_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let segment_id = segment_id.into_inner();
+    let in_use = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM newsletter_issues WHERE segment_id = $1
+        ) AS "in_use!"
+        "#,
+        segment_id,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to check whether the segment is in use.")
+    .map_err(e500)?
+    .in_use;
+    if in_use {
+        return Ok(HttpResponse::Conflict().body("Issues are addressed to this segment."));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let deleted = sqlx::query!(
+        "DELETE FROM segments WHERE segment_id = $1 RETURNING name, definition",
+        segment_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete the segment.")
+    .map_err(e500)?;
+    let Some(deleted) = deleted else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let event = AuditEvent::new("segment.delete", "segment", segment_id)
+        .before(serde_json::json!({ "name": deleted.name, "definition": deleted.definition }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the deletion in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the deletion.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Count the subscribers a definition matches, before saving it.
+#[tracing::instrument(name = "Preview a segment definition", skip(request, pool))]
+pub async fn preview_segment_definition(
+    request: web::Json<SegmentPreviewRequest>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let segment = Segment::parse(&request.definition).map_err(e400)?;
+    let size = measure(pool.get_ref(), &segment)
+        .await
+        .context("Failed to measure the segment.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(size))
+}
+
+#[tracing::instrument(name = "Preview a segment", skip(pool))]
+pub async fn preview_segment(
+    segment_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let definition = sqlx::query!(
+        "SELECT definition FROM segments WHERE segment_id = $1",
+        segment_id.into_inner(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the segment.")
+    .map_err(e500)?;
+    let Some(definition) = definition else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let segment = Segment::parse(&definition.definition)
+        .context("The stored segment definition is not valid.")
+        .map_err(e500)?;
+    let size = measure(pool.get_ref(), &segment)
+        .await
+        .context("Failed to measure the segment.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(size))
+}
@@ -0,0 +1,282 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::attributes::{get_attribute_schema, AttributeSchema, Attributes};
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::authentication::{Authorized, SubscribersWrite};
+use crate::configuration::ConsentSettings;
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::outbound_webhooks::{enqueue_webhook_event, WebhookEventType};
+use crate::domain::{ListName, NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTimezone};
+use crate::routes::{confirm_subscriber, insert_subscriber, is_erased, store_token};
+use crate::utils::{e400, e500, generate_token};
+
+/// Imports are sent in one request, so they are kept to a reasonable size.
+const MAX_IMPORT_SIZE: usize = 10_000;
+
+#[derive(serde::Deserialize)]
+pub struct AttributesUpdate {
+    attributes: Attributes,
+}
+
+/// Replace the attributes of a subscriber.
+#[tracing::instrument(
+    name = "Update the attributes of a subscriber",
+    skip(update, pool, request)
+)]
+pub async fn update_subscriber_attributes(
+    _: Authorized<SubscribersWrite>,
+    subscriber_id: web::Path<Uuid>,
+    update: web::Json<AttributesUpdate>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let schema = get_attribute_schema(pool.get_ref())
+        .await
+        .context("Failed to fetch the attribute schema.")
+        .map_err(e500)?;
+    let attributes = schema.validate(&update.attributes).map_err(e400)?;
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let previous = sqlx::query!(
+        "SELECT attributes FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the attributes of the subscriber.")
+    .map_err(e500)?;
+    let Some(previous) = previous else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    // The values are the subscriber's own data: the log only says which
+    // attributes changed.
+    let previous = match previous.attributes {
+        serde_json::Value::Object(previous) => previous,
+        _ => Default::default(),
+    };
+    let mut changed: Vec<&String> = attributes
+        .keys()
+        .chain(previous.keys())
+        .filter(|name| attributes.get(*name) != previous.get(*name))
+        .collect();
+    changed.sort();
+    changed.dedup();
+    let event = AuditEvent::new("subscriber.update_attributes", "subscriber", subscriber_id)
+        .after(serde_json::json!({ "changed_attributes": changed }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the update in the audit log.")
+        .map_err(e500)?;
+    sqlx::query!(
+        "UPDATE subscriptions SET attributes = $2 WHERE id = $1",
+        subscriber_id,
+        serde_json::Value::Object(attributes),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the attributes of the subscriber.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the attributes of the subscriber.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportedSubscriber {
+    email: String,
+    name: String,
+    timezone: Option<String>,
+    list: Option<String>,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+impl ImportedSubscriber {
+    fn parse(self, schema: &AttributeSchema) -> Result<NewSubscriber, String> {
+        Ok(NewSubscriber {
+            email: SubscriberEmail::parse(self.email)?,
+            name: SubscriberName::parse(self.name)?,
+            timezone: self
+                .timezone
+                .filter(|t| !t.trim().is_empty())
+                .map(SubscriberTimezone::parse)
+                .transpose()?,
+            list: self
+                .list
+                .filter(|l| !l.trim().is_empty())
+                .map(ListName::parse)
+                .transpose()?,
+            attributes: schema.validate(&self.attributes)?,
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ImportError {
+    /// 0-based, in the submitted array.
+    row: usize,
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+struct SkippedRow {
+    /// 0-based, in the submitted array.
+    row: usize,
+    reason: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct ImportOutcome {
+    imported: usize,
+    /// Already subscribed, erased at their own request, or further down the
+    /// import a second time.
+    skipped: usize,
+    skipped_rows: Vec<SkippedRow>,
+}
+
+/// Add subscribers whose consent was collected elsewhere, e.g. when moving
+/// from another newsletter service. They are confirmed right away.
+///
+/// Every row is checked before anything is stored: a single invalid row
+/// rejects the whole import, with the list of problems. Addresses are
+/// compared case-insensitively, and rows that would not add anybody are
+/// skipped, and reported as such.
+#[tracing::instrument(name = "Import subscribers", skip(rows, request, pool, consent))]
+pub async fn import_subscribers(
+    _: Authorized<SubscribersWrite>,
+    rows: web::Json<Vec<ImportedSubscriber>>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    consent: web::Data<ConsentSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = rows.into_inner();
+    if rows.len() > MAX_IMPORT_SIZE {
+        return Err(e400(format!(
+            "Imports are limited to {} subscribers at a time.",
+            MAX_IMPORT_SIZE
+        )));
+    }
+    let schema = get_attribute_schema(pool.get_ref())
+        .await
+        .context("Failed to fetch the attribute schema.")
+        .map_err(e500)?;
+    let mut subscribers = Vec::with_capacity(rows.len());
+    let mut errors = Vec::new();
+    for (row, imported) in rows.into_iter().enumerate() {
+        let parsed = imported.parse(&schema);
+        match parsed {
+            Ok(subscriber) => subscribers.push((row, subscriber)),
+            Err(message) => errors.push(ImportError { row, message }),
+        }
+    }
+    if !errors.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "errors": errors })));
+    }
+
+    let context = ConsentContext::from_request(&request, Some("import".into()));
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let mut outcome = ImportOutcome {
+        imported: 0,
+        skipped: 0,
+        skipped_rows: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    for (row, subscriber) in subscribers {
+        // An import is not fresh consent from the subscriber: erasures stand.
+        let reason = if !seen.insert(subscriber.email.as_ref().to_lowercase()) {
+            Some("The address appears earlier in the import.")
+        } else if subscriber_exists(&mut transaction, &subscriber.email)
+            .await
+            .context("Failed to look up the subscriber.")
+            .map_err(e500)?
+        {
+            Some("The address is already subscribed.")
+        } else if is_erased(&pool, &subscriber.email)
+            .await
+            .context("Failed to check for an erasure tombstone.")
+            .map_err(e500)?
+        {
+            Some("The subscriber asked for their data to be erased.")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            outcome.skipped += 1;
+            outcome.skipped_rows.push(SkippedRow { row, reason });
+            continue;
+        }
+        let subscriber_id = insert_subscriber(&mut transaction, &subscriber)
+            .await
+            .context("Failed to store the subscriber.")
+            .map_err(e500)?;
+        confirm_subscriber(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to confirm the subscriber.")
+            .map_err(e500)?;
+        store_token(&mut transaction, subscriber_id, &generate_token())
+            .await
+            .context("Failed to store the subscription token.")
+            .map_err(e500)?;
+        record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEventType::Import,
+            &context,
+            &consent.privacy_policy_version,
+            &consent.import_wording,
+        )
+        .await
+        .context("Failed to record the consent event.")
+        .map_err(e500)?;
+        for event_type in [
+            WebhookEventType::SubscriberCreated,
+            WebhookEventType::SubscriberConfirmed,
+        ] {
+            enqueue_webhook_event(&mut transaction, event_type, subscriber_id)
+                .await
+                .map_err(e500)?;
+        }
+        outcome.imported += 1;
+    }
+    // Counts only: who was imported is in the consent events.
+    let event = AuditEvent::new("subscriber.import", "subscriber_list", "import")
+        .after(serde_json::json!({ "imported": outcome.imported, "skipped": outcome.skipped }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the import in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the import.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(outcome))
+}
+
+async fn subscriber_exists(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE lower(email) = lower($1)",
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(record.is_some())
+}
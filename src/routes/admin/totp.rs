@@ -0,0 +1,140 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::authentication::{generate_recovery_codes, hash_recovery_code, TotpSecret, UserId};
+use crate::clock::Clock;
+use crate::utils::{e400, e500};
+
+use super::dashboard::get_username;
+
+#[derive(serde::Deserialize)]
+pub struct TotpConfirmation {
+    code: String,
+}
+
+/// Start enabling TOTP: the secret is kept aside until the user proves,
+/// with a first code, that their authenticator app has it.
+#[tracing::instrument(name = "Start a TOTP enrolment", skip(pool), fields(user_id = %*user_id))]
+pub async fn start_totp_enrolment(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let user = sqlx::query!("SELECT totp_secret FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool.get_ref())
+        .await
+        .context("Failed to retrieve the TOTP secret of the user.")
+        .map_err(e500)?;
+    if user.totp_secret.is_some() {
+        return Ok(HttpResponse::Conflict().body("TOTP is already enabled."));
+    }
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let secret = TotpSecret::generate();
+    sqlx::query!(
+        "UPDATE users SET pending_totp_secret = $2 WHERE user_id = $1",
+        user_id,
+        secret.encoded(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the pending TOTP secret.")
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "secret": secret.encoded(),
+        "otpauth_uri": secret.otpauth_uri(&username),
+    })))
+}
+
+/// Enable TOTP once a code checks out, and hand out the recovery codes.
+/// They are only ever shown here: we keep their hashes.
+#[tracing::instrument(
+    name = "Confirm a TOTP enrolment",
+    skip(confirmation, pool, clock, request),
+    fields(user_id = %*user_id)
+)]
+pub async fn confirm_totp_enrolment(
+    confirmation: web::Json<TotpConfirmation>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    clock: web::Data<dyn Clock>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let user = sqlx::query!(
+        "SELECT pending_totp_secret FROM users WHERE user_id = $1 FOR UPDATE",
+        user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to retrieve the pending TOTP secret.")
+    .map_err(e500)?;
+    let Some(pending_secret) = user.pending_totp_secret else {
+        return Err(e400("There is no TOTP enrolment in progress."));
+    };
+    let secret = TotpSecret::parse(&pending_secret).map_err(e500)?;
+    let Some(step) = secret.matching_step(&confirmation.code, clock.now()) else {
+        return Err(e400("Invalid code."));
+    };
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = pending_totp_secret,
+            pending_totp_secret = NULL,
+            totp_last_step = $2,
+            failed_totp_attempts = 0,
+            totp_locked_until = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+        step,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable TOTP.")
+    .map_err(e500)?;
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+    sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous recovery codes.")
+    .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::TEXT[])
+        "#,
+        user_id,
+        &code_hashes,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the recovery codes.")
+    .map_err(e500)?;
+    let event = AuditEvent::new("admin.enable_totp", "admin", user_id)
+        .before(serde_json::json!({ "totp_enabled": false }))
+        .after(serde_json::json!({ "totp_enabled": true }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the enrolment in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to enable TOTP.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "recovery_codes": recovery_codes })))
+}
@@ -0,0 +1,244 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditEvent};
+use crate::outbound_webhooks::WebhookEventType;
+use crate::utils::{e400, e500, generate_token};
+
+/// How many deliveries the log shows at most, most recent first.
+const DELIVERY_LOG_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct WebhookEndpointDraft {
+    url: String,
+    events: Vec<WebhookEventType>,
+}
+
+impl WebhookEndpointDraft {
+    fn validate(&self) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.url)
+            .map_err(|_| format!("{} is not a valid URL.", self.url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("Webhook endpoints must be http(s) URLs.".into());
+        }
+        if self.events.is_empty() {
+            return Err("Subscribe the endpoint to at least one event.".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct WebhookEndpoint {
+    endpoint_id: Uuid,
+    url: String,
+    events: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List webhook endpoints", skip(pool))]
+pub async fn list_webhook_endpoints(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let endpoints = sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+        SELECT endpoint_id, url, event_types AS events, created_at
+        FROM webhook_endpoints
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the webhook endpoints.")
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(endpoints))
+}
+
+/// The signing secret is only ever shown in the response to this request.
+#[tracing::instrument(name = "Register a webhook endpoint", skip(draft, pool, request))]
+pub async fn create_webhook_endpoint(
+    draft: web::Json<WebhookEndpointDraft>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    draft.validate().map_err(e400)?;
+    let endpoint_id = Uuid::new_v4();
+    let secret = generate_token();
+    let mut events: Vec<&str> = draft.events.iter().map(|e| e.as_str()).collect();
+    events.sort_unstable();
+    events.dedup();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_endpoints (endpoint_id, url, secret, event_types, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        endpoint_id,
+        draft.url,
+        secret,
+        &events as &[&str],
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the webhook endpoint.")
+    .map_err(e500)?;
+    // Never the secret.
+    let event = AuditEvent::new("webhook_endpoint.create", "webhook_endpoint", endpoint_id)
+        .after(serde_json::json!({ "url": draft.url, "events": events }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the new endpoint in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new webhook endpoint.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "endpoint_id": endpoint_id,
+        "secret": secret,
+    })))
+}
+
+/// Pending deliveries to the endpoint are dropped along with it.
+#[tracing::instrument(name = "Delete a webhook endpoint", skip(pool, request))]
+pub async fn delete_webhook_endpoint(
+    endpoint_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let endpoint_id = endpoint_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let deleted = sqlx::query!(
+        "DELETE FROM webhook_endpoints WHERE endpoint_id = $1 RETURNING url, event_types",
+        endpoint_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete the webhook endpoint.")
+    .map_err(e500)?;
+    let Some(deleted) = deleted else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let event = AuditEvent::new("webhook_endpoint.delete", "webhook_endpoint", endpoint_id)
+        .before(serde_json::json!({ "url": deleted.url, "events": deleted.event_types }));
+    record_audit_event(&mut *transaction, &request, event)
+        .await
+        .context("Failed to record the deletion in the audit log.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the deletion.")
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryLogEntry {
+    delivery_id: Uuid,
+    event_type: String,
+    /// `pending`, `delivered` or `failed`.
+    status: String,
+    attempts: Vec<DeliveryAttempt>,
+    created_at: DateTime<Utc>,
+    /// When the next attempt is due, for pending deliveries.
+    next_attempt_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryAttempt {
+    attempted_at: DateTime<Utc>,
+    response_status: Option<i16>,
+    error: Option<String>,
+    duration_ms: i32,
+}
+
+#[tracing::instrument(name = "Fetch the delivery log of a webhook endpoint", skip(pool))]
+pub async fn webhook_delivery_log(
+    endpoint_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let endpoint_id = endpoint_id.into_inner();
+    let exists = sqlx::query!(
+        "SELECT endpoint_id FROM webhook_endpoints WHERE endpoint_id = $1",
+        endpoint_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the webhook endpoint.")
+    .map_err(e500)?
+    .is_some();
+    if !exists {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT delivery_id, event_type, status, created_at, next_attempt_at
+        FROM webhook_deliveries
+        WHERE endpoint_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+        endpoint_id,
+        DELIVERY_LOG_SIZE,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the webhook deliveries.")
+    .map_err(e500)?;
+    let delivery_ids: Vec<Uuid> = deliveries.iter().map(|d| d.delivery_id).collect();
+    let attempts = sqlx::query!(
+        r#"
+        SELECT delivery_id, attempted_at, response_status, error, duration_ms
+        FROM webhook_delivery_attempts
+        WHERE delivery_id = ANY($1)
+        ORDER BY attempt
+        "#,
+        &delivery_ids,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the webhook delivery attempts.")
+    .map_err(e500)?;
+    let mut attempts_by_delivery: HashMap<Uuid, Vec<DeliveryAttempt>> = HashMap::new();
+    for a in attempts {
+        attempts_by_delivery
+            .entry(a.delivery_id)
+            .or_default()
+            .push(DeliveryAttempt {
+                attempted_at: a.attempted_at,
+                response_status: a.response_status,
+                error: a.error,
+                duration_ms: a.duration_ms,
+            });
+    }
+    let log: Vec<DeliveryLogEntry> = deliveries
+        .into_iter()
+        .map(|d| DeliveryLogEntry {
+            attempts: attempts_by_delivery
+                .remove(&d.delivery_id)
+                .unwrap_or_default(),
+            next_attempt_at: (d.status == "pending").then_some(d.next_attempt_at),
+            delivery_id: d.delivery_id,
+            event_type: d.event_type,
+            status: d.status,
+            created_at: d.created_at,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(log))
+}
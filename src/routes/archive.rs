@@ -0,0 +1,438 @@
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, ContentType, EntityTag, Header, HttpDate, IfModifiedSince,
+    IfNoneMatch, LastModified,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+use crate::configuration::ArchiveSettings;
+use crate::email_templates::EmailTemplates;
+use crate::issue_rendering::render_web_issue;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e400, e500};
+
+/// How long a CDN (or a browser) may serve an archive response without
+/// checking back with us. Making an issue private takes up to this long to
+/// show everywhere.
+const MAX_AGE_SECONDS: u32 = 300;
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParameters {
+    /// Starts from 1, the most recent issues.
+    page: Option<i64>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    #[default]
+    Rss,
+    Atom,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FeedParameters {
+    #[serde(default)]
+    format: FeedFormat,
+}
+
+#[derive(serde::Serialize)]
+struct ArchivePage {
+    archive_title: String,
+    archive_description: String,
+    issues: Vec<ArchiveEntry>,
+    newer_page_link: Option<String>,
+    older_page_link: Option<String>,
+    rss_feed_link: String,
+    atom_feed_link: String,
+}
+
+#[derive(serde::Serialize)]
+struct ArchiveEntry {
+    title: String,
+    link: String,
+    published_at: String,
+    published_on: String,
+}
+
+struct PublicIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    slug: String,
+    markdown_content: String,
+    web_html_content: Option<String>,
+    published_at: DateTime<Utc>,
+}
+
+/// The list of public issues, most recent first.
+#[tracing::instrument(
+    name = "Show the archive",
+    skip(parameters, request, pool, templates, settings, base_url)
+)]
+pub async fn archive(
+    parameters: web::Query<ArchiveParameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    settings: web::Data<ArchiveSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Archive pages are numbered from 1."));
+    }
+    let page_size = i64::from(settings.page_size);
+    // One more than we show, to know whether there is an older page.
+    let offset = (page - 1).saturating_mul(page_size);
+    let mut issues = get_public_issues(&pool, page_size + 1, offset)
+        .await
+        .map_err(e500)?;
+    let has_older_page = issues.len() as i64 > page_size;
+    issues.truncate(settings.page_size.into());
+    let last_modified = get_archive_last_modified(&pool).await.map_err(e500)?;
+    let base_url = &base_url.0;
+    let page_link = |page: i64| format!("{}/archive?page={}", base_url, page);
+    let body = templates
+        .render_page(
+            "archive",
+            &ArchivePage {
+                archive_title: settings.title.clone(),
+                archive_description: settings.description.clone(),
+                issues: issues
+                    .into_iter()
+                    .map(|issue| ArchiveEntry {
+                        link: issue_link(base_url, &issue.slug),
+                        title: issue.title,
+                        published_at: issue.published_at.to_rfc3339(),
+                        published_on: issue.published_at.format("%-d %B %Y").to_string(),
+                    })
+                    .collect(),
+                newer_page_link: (page > 1).then(|| page_link(page - 1)),
+                older_page_link: has_older_page.then(|| page_link(page + 1)),
+                rss_feed_link: format!("{}/archive/feed.xml", base_url),
+                atom_feed_link: format!("{}/archive/feed.xml?format=atom", base_url),
+            },
+        )
+        .context("Failed to render the archive.")
+        .map_err(e500)?;
+    Ok(cacheable(&request, ContentType::html(), body, last_modified))
+}
+
+/// A single public issue, as it looked when it was published.
+#[tracing::instrument(name = "Show an archived issue", skip(request, pool, templates))]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            title,
+            markdown_content,
+            web_html_content,
+            GREATEST(published_at, visibility_changed_at) AS "last_modified!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND NOT private
+        "#,
+        slug.into_inner(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the archived issue.")
+    .map_err(e500)?;
+    let Some(issue) = issue else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let body = web_version(
+        &templates,
+        &issue.title,
+        &issue.markdown_content,
+        issue.web_html_content,
+    )?;
+    Ok(cacheable(
+        &request,
+        ContentType::html(),
+        body,
+        Some(issue.last_modified),
+    ))
+}
+
+/// The web version of an issue, as frozen when it was published (or when
+/// the winner of its A/B test was chosen).
+fn web_version(
+    templates: &EmailTemplates,
+    title: &str,
+    markdown_content: &str,
+    web_html_content: Option<String>,
+) -> Result<String, actix_web::Error> {
+    match web_html_content {
+        Some(html) => Ok(html),
+        // Issues published before the archive existed have no web version.
+        None => render_web_issue(templates, title, markdown_content)
+            .context("Failed to render the archived issue.")
+            .map_err(e500),
+    }
+}
+
+/// RSS 2.0 by default, Atom with `?format=atom`. Entries carry the same web
+/// version of the issues as the archive pages.
+#[tracing::instrument(
+    name = "Serve the archive feed",
+    skip(parameters, request, pool, templates, settings, base_url)
+)]
+pub async fn archive_feed(
+    parameters: web::Query<FeedParameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    settings: web::Data<ArchiveSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_public_issues(&pool, settings.page_size.into(), 0)
+        .await
+        .map_err(e500)?;
+    let entries = issues
+        .into_iter()
+        .map(|mut issue| {
+            let content = web_version(
+                &templates,
+                &issue.title,
+                &issue.markdown_content,
+                issue.web_html_content.take(),
+            )?;
+            Ok((issue, content))
+        })
+        .collect::<Result<Vec<_>, actix_web::Error>>()?;
+    let last_modified = get_archive_last_modified(&pool).await.map_err(e500)?;
+    let (content_type, body) = match parameters.format {
+        FeedFormat::Rss => (
+            "application/rss+xml; charset=utf-8",
+            rss_feed(&settings, &base_url.0, &entries),
+        ),
+        FeedFormat::Atom => (
+            "application/atom+xml; charset=utf-8",
+            atom_feed(&settings, &base_url.0, &entries, last_modified),
+        ),
+    };
+    Ok(cacheable(
+        &request,
+        ContentType(content_type.parse().unwrap()),
+        body,
+        last_modified,
+    ))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_public_issues(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PublicIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        PublicIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            slug AS "slug!",
+            markdown_content,
+            web_html_content,
+            published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE slug IS NOT NULL AND NOT private
+        ORDER BY published_at DESC, newsletter_issue_id
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the public issues.")?;
+    Ok(issues)
+}
+
+/// When the archive as a whole last changed: an issue was published, or
+/// one was hidden from (or brought back to) the archive.
+#[tracing::instrument(skip(pool))]
+async fn get_archive_last_modified(pool: &PgPool) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let last_modified = sqlx::query!(
+        r#"
+        SELECT MAX(GREATEST(published_at, visibility_changed_at)) AS last_modified
+        FROM newsletter_issues
+        WHERE slug IS NOT NULL
+        "#,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch when the archive last changed.")?
+    .last_modified;
+    Ok(last_modified)
+}
+
+fn issue_link(base_url: &str, slug: &str) -> String {
+    format!("{}/archive/{}", base_url, slug)
+}
+
+/// `entries` are the issues along with their web version.
+fn rss_feed(
+    settings: &ArchiveSettings,
+    base_url: &str,
+    entries: &[(PublicIssue, String)],
+) -> String {
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+<channel>
+<title>{}</title>
+<link>{}/archive</link>
+<description>{}</description>
+<atom:link href="{}/archive/feed.xml" rel="self" type="application/rss+xml"/>
+"#,
+        escape_xml(&settings.title),
+        escape_xml(base_url),
+        escape_xml(&settings.description),
+        escape_xml(base_url),
+    );
+    for (issue, content) in entries {
+        let link = issue_link(base_url, &issue.slug);
+        feed.push_str(&format!(
+            r#"<item>
+<title>{}</title>
+<link>{}</link>
+<guid isPermaLink="true">{}</guid>
+<pubDate>{}</pubDate>
+<description>{}</description>
+</item>
+"#,
+            escape_xml(&issue.title),
+            escape_xml(&link),
+            escape_xml(&link),
+            issue.published_at.to_rfc2822(),
+            escape_xml(content),
+        ));
+    }
+    feed.push_str("</channel>\n</rss>\n");
+    feed
+}
+
+fn atom_feed(
+    settings: &ArchiveSettings,
+    base_url: &str,
+    entries: &[(PublicIssue, String)],
+    last_modified: Option<DateTime<Utc>>,
+) -> String {
+    // An empty archive has never been updated; any fixed date will do.
+    let updated = last_modified.unwrap_or(DateTime::UNIX_EPOCH);
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{}</title>
+<subtitle>{}</subtitle>
+<id>{}/archive</id>
+<link href="{}/archive"/>
+<link href="{}/archive/feed.xml?format=atom" rel="self"/>
+<updated>{}</updated>
+<author><name>{}</name></author>
+"#,
+        escape_xml(&settings.title),
+        escape_xml(&settings.description),
+        escape_xml(base_url),
+        escape_xml(base_url),
+        escape_xml(base_url),
+        updated.to_rfc3339(),
+        escape_xml(&settings.title),
+    );
+    for (issue, content) in entries {
+        feed.push_str(&format!(
+            r#"<entry>
+<title>{}</title>
+<id>urn:uuid:{}</id>
+<link href="{}"/>
+<published>{}</published>
+<updated>{}</updated>
+<content type="html">{}</content>
+</entry>
+"#,
+            escape_xml(&issue.title),
+            issue.newsletter_issue_id,
+            escape_xml(&issue_link(base_url, &issue.slug)),
+            issue.published_at.to_rfc3339(),
+            issue.published_at.to_rfc3339(),
+            escape_xml(content),
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn escape_xml(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serve `body` with validators a cache can revalidate against, or a bare
+/// `304 Not Modified` if the cached copy is still good.
+///
+/// The ETag is a hash of the body, so it changes whenever anything on the
+/// page does. `If-None-Match` wins over `If-Modified-Since`, as per RFC 9110.
+fn cacheable(
+    request: &HttpRequest,
+    content_type: ContentType,
+    body: String,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(Sha256::digest(body.as_bytes())));
+    // HTTP dates have a one second resolution.
+    let last_modified = last_modified.map(|t| HttpDate::from(SystemTime::from(t.trunc_subsecs(0))));
+    let not_modified = if request.headers().contains_key(header::IF_NONE_MATCH) {
+        match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (IfModifiedSince::parse(request), last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => {
+                SystemTime::from(last_modified) <= SystemTime::from(since)
+            }
+            _ => false,
+        }
+    };
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(MAX_AGE_SECONDS),
+        ]))
+        .insert_header(header::ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
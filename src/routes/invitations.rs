@@ -0,0 +1,182 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::error_chain_fmt;
+use crate::authentication::{compute_password_hash, validate_new_password};
+use crate::telemetry::spawn_blocking_with_tracing;
+use crate::utils::{hash_token, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptInvitationForm {
+    token: String,
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum InvitationError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The invitation is unknown, has expired or was already accepted.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for InvitationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for InvitationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+pub async fn accept_invitation_form(parameters: web::Query<InvitationParameters>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Accept your invitation</title></head>
+<body>
+<form action="/invitations/accept" method="post">
+<input type="hidden" name="token" value="{}">
+<label>Username <input type="text" name="username"></label>
+<label>Password <input type="password" name="password"></label>
+<button type="submit">Create my account</button>
+</form>
+</body>
+</html>"#,
+            // Tokens are alphanumeric: anything else cannot be valid and
+            // must not end up in the markup.
+            parameters
+                .token
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect::<String>()
+        ))
+}
+
+/// Create the account of an invited admin, with the role they were invited
+/// with, then send them to the login form.
+#[tracing::instrument(
+    name = "Accept an admin invitation",
+    skip(form, pool),
+    fields(username = %form.username)
+)]
+pub async fn accept_invitation(
+    form: web::Form<AcceptInvitationForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InvitationError> {
+    let AcceptInvitationForm {
+        token,
+        username,
+        password,
+    } = form.into_inner();
+    let username = username.trim().to_owned();
+    if username.is_empty() {
+        return Err(InvitationError::ValidationError(
+            "The username cannot be empty.".into(),
+        ));
+    }
+    validate_new_password(&password).map_err(InvitationError::ValidationError)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let invitation = sqlx::query!(
+        r#"
+        SELECT invitation_id, email, role
+        FROM admin_invitations
+        WHERE token_hash = $1
+            AND accepted_at IS NULL
+            AND expires_at > now()
+        FOR UPDATE
+        "#,
+        hash_token(&token),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the invitation.")?
+    .ok_or(InvitationError::UnknownToken)?;
+    check_availability(&mut transaction, &username, &invitation.email).await?;
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        invitation.role,
+        invitation.email,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the new admin.")?;
+    sqlx::query!(
+        "UPDATE admin_invitations SET accepted_at = $2 WHERE invitation_id = $1",
+        invitation.invitation_id,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark the invitation as accepted.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to accept an invitation.")?;
+    Ok(see_other("/login"))
+}
+
+async fn check_availability(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    email: &str,
+) -> Result<(), InvitationError> {
+    let taken = sqlx::query!(
+        r#"
+        SELECT username = $1 AS "username_taken!"
+        FROM users
+        WHERE username = $1 OR email = $2
+        "#,
+        username,
+        email,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to check whether the username is available.")?;
+    if taken.iter().any(|row| row.username_taken) {
+        return Err(InvitationError::ValidationError(
+            "This username is already taken.".into(),
+        ));
+    }
+    if !taken.is_empty() {
+        return Err(InvitationError::ValidationError(
+            "An admin with this email address already exists.".into(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+
+pub async fn login_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Login</title></head>
+<body>
+<form action="/login" method="post">
+<label>Username <input type="text" name="username"></label>
+<label>Password <input type="password" name="password"></label>
+<button type="submit">Login</button>
+</form>
+<p><a href="/password_reset">Forgot your password?</a></p>
+</body>
+</html>"#,
+    )
+}
@@ -0,0 +1,7 @@
+mod get;
+mod post;
+mod totp;
+
+pub use get::login_form;
+pub use post::{login, LoginError};
+pub use totp::{totp_form, verify_totp};
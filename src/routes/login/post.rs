@@ -0,0 +1,83 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::authentication::{
+    get_session_generation, has_totp, validate_credentials, AuthError, Credentials,
+};
+use crate::routes::error_chain_fmt;
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Too many failed attempts")]
+    TooManyAttempts(#[source] anyhow::Error),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LoginError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            LoginError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            LoginError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip(form, pool, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, LoginError> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    session.renew();
+    // With TOTP enabled, the password only gets the user to the second step.
+    if has_totp(user_id, &pool).await? {
+        session
+            .insert_pending_user_id(user_id)
+            .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+        return Ok(see_other("/login/totp"));
+    }
+    let session_generation = get_session_generation(user_id, &pool).await?;
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    session
+        .insert_session_generation(session_generation)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    Ok(see_other("/admin/dashboard"))
+}
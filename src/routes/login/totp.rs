@@ -0,0 +1,67 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use super::LoginError;
+use crate::authentication::{get_session_generation, verify_second_factor, TotpError};
+use crate::clock::Clock;
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+
+#[derive(serde::Deserialize)]
+pub struct TotpForm {
+    /// From the authenticator app, or one of the recovery codes.
+    code: String,
+}
+
+pub async fn totp_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Login</title></head>
+<body>
+<form action="/login/totp" method="post">
+<label>Code from your authenticator app, or a recovery code
+<input type="text" name="code" autocomplete="one-time-code"></label>
+<button type="submit">Login</button>
+</form>
+</body>
+</html>"#,
+    )
+}
+
+/// The second step of the login of users who enabled TOTP.
+#[tracing::instrument(
+    skip(form, pool, clock, session),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn verify_totp(
+    form: web::Form<TotpForm>,
+    pool: web::Data<PgPool>,
+    clock: web::Data<dyn Clock>,
+    session: TypedSession,
+) -> Result<HttpResponse, LoginError> {
+    let user_id = session
+        .get_pending_user_id()
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?
+        .ok_or_else(|| LoginError::AuthError(anyhow::anyhow!("No login is in progress.")))?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    verify_second_factor(user_id, &form.code, clock.now(), &pool)
+        .await
+        .map_err(|e| match e {
+            TotpError::InvalidCode => LoginError::AuthError(e.into()),
+            TotpError::TooManyAttempts => LoginError::TooManyAttempts(e.into()),
+            TotpError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+        })?;
+
+    session.renew();
+    session.remove_pending_user_id();
+    let session_generation = get_session_generation(user_id, &pool).await?;
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    session
+        .insert_session_generation(session_generation)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    Ok(see_other("/admin/dashboard"))
+}
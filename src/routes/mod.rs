@@ -1,6 +1,28 @@
+mod admin;
+mod archive;
 mod health_check;
+mod invitations;
+mod login;
+mod password_reset;
+mod preferences;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriber_data;
+mod tracking;
+mod unsubscribe;
+mod webhooks;
 
 
+pub use admin::*;
+pub use archive::*;
 pub use health_check::*;
-pub use subscriptions::*;
\ No newline at end of file
+pub use invitations::*;
+pub use login::*;
+pub use password_reset::*;
+pub use preferences::*;
+pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriber_data::*;
+pub use tracking::*;
+pub use unsubscribe::*;
+pub use webhooks::*;
@@ -0,0 +1,219 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error_chain_fmt;
+use crate::authentication::{set_password, validate_new_password};
+use crate::consent::client_ip_address;
+use crate::domain::SubscriberEmail;
+use crate::password_reset_worker::PASSWORD_RESET_QUEUE_CHANNEL;
+use crate::utils::{hash_token, see_other};
+
+/// Reset links that can be requested for an address, and from a client
+/// address, within `THROTTLING_WINDOW_MINUTES`.
+const MAX_REQUESTS_PER_ADDRESS: i64 = 3;
+const MAX_REQUESTS_PER_IP_ADDRESS: i64 = 10;
+const THROTTLING_WINDOW_MINUTES: i32 = 60;
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestForm {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetForm {
+    token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PasswordResetError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The password reset link is unknown, has expired or was already used.")]
+    UnknownToken,
+    #[error("Too many password reset links were requested. Try again later.")]
+    TooManyRequests,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PasswordResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+pub async fn password_reset_request_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Forgot your password?</title></head>
+<body>
+<form action="/password_reset" method="post">
+<label>Email <input type="email" name="email"></label>
+<button type="submit">Send me a reset link</button>
+</form>
+</body>
+</html>"#,
+    )
+}
+
+/// Queue a password reset link for the admin with this address, if any.
+///
+/// We answer in the same way, and as fast, whether or not we know the
+/// address: otherwise this endpoint could be used to find out who the
+/// admins are. The link is sent by the password reset worker.
+#[tracing::instrument(name = "Request a password reset", skip(form, request, pool))]
+pub async fn request_password_reset(
+    form: web::Form<PasswordResetRequestForm>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).map_err(PasswordResetError::ValidationError)?;
+    let ip_address = client_ip_address(&request);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // Known and unknown addresses are throttled alike.
+    let recent = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE lower(email) = lower($1)) AS "for_address!",
+            COUNT(*) FILTER (WHERE ip_address = $2) AS "from_ip_address!"
+        FROM password_reset_requests
+        WHERE requested_at > now() - make_interval(mins => $3)
+        "#,
+        email.as_ref(),
+        ip_address,
+        THROTTLING_WINDOW_MINUTES,
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to count the recent password reset requests.")?;
+    if recent.for_address >= MAX_REQUESTS_PER_ADDRESS
+        || recent.from_ip_address >= MAX_REQUESTS_PER_IP_ADDRESS
+    {
+        return Err(PasswordResetError::TooManyRequests);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_requests (
+            password_reset_request_id,
+            email,
+            ip_address,
+            requested_at,
+            user_id
+        )
+        VALUES ($1, $2, $3, now(), (SELECT user_id FROM users WHERE email = $2))
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        ip_address,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to queue the password reset request.")?;
+    sqlx::query!("SELECT pg_notify($1, '')", PASSWORD_RESET_QUEUE_CHANNEL)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to wake up the password reset worker.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the password reset request.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn password_reset_form(parameters: web::Query<PasswordResetParameters>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Reset your password</title></head>
+<body>
+<form action="/password_reset/confirm" method="post">
+<input type="hidden" name="token" value="{}">
+<label>New password <input type="password" name="new_password"></label>
+<label>New password, again <input type="password" name="new_password_check"></label>
+<button type="submit">Reset my password</button>
+</form>
+</body>
+</html>"#,
+            // Tokens are alphanumeric: anything else cannot be valid and
+            // must not end up in the markup.
+            parameters
+                .token
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect::<String>()
+        ))
+}
+
+/// Set a new password from a reset link, then send the admin to the login
+/// form. Every session they had is over.
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, pool),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn reset_password(
+    form: web::Form<PasswordResetForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let PasswordResetForm {
+        token,
+        new_password,
+        new_password_check,
+    } = form.into_inner();
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err(PasswordResetError::ValidationError(
+            "The two new passwords do not match.".into(),
+        ));
+    }
+    validate_new_password(&new_password).map_err(PasswordResetError::ValidationError)?;
+    // Deleting the token is what makes it single-use, even when two
+    // requests race each other.
+    let user_id = sqlx::query!(
+        r#"
+        DELETE FROM password_reset_tokens
+        WHERE token_hash = $1 AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_token(&token),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the password reset token.")?
+    .ok_or(PasswordResetError::UnknownToken)?
+    .user_id;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    set_password(user_id, new_password, &pool)
+        .await
+        .context("Failed to set the new password.")?;
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,140 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::{ConsentSettings, DeliverySettings};
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::domain::SubscriberTimezone;
+use super::get_subscriber_id_from_token;
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    subscription_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesForm {
+    subscription_token: String,
+    /// An IANA timezone name; leave it empty to go back to the default.
+    timezone: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Preferences {
+    email: String,
+    name: String,
+    timezone: Option<String>,
+    default_timezone: String,
+}
+
+#[tracing::instrument(name = "Show subscriber preferences", skip(parameters, pool, delivery))]
+pub async fn get_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    delivery: web::Data<DeliverySettings>,
+) -> HttpResponse {
+    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber_id = match id {
+        None => return HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => subscriber_id,
+    };
+    let subscriber = match sqlx::query!(
+        "SELECT email, name, timezone FROM subscriptions WHERE id = $1",
+        subscriber_id,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    {
+        Ok(subscriber) => subscriber,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    HttpResponse::Ok().json(Preferences {
+        email: subscriber.email,
+        name: subscriber.name,
+        timezone: subscriber.timezone,
+        default_timezone: delivery.default_timezone.name().to_owned(),
+    })
+}
+
+#[tracing::instrument(name = "Update subscriber preferences", skip(form, request, pool, consent))]
+pub async fn update_preferences(
+    form: web::Form<PreferencesForm>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    consent: web::Data<ConsentSettings>,
+) -> HttpResponse {
+    let timezone = match form
+        .timezone
+        .clone()
+        .filter(|t| !t.trim().is_empty())
+        .map(SubscriberTimezone::parse)
+        .transpose()
+    {
+        Ok(timezone) => timezone,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let id = match get_subscriber_id_from_token(&pool, &form.subscription_token).await {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber_id = match id {
+        None => return HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => subscriber_id,
+    };
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let changed = match update_timezone(&mut transaction, subscriber_id, timezone).await {
+        Ok(changed) => changed,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if changed {
+        let context = ConsentContext::from_request(&request, None);
+        if record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEventType::PreferenceChange,
+            &context,
+            &consent.privacy_policy_version,
+            &consent.preference_change_wording,
+        )
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(consent.preference_change_wording.clone())
+}
+
+#[tracing::instrument(name = "Update subscriber timezone", skip(transaction))]
+async fn update_timezone(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    timezone: Option<SubscriberTimezone>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET timezone = $2
+        WHERE id = $1 AND timezone IS DISTINCT FROM $2
+        "#,
+        subscriber_id,
+        timezone.as_ref().map(AsRef::as_ref),
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,517 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::consent::{get_consent_history, ConsentEventRecord};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_templates::EmailTemplates;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::generate_token;
+use super::error_chain_fmt;
+
+const DATA_ACCESS_TOKEN_TTL_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestForm {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataAccessParameters {
+    token: String,
+}
+
+/// The values of tokens are left out: they are credentials rather than data
+/// about the subscriber, and the export may end up anywhere.
+#[derive(serde::Serialize)]
+struct SubscriberDataExport {
+    subscription: SubscriptionRecord,
+    lists: Vec<ListRecord>,
+    data_access_tokens: Vec<DataAccessTokenRecord>,
+    consent_events: Vec<ConsentEventRecord>,
+    delivery_history: Vec<DeliveryRecord>,
+    email_events: Vec<EmailEventRecord>,
+    opens: Vec<OpenRecord>,
+    clicks: Vec<ClickRecord>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    status: String,
+    timezone: Option<String>,
+    attributes: serde_json::Value,
+}
+
+#[derive(serde::Serialize)]
+struct ListRecord {
+    list_name: String,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryRecord {
+    newsletter_issue_id: Uuid,
+    title: String,
+    /// The A/B test variant the subscriber received, if any.
+    variant: Option<i16>,
+    outcome: String,
+    attempted_at: DateTime<Utc>,
+}
+
+/// What our email provider told us about messages sent to the subscriber.
+#[derive(serde::Serialize)]
+struct EmailEventRecord {
+    provider: String,
+    record_type: String,
+    event_type: Option<String>,
+    payload: serde_json::Value,
+    received_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct OpenRecord {
+    newsletter_issue_id: Uuid,
+    opened_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct ClickRecord {
+    newsletter_issue_id: Uuid,
+    url: String,
+    clicked_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DataAccessTokenRecord {
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The data access token is unknown or has expired.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Hash under which an erased address is remembered.
+/// Addresses are normalised to lower case so that a re-import with different
+/// casing still matches the tombstone.
+pub fn erasure_tombstone_hash(email: &str) -> String {
+    let normalised = email.trim().to_lowercase();
+    hex::encode(Sha256::digest(normalised.as_bytes()))
+}
+
+#[tracing::instrument(name = "Checking for an erasure tombstone", skip(pool, email))]
+pub async fn is_erased(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        "SELECT email_hash FROM erased_subscribers WHERE email_hash = $1",
+        erasure_tombstone_hash(email.as_ref()),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(record.is_some())
+}
+
+/// An explicit signup is fresh consent: the tombstone left by a previous
+/// erasure no longer applies to this address.
+#[tracing::instrument(name = "Lifting an erasure tombstone", skip(transaction, email))]
+pub async fn lift_erasure_tombstone(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM erased_subscribers WHERE email_hash = $1",
+        erasure_tombstone_hash(email.as_ref()),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Requesting access to subscriber data",
+    skip(form, pool, email_client, templates, base_url)
+)]
+pub async fn request_data_access(
+    form: web::Form<DataRequestForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(SubscriberDataError::ValidationError)?;
+    // We answer in the same way whether or not we know the address,
+    // otherwise this endpoint could be used to probe our subscriber list.
+    let subscriber_id = get_subscriber_id_from_email(&pool, &email)
+        .await
+        .context("Failed to look up the subscriber by email.")?;
+    let Some(subscriber_id) = subscriber_id else {
+        return Ok(HttpResponse::Ok().finish());
+    };
+    let token = generate_token();
+    store_data_access_token(&pool, subscriber_id, &token)
+        .await
+        .context("Failed to store the data access token.")?;
+    send_data_access_email(&email_client, &templates, email, &base_url.0, &token)
+        .await
+        .context("Failed to send the data access email.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Exporting subscriber data", skip(parameters, pool))]
+pub async fn export_subscriber_data(
+    parameters: web::Query<DataAccessParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = get_subscriber_id_from_data_access_token(&pool, &parameters.token)
+        .await
+        .context("Failed to look up the data access token.")?
+        .ok_or(SubscriberDataError::UnknownToken)?;
+    let export = collect_subscriber_data(&pool, subscriber_id)
+        .await
+        .context("Failed to collect the subscriber data.")?;
+    Ok(HttpResponse::Ok().json(export))
+}
+
+pub async fn erase_subscriber_data_form(
+    parameters: web::Query<DataAccessParameters>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Erase my data</title></head>
+<body>
+<p>This permanently removes your subscription and everything we hold about this address.</p>
+<form action="/me/data/erase" method="post">
+<input type="hidden" name="token" value="{}">
+<button type="submit">Erase my data</button>
+</form>
+</body>
+</html>"#,
+            // Tokens are alphanumeric: anything else cannot be valid and
+            // must not end up in the markup.
+            parameters
+                .token
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect::<String>()
+        ))
+}
+
+#[tracing::instrument(name = "Erasing subscriber data", skip(form, pool))]
+pub async fn erase_subscriber_data(
+    form: web::Form<DataAccessParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = get_subscriber_id_from_data_access_token(&pool, &form.token)
+        .await
+        .context("Failed to look up the data access token.")?
+        .ok_or(SubscriberDataError::UnknownToken)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    erase_subscriber(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the erasure transaction.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Get subscriber_id from email", skip(pool, email))]
+pub async fn get_subscriber_id_from_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = $1",
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Store data access token in the database", skip(pool, token))]
+async fn store_data_access_token(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO data_access_tokens (data_access_token, subscription_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        token,
+        subscriber_id,
+        now,
+        now + chrono::Duration::hours(DATA_ACCESS_TOKEN_TTL_HOURS),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get subscriber_id from data access token", skip(pool, token))]
+async fn get_subscriber_id_from_data_access_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT subscription_id FROM data_access_tokens
+        WHERE data_access_token = $1 AND expires_at > now()
+        "#,
+        token,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(result.map(|r| r.subscription_id))
+}
+
+#[tracing::instrument(name = "Collect subscriber data", skip(pool))]
+async fn collect_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<SubscriberDataExport, sqlx::Error> {
+    let subscription = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, subscribed_at, status, timezone, attributes
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_one(pool)
+    .await?;
+    let lists = sqlx::query_as!(
+        ListRecord,
+        r#"
+        SELECT list_name, joined_at FROM subscriber_lists
+        WHERE subscriber_id = $1
+        ORDER BY joined_at, list_name
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    let data_access_tokens = sqlx::query_as!(
+        DataAccessTokenRecord,
+        r#"
+        SELECT created_at, expires_at FROM data_access_tokens
+        WHERE subscription_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    let consent_events = get_consent_history(pool, subscriber_id).await?;
+    let delivery_history = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.variant, d.outcome, d.attempted_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE d.subscriber_id = $1
+        ORDER BY d.attempted_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    let email_events = sqlx::query_as!(
+        EmailEventRecord,
+        r#"
+        SELECT provider, record_type, event_type, payload, received_at
+        FROM email_events
+        WHERE subscriber_id = $1 OR lower(email) = lower($2)
+        ORDER BY received_at
+        "#,
+        subscriber_id,
+        subscription.email,
+    )
+    .fetch_all(pool)
+    .await?;
+    let opens = sqlx::query_as!(
+        OpenRecord,
+        r#"
+        SELECT newsletter_issue_id, opened_at FROM issue_opens
+        WHERE subscriber_id = $1
+        ORDER BY opened_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    let clicks = sqlx::query_as!(
+        ClickRecord,
+        r#"
+        SELECT newsletter_issue_id, url, clicked_at FROM issue_clicks
+        WHERE subscriber_id = $1
+        ORDER BY clicked_at
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(SubscriberDataExport {
+        subscription,
+        lists,
+        data_access_tokens,
+        consent_events,
+        delivery_history,
+        email_events,
+        opens,
+        clicks,
+    })
+}
+
+/// Remove every row that refers to the subscriber and leave a hashed
+/// tombstone behind, so that the address cannot be re-added silently.
+#[tracing::instrument(name = "Erase subscriber", skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let email = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        subscriber_id,
+    )
+    .fetch_one(&mut **transaction)
+    .await?
+    .email;
+    sqlx::query!(
+        "DELETE FROM data_access_tokens WHERE subscription_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM issue_deliveries WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!("DELETE FROM issue_opens WHERE subscriber_id = $1", subscriber_id)
+        .execute(&mut **transaction)
+        .await?;
+    sqlx::query!("DELETE FROM issue_clicks WHERE subscriber_id = $1", subscriber_id)
+        .execute(&mut **transaction)
+        .await?;
+    // Events about the address may predate the subscription.
+    sqlx::query!(
+        "DELETE FROM email_events WHERE subscriber_id = $1 OR lower(email) = lower($2)",
+        subscriber_id,
+        email,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM webhook_deliveries WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM consent_events WHERE subscription_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscriptions_tokens WHERE subscription_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscriber_lists WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut **transaction)
+        .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO erased_subscribers (email_hash, erased_at)
+        VALUES ($1, $2)
+        ON CONFLICT (email_hash) DO UPDATE SET erased_at = EXCLUDED.erased_at
+        "#,
+        erasure_tombstone_hash(&email),
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a data access email",
+    skip(email_client, templates, recipient, base_url, token)
+)]
+async fn send_data_access_email(
+    email_client: &EmailClient,
+    templates: &EmailTemplates,
+    recipient: SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let email = templates.render(
+        "data_access",
+        &serde_json::json!({
+            "access_link": format!("{}/me/data?token={}", base_url, token),
+            "erase_link": format!("{}/me/data/erase?token={}", base_url, token),
+            "expires_in_hours": DATA_ACCESS_TOKEN_TTL_HOURS,
+        }),
+    )?;
+    email_client
+        .send_email(recipient, "Your data", &email.html_body, &email.text_body)
+        .await?;
+    Ok(())
+}
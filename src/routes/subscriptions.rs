@@ -1,51 +1,158 @@
 
-use actix_web::{web, HttpResponse};
-use sqlx::{ PgPool};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{ PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
 // use tracing::Instrument;
 // use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{domain::{NewSubscriber, SubscriberEmail, SubscriberName}, email_client::{ EmailClient}};
+use crate::{domain::{ListName, NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTimezone}, email_client::{ EmailClient}};
+use crate::attributes::{get_attribute_schema, Attributes};
+use crate::configuration::ConsentSettings;
+use crate::email_templates::EmailTemplates;
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::outbound_webhooks::{enqueue_webhook_event, WebhookEventType};
+use crate::startup::ApplicationBaseUrl;
+use crate::telemetry::{RedactedEmail, RedactedName};
+use crate::utils::generate_token;
+use super::lift_erasure_tombstone;
 
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct FormData{
     pub email: String,
-    pub name: String
+    pub name: String,
+    /// Identifies the signup form, e.g. `homepage-footer`.
+    pub source: Option<String>,
+    /// An IANA timezone name, usually detected by the signup form.
+    pub timezone: Option<String>,
+    /// The list to join, such as `rust`, for forms on topic pages.
+    pub list: Option<String>,
+    /// Custom attributes arrive as `attributes.<name>` fields; anything
+    /// else the form sends along is ignored.
+    #[serde(flatten)]
+    pub extra_fields: HashMap<String, String>,
+}
+
+impl FormData {
+    fn take_attributes(&mut self) -> HashMap<String, String> {
+        std::mem::take(&mut self.extra_fields)
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix("attributes.")?.to_owned(), value)))
+            .collect()
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber{
     type Error = String;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        println!("Received form data: {:?}", value.email);
         let name = SubscriberName::parse(value.name)?;
         let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+        let timezone = value
+            .timezone
+            .filter(|t| !t.trim().is_empty())
+            .map(SubscriberTimezone::parse)
+            .transpose()?;
+        let list = value
+            .list
+            .filter(|l| !l.trim().is_empty())
+            .map(ListName::parse)
+            .transpose()?;
+        Ok(NewSubscriber {
+            email,
+            name,
+            timezone,
+            list,
+            attributes: Attributes::new(),
+        })
     }
 }
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client),
+    skip(form, request, pool, email_client, templates, base_url, consent),
     fields(
-        // request_id = %uuid::Uuid::new_v4(),
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = %RedactedEmail(&form.email),
+        subscriber_name = %RedactedName(&form.name)
     )
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    consent: web::Data<ConsentSettings>,
 ) -> HttpResponse {
-    let new_subscriber = match form.0.try_into() {
+    let context = ConsentContext::from_request(&request, form.source.clone());
+    let mut form = form.into_inner();
+    let attribute_fields = form.take_attributes();
+    let mut new_subscriber: NewSubscriber = match form.try_into() {
         Ok(form) => form,
         Err(_e) => return HttpResponse::BadRequest().finish()
     };
-    if insert_subscriber(&pool, &new_subscriber).await.is_err() {
+    let schema = match get_attribute_schema(pool.get_ref()).await {
+        Ok(schema) => schema,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    new_subscriber.attributes = match schema.parse_form(&attribute_fields) {
+        Ok(attributes) => attributes,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if lift_erasure_tombstone(&mut transaction, &new_subscriber.email)
+        .await
+        .is_err()
+    {
         return HttpResponse::InternalServerError().finish();
     }
-    if send_confirmation_email(&email_client, new_subscriber)
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber).await {
+        Ok(subscriber_id) => subscriber_id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscription_token = generate_token();
+    if store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .is_err() {
+            return HttpResponse::InternalServerError().finish();
+        }
+    if record_consent_event(
+        &mut transaction,
+        subscriber_id,
+        ConsentEventType::Signup,
+        &context,
+        &consent.privacy_policy_version,
+        &consent.signup_wording,
+    )
+    .await
+    .is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if enqueue_webhook_event(
+        &mut transaction,
+        WebhookEventType::SubscriberCreated,
+        subscriber_id,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if send_confirmation_email(
+        &email_client,
+        &templates,
+        new_subscriber,
+        &base_url.0,
+        &subscription_token
+    )
         .await
         .is_err() {
             return HttpResponse::InternalServerError().finish();
@@ -57,24 +164,70 @@ pub async fn subscribe(
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(pool, new_subscriber)
+    skip(transaction, new_subscriber)
 )]
 pub async fn insert_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     // form: &FormData,
     new_subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
+) -> Result<Uuid, sqlx::Error> {
+    let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status) 
-        VALUES ($1, $2, $3, $4, 'confirmed')
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, timezone, attributes)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5, $6)
         "#,
-        uuid::Uuid::new_v4(),
+        subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         chrono::Utc::now(),
+        new_subscriber.timezone.as_ref().map(AsRef::as_ref),
+        serde_json::Value::Object(new_subscriber.attributes.clone()),
     )
-    .execute(pool)
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    if let Some(list) = &new_subscriber.list {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_lists (subscriber_id, list_name, joined_at)
+            VALUES ($1, $2, $3)
+            "#,
+            subscriber_id,
+            list.as_ref(),
+            chrono::Utc::now(),
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    }
+    Ok(subscriber_id)
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(transaction, subscription_token)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions_tokens (subscription_token, subscription_id)
+        VALUES ($1, $2)
+        "#,
+        subscription_token,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -85,28 +238,48 @@ pub async fn insert_subscriber(
 
 #[tracing::instrument(
     name = "send a confirmation email",
-    skip(email_client, new_subscriber)
+    skip(email_client, templates, new_subscriber, base_url, subscription_token)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = "https://example.com/confirm?subscription_token=some_token";
-    
-    let plain_body = format!(
-        "Welcome to our newsletter! Click here to confirm your subscription: {}",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter! <a href=\"{}\">Click here to confirm your subscription</a>",
-        confirmation_link
+    templates: &EmailTemplates,
+    new_subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url,
+        subscription_token
     );
+    let email = templates.render_for(
+        "confirmation",
+        &serde_json::json!({
+            "subscriber_name": new_subscriber.name.as_ref(),
+            "confirmation_link": confirmation_link,
+        }),
+        &new_subscriber.attributes,
+    )?;
     email_client
         .send_email(
             new_subscriber.email,
              "Welcome!", 
-            &html_body,
-            &plain_body
+            &email.html_body,
+            &email.text_body
         )
-        .await
-}
\ No newline at end of file
+        .await?;
+    Ok(())
+}
+
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::ConsentSettings;
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::outbound_webhooks::{enqueue_webhook_event, WebhookEventType};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, request, pool, consent))]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    consent: web::Data<ConsentSettings>,
+) -> HttpResponse {
+    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber_id = match id {
+        None => return HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => subscriber_id,
+    };
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let confirmed = match confirm_subscriber(&mut transaction, subscriber_id).await {
+        Ok(confirmed) => confirmed,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    // Following the link twice is harmless and must not record a second event.
+    if confirmed {
+        let context = ConsentContext::from_request(&request, None);
+        if record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEventType::Confirm,
+            &context,
+            &consent.privacy_policy_version,
+            &consent.confirmation_wording,
+        )
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+        if enqueue_webhook_event(
+            &mut transaction,
+            WebhookEventType::SubscriberConfirmed,
+            subscriber_id,
+        )
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
+
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(transaction))]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
+pub async fn get_subscriber_id_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        "SELECT subscription_id FROM subscriptions_tokens WHERE subscription_token = $1",
+        subscription_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscription_id))
+}
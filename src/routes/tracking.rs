@@ -0,0 +1,112 @@
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::tracking::{TrackedEvent, Tracker};
+
+/// The smallest transparent GIF there is.
+const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[tracing::instrument(name = "Track an open", skip(token, pool, tracker))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracker: web::Data<Tracker>,
+) -> HttpResponse {
+    let Ok(TrackedEvent::Open {
+        newsletter_issue_id,
+        subscriber_id,
+    }) = tracker.verify(&token)
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    // The pixel is served even if we fail to record the open: there is
+    // nothing the recipient could do about it.
+    if let Err(e) = record_open(&pool, newsletter_issue_id, subscriber_id).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record an open.");
+    }
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .content_type("image/gif")
+        .body(TRACKING_PIXEL)
+}
+
+/// Redirect to the destination of a tracked link.
+///
+/// Only URLs we signed ourselves are followed: anything else would turn
+/// this endpoint into an open redirect.
+#[tracing::instrument(name = "Track a click", skip(token, pool, tracker))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracker: web::Data<Tracker>,
+) -> HttpResponse {
+    let Ok(TrackedEvent::Click {
+        newsletter_issue_id,
+        subscriber_id,
+        url,
+    }) = tracker.verify(&token)
+    else {
+        return HttpResponse::BadRequest().body("This link is not valid.");
+    };
+    if let Err(e) = record_click(&pool, newsletter_issue_id, subscriber_id, &url).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record a click.");
+    }
+    HttpResponse::Found()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+/// Events for subscribers who have since been erased are dropped.
+#[tracing::instrument(name = "Record an open", skip(pool))]
+async fn record_open(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_opens (newsletter_issue_id, subscriber_id, opened_at)
+        SELECT $1, $2, $3
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE id = $2)
+            AND EXISTS (SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1)
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        Utc::now(),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Record a click", skip(pool, url))]
+async fn record_click(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    url: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_clicks (newsletter_issue_id, subscriber_id, url, clicked_at)
+        SELECT $1, $2, $3, $4
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE id = $2)
+            AND EXISTS (SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1)
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        url,
+        Utc::now(),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
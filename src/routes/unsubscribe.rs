@@ -0,0 +1,91 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::ConsentSettings;
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::outbound_webhooks::{enqueue_webhook_event, WebhookEventType};
+use super::get_subscriber_id_from_token;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, request, pool, consent))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    consent: web::Data<ConsentSettings>,
+) -> HttpResponse {
+    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber_id = match id {
+        None => return HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => subscriber_id,
+    };
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let unsubscribed = match mark_subscriber_as_unsubscribed(&mut transaction, subscriber_id).await {
+        Ok(unsubscribed) => unsubscribed,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if unsubscribed {
+        let context = ConsentContext::from_request(&request, None);
+        if record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEventType::Unsubscribe,
+            &context,
+            &consent.privacy_policy_version,
+            &consent.unsubscribe_wording,
+        )
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+        if enqueue_webhook_event(
+            &mut transaction,
+            WebhookEventType::SubscriberUnsubscribed,
+            subscriber_id,
+        )
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(consent.unsubscribe_wording.clone())
+}
+
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(transaction))]
+async fn mark_subscriber_as_unsubscribed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = $1 AND status <> 'unsubscribed'
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected() == 1)
+}
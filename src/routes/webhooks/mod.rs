@@ -0,0 +1,3 @@
+mod postmark;
+
+pub use postmark::{postmark_webhook, WebhookError};
@@ -0,0 +1,314 @@
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::{ConsentSettings, PostmarkWebhookSettings};
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::domain::SubscriptionStatus;
+use crate::outbound_webhooks::{enqueue_webhook_event, WebhookEventType};
+use crate::routes::error_chain_fmt;
+use crate::utils::constant_time_eq;
+
+/// The payloads Postmark delivers to this endpoint, keyed on `RecordType`.
+/// Anything else (opens, clicks, deliveries, ...) is recorded and ignored.
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    Bounce(BounceEvent),
+    SpamComplaint(BounceEvent),
+    SubscriptionChange(SubscriptionChangeEvent),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BounceEvent {
+    /// `HardBounce`, `SoftBounce`, `SpamComplaint`, ...
+    #[serde(rename = "Type")]
+    bounce_type: String,
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SubscriptionChangeEvent {
+    recipient: String,
+    suppress_sending: bool,
+    /// `HardBounce`, `SpamComplaint` or `ManualSuppression`.
+    suppression_reason: Option<String>,
+}
+
+/// What an event means for the subscriber it is about.
+enum SubscriberUpdate {
+    Bounced,
+    Complained,
+    Unsubscribed,
+}
+
+impl SubscriberUpdate {
+    fn status(&self) -> SubscriptionStatus {
+        match self {
+            SubscriberUpdate::Bounced => SubscriptionStatus::Bounced,
+            SubscriberUpdate::Complained => SubscriptionStatus::Complained,
+            SubscriberUpdate::Unsubscribed => SubscriptionStatus::Unsubscribed,
+        }
+    }
+
+    fn webhook_event_type(&self) -> WebhookEventType {
+        match self {
+            SubscriberUpdate::Bounced => WebhookEventType::SubscriberBounced,
+            SubscriberUpdate::Complained | SubscriberUpdate::Unsubscribed => {
+                WebhookEventType::SubscriberUnsubscribed
+            }
+        }
+    }
+}
+
+impl PostmarkEvent {
+    fn record_type(payload: &serde_json::Value) -> &str {
+        payload["RecordType"].as_str().unwrap_or("Unknown")
+    }
+
+    fn event_type(&self) -> Option<&str> {
+        match self {
+            PostmarkEvent::Bounce(e) | PostmarkEvent::SpamComplaint(e) => Some(&e.bounce_type),
+            PostmarkEvent::SubscriptionChange(e) => e.suppression_reason.as_deref(),
+            PostmarkEvent::Other => None,
+        }
+    }
+
+    fn email(&self) -> Option<&str> {
+        match self {
+            PostmarkEvent::Bounce(e) | PostmarkEvent::SpamComplaint(e) => Some(&e.email),
+            PostmarkEvent::SubscriptionChange(e) => Some(&e.recipient),
+            PostmarkEvent::Other => None,
+        }
+    }
+
+    fn subscriber_update(&self) -> Option<SubscriberUpdate> {
+        match self {
+            // Soft bounces, auto-responders and the like are transient.
+            PostmarkEvent::Bounce(e) => match e.bounce_type.as_str() {
+                "HardBounce" | "BadEmailAddress" => Some(SubscriberUpdate::Bounced),
+                _ => None,
+            },
+            PostmarkEvent::SpamComplaint(_) => Some(SubscriberUpdate::Complained),
+            // Reactivations are recorded, but never resubscribe anybody.
+            PostmarkEvent::SubscriptionChange(e) if e.suppress_sending => {
+                match e.suppression_reason.as_deref() {
+                    Some("HardBounce") => Some(SubscriberUpdate::Bounced),
+                    Some("SpamComplaint") => Some(SubscriberUpdate::Complained),
+                    Some("ManualSuppression") => Some(SubscriberUpdate::Unsubscribed),
+                    _ => None,
+                }
+            }
+            PostmarkEvent::SubscriptionChange(_) | PostmarkEvent::Other => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+            WebhookError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Handle a Postmark webhook",
+    skip(body, request, pool, settings, consent),
+    fields(record_type = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    body: web::Bytes,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    settings: web::Data<PostmarkWebhookSettings>,
+    consent: web::Data<ConsentSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    check_basic_authentication(request.headers(), &settings).map_err(WebhookError::AuthError)?;
+    let payload: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+    let event: PostmarkEvent = serde_json::from_value(payload.clone())
+        .map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+    let record_type = PostmarkEvent::record_type(&payload);
+    tracing::Span::current().record("record_type", tracing::field::display(record_type));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_id = match event.email() {
+        Some(email) => get_subscriber_id_for_update(&mut transaction, email)
+            .await
+            .context("Failed to look up the subscriber.")?,
+        None => None,
+    };
+    store_email_event(&mut transaction, &event, record_type, subscriber_id, &payload)
+        .await
+        .context("Failed to record the email event.")?;
+    if let (Some(subscriber_id), Some(update)) = (subscriber_id, event.subscriber_update()) {
+        let changed = update_subscriber_status(&mut transaction, subscriber_id, &update)
+            .await
+            .context("Failed to update the subscriber status.")?;
+        if changed && matches!(update, SubscriberUpdate::Unsubscribed) {
+            // The subscriber used the provider's own unsubscribe link.
+            let context = ConsentContext {
+                source: Some("postmark".into()),
+                ..Default::default()
+            };
+            record_consent_event(
+                &mut transaction,
+                subscriber_id,
+                ConsentEventType::Unsubscribe,
+                &context,
+                &consent.privacy_policy_version,
+                &consent.unsubscribe_wording,
+            )
+            .await
+            .context("Failed to record the consent event.")?;
+        }
+        if changed {
+            enqueue_webhook_event(&mut transaction, update.webhook_event_type(), subscriber_id)
+                .await?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the webhook transaction.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn check_basic_authentication(
+    headers: &HeaderMap,
+    settings: &PostmarkWebhookSettings,
+) -> Result<(), anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth.")?;
+    let username_matches = constant_time_eq(username, &settings.username);
+    let password_matches = constant_time_eq(password, settings.password.expose_secret());
+    if !(username_matches && password_matches) {
+        anyhow::bail!("Invalid webhook credentials.");
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get subscriber_id for a reported address", skip_all)]
+async fn get_subscriber_id_for_update(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE",
+        email.trim(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Store an email event", skip_all)]
+async fn store_email_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &PostmarkEvent,
+    record_type: &str,
+    subscriber_id: Option<Uuid>,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            email_event_id,
+            provider,
+            record_type,
+            event_type,
+            email,
+            subscriber_id,
+            payload,
+            received_at
+        )
+        VALUES ($1, 'postmark', $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        record_type,
+        event.event_type(),
+        event.email(),
+        subscriber_id,
+        payload,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// A complaint is final: a later bounce or unsubscribe does not overwrite it.
+#[tracing::instrument(name = "Update subscriber status from an email event", skip(transaction, update))]
+async fn update_subscriber_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    update: &SubscriberUpdate,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2
+        WHERE id = $1 AND status <> $2 AND status <> 'complained'
+        "#,
+        subscriber_id,
+        update.status().as_str(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
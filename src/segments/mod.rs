@@ -0,0 +1,374 @@
+//! Segments: the audience of an issue, written in a small language.
+//!
+//! ```text
+//! status = "confirmed" and joined within 30 days
+//!     and in list "rust"
+//!     and (opened any of last 3 issues or clicked any of last 3 issues)
+//! ```
+//!
+//! Conditions combine with `and`, `or`, `not` and parentheses, `and` binding
+//! tighter than `or`. The conditions are:
+//!
+//! - `status`, `email`, `name` or `timezone`, then `=`, `!=`, `contains` or
+//!   `ends_with`, then a string. Comparisons ignore case; subscribers on the
+//!   default timezone have an empty one.
+//! - `joined within <n> days`, `joined before "<date>"` and
+//!   `joined after "<date>"`, with dates such as `2025-01-31` read in UTC.
+//! - `in list "<list>"`.
+//! - `opened any of last <n> issues` and `clicked any of last <n> issues`,
+//!   counting the most recently published issues.
+//!
+//! A definition is parsed into an [`Expr`], validated, and compiled to a
+//! condition on `subscriptions` in which every value is a bind parameter.
+
+mod parser;
+mod sql;
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use sqlx::{PgExecutor, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{ListName, SubscriberTimezone, SubscriptionStatus};
+
+/// Longer definitions are almost certainly a mistake.
+const MAX_DEFINITION_LENGTH: usize = 4096;
+const MAX_CONDITIONS: usize = 50;
+const MAX_NESTING: usize = 16;
+const MAX_STRING_LENGTH: usize = 256;
+/// A hundred years.
+const MAX_DAYS: u32 = 36_500;
+const MAX_LAST_ISSUES: u32 = 100;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SegmentError {
+    #[error("Syntax error at column {column}: {message}")]
+    Syntax { column: usize, message: String },
+    #[error("{0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Compare {
+        field: Field,
+        comparison: Comparison,
+        value: String,
+    },
+    JoinedWithin {
+        days: u32,
+    },
+    /// Joined before the start of the day.
+    JoinedBefore(NaiveDate),
+    /// Joined after the end of the day.
+    JoinedAfter(NaiveDate),
+    InList(String),
+    Engaged {
+        engagement: Engagement,
+        last_issues: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Status,
+    Email,
+    Name,
+    Timezone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equals,
+    NotEquals,
+    Contains,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engagement {
+    Opened,
+    Clicked,
+}
+
+/// A parsed and validated segment definition.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    expr: Expr,
+}
+
+impl Segment {
+    pub fn parse(definition: &str) -> Result<Segment, SegmentError> {
+        if definition.len() > MAX_DEFINITION_LENGTH {
+            return Err(SegmentError::Invalid(format!(
+                "A segment definition cannot be longer than {} bytes.",
+                MAX_DEFINITION_LENGTH
+            )));
+        }
+        let expr = parser::parse(definition)?;
+        validate(&expr)?;
+        Ok(Self { expr })
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Push the segment as a boolean SQL expression over `subscriptions s`.
+    ///
+    /// `sending` is the issue the audience is for: it does not count among
+    /// the last issues a subscriber could have opened or clicked.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>, sending: Option<Uuid>) {
+        self.expr.push_sql(builder, sending);
+    }
+}
+
+fn validate(expr: &Expr) -> Result<(), SegmentError> {
+    let mut conditions = Vec::new();
+    collect_conditions(expr, &mut conditions);
+    if conditions.len() > MAX_CONDITIONS {
+        return Err(SegmentError::Invalid(format!(
+            "A segment cannot have more than {} conditions.",
+            MAX_CONDITIONS
+        )));
+    }
+    conditions.into_iter().try_for_each(validate_condition)
+}
+
+fn collect_conditions<'a>(expr: &'a Expr, conditions: &mut Vec<&'a Condition>) {
+    match expr {
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            collect_conditions(left, conditions);
+            collect_conditions(right, conditions);
+        }
+        Expr::Not(inner) => collect_conditions(inner, conditions),
+        Expr::Condition(condition) => conditions.push(condition),
+    }
+}
+
+fn validate_condition(condition: &Condition) -> Result<(), SegmentError> {
+    let invalid = |message: String| Err(SegmentError::Invalid(message));
+    match condition {
+        Condition::Compare {
+            field,
+            comparison,
+            value,
+        } => {
+            if value.is_empty() || value.chars().count() > MAX_STRING_LENGTH {
+                return invalid(format!(
+                    "Values must be between 1 and {} characters long.",
+                    MAX_STRING_LENGTH
+                ));
+            }
+            let exact = matches!(comparison, Comparison::Equals | Comparison::NotEquals);
+            match field {
+                Field::Status if !exact || SubscriptionStatus::parse(value).is_err() => {
+                    invalid(format!(
+                        "`status` can only be compared with `=` or `!=` to one of: {}.",
+                        SubscriptionStatus::ALL.map(|status| status.as_str()).join(", ")
+                    ))
+                }
+                Field::Timezone if exact => SubscriberTimezone::parse(value.clone())
+                    .map(|_| ())
+                    .map_err(SegmentError::Invalid),
+                _ => Ok(()),
+            }
+        }
+        Condition::JoinedWithin { days } if !(1..=MAX_DAYS).contains(days) => invalid(format!(
+            "`joined within` takes between 1 and {} days.",
+            MAX_DAYS
+        )),
+        Condition::InList(list) => ListName::parse(list.clone())
+            .map(|_| ())
+            .map_err(SegmentError::Invalid),
+        Condition::Engaged { last_issues, .. } if !(1..=MAX_LAST_ISSUES).contains(last_issues) => {
+            invalid(format!(
+                "Engagement can be checked over the last 1 to {} issues.",
+                MAX_LAST_ISSUES
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// How many subscribers a segment matches, and how many of them would
+/// receive an issue sent to it.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct SegmentSize {
+    pub subscribers: i64,
+    pub recipients: i64,
+}
+
+#[tracing::instrument(skip(executor, segment))]
+pub async fn measure<'c, E: PgExecutor<'c>>(
+    executor: E,
+    segment: &Segment,
+) -> Result<SegmentSize, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "SELECT COUNT(*), COUNT(*) FILTER (WHERE s.status = 'confirmed') \
+         FROM subscriptions s WHERE ",
+    );
+    segment.push_sql(&mut builder, None);
+    let (subscribers, recipients) = builder
+        .build_query_as::<(i64, i64)>()
+        .fetch_one(executor)
+        .await?;
+    Ok(SegmentSize {
+        subscribers,
+        recipients,
+    })
+}
+
+/// The confirmed subscribers an issue goes to: all of them, or those in the
+/// issue's segment.
+#[tracing::instrument(skip(transaction))]
+pub async fn get_audience(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let segment = get_issue_segment(transaction, newsletter_issue_id).await?;
+    let mut builder =
+        QueryBuilder::new("SELECT s.id FROM subscriptions s WHERE s.status = 'confirmed'");
+    if let Some(segment) = segment {
+        builder.push(" AND ");
+        segment.push_sql(&mut builder, Some(newsletter_issue_id));
+    }
+    let audience = builder
+        .build_query_scalar::<Uuid>()
+        .fetch_all(&mut **transaction)
+        .await
+        .context("Failed to fetch the audience of the newsletter issue.")?;
+    Ok(audience)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_issue_segment(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Segment>, anyhow::Error> {
+    let definition = sqlx::query!(
+        r#"
+        SELECT g.definition
+        FROM newsletter_issues i
+        JOIN segments g USING (segment_id)
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to fetch the segment of the newsletter issue.")?;
+    definition
+        .map(|d| Segment::parse(&d.definition))
+        .transpose()
+        .context("The stored segment definition is not valid.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparison, Condition, Engagement, Expr, Field, Segment, SegmentError};
+    use claims::{assert_err, assert_ok};
+
+    fn condition(definition: &str) -> Condition {
+        match Segment::parse(definition).unwrap().expr {
+            Expr::Condition(condition) => condition,
+            other => panic!("Not a single condition: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_kind_of_condition_parses() {
+        assert_eq!(
+            condition(r#"email ends_with "@example.com""#),
+            Condition::Compare {
+                field: Field::Email,
+                comparison: Comparison::EndsWith,
+                value: "@example.com".into(),
+            }
+        );
+        assert_eq!(
+            condition("joined within 30 days"),
+            Condition::JoinedWithin { days: 30 }
+        );
+        assert_eq!(
+            condition(r#"joined before "2025-01-31""#),
+            Condition::JoinedBefore(chrono::NaiveDate::from_ymd_opt(2025, 1, 31).unwrap())
+        );
+        assert_eq!(
+            condition("in list 'rust'"),
+            Condition::InList("rust".into())
+        );
+        assert_eq!(
+            condition("clicked any of the last 3 issues"),
+            Condition::Engaged {
+                engagement: Engagement::Clicked,
+                last_issues: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = Segment::parse(r#"in list "a" or in list "b" and not in list "c""#)
+            .unwrap()
+            .expr;
+        let list = |name: &str| Box::new(Expr::Condition(Condition::InList(name.into())));
+        assert_eq!(
+            expr,
+            Expr::Or(
+                list("a"),
+                Box::new(Expr::And(list("b"), Box::new(Expr::Not(list("c"))))),
+            )
+        );
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_offending_token() {
+        assert_eq!(
+            Segment::parse("joined within thirty days").unwrap_err(),
+            SegmentError::Syntax {
+                column: 15,
+                message: "expected a number, found `thirty`.".into(),
+            }
+        );
+        assert_err!(Segment::parse(""));
+        assert_err!(Segment::parse("in list \"rust"));
+        assert_err!(Segment::parse("(in list \"rust\""));
+        assert_err!(Segment::parse("in list \"rust\" in list \"go\""));
+        assert_err!(Segment::parse("joined before \"last tuesday\""));
+        assert_err!(Segment::parse("status; DROP TABLE subscriptions"));
+    }
+
+    #[test]
+    fn semantically_invalid_segments_are_rejected() {
+        assert_err!(Segment::parse(r#"status = "subscribed""#));
+        assert_err!(Segment::parse(r#"status contains "conf""#));
+        assert_ok!(Segment::parse(r#"status = "Confirmed""#));
+        assert_ok!(Segment::parse(r#"status != "unsubscribed" and status != "bounced""#));
+        assert_ok!(Segment::parse(r#"not status = "complained""#));
+        assert_err!(Segment::parse(r#"timezone = "Europe/Atlantis""#));
+        assert_err!(Segment::parse(r#"in list "Rust Weekly""#));
+        assert_err!(Segment::parse(r#"email contains """#));
+        assert_err!(Segment::parse("joined within 0 days"));
+        assert_err!(Segment::parse("opened any of last 1000 issues"));
+        assert_ok!(Segment::parse(r#"timezone contains "Europe/""#));
+    }
+
+    #[test]
+    fn deeply_nested_or_oversized_segments_are_rejected() {
+        let nested = format!("{}in list \"rust\"{}", "(".repeat(100), ")".repeat(100));
+        assert_err!(Segment::parse(&nested));
+        let negated = format!("{}in list \"rust\"", "not ".repeat(100));
+        assert_err!(Segment::parse(&negated));
+        let long = vec!["joined within 1 days"; 51].join(" or ");
+        assert_err!(Segment::parse(&long));
+    }
+}
@@ -0,0 +1,317 @@
+//! A recursive descent parser for segment definitions.
+
+use chrono::NaiveDate;
+
+use super::{Comparison, Condition, Engagement, Expr, Field, SegmentError, MAX_NESTING};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    /// Keywords and field names, lowercased.
+    Word(String),
+    String(String),
+    Number(String),
+    LeftParen,
+    RightParen,
+    Equals,
+    NotEquals,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    /// 1-based, in characters.
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word) => format!("`{}`", word),
+            TokenKind::String(value) => format!("\"{}\"", value),
+            TokenKind::Number(number) => format!("`{}`", number),
+            TokenKind::LeftParen => "`(`".into(),
+            TokenKind::RightParen => "`)`".into(),
+            TokenKind::Equals => "`=`".into(),
+            TokenKind::NotEquals => "`!=`".into(),
+        }
+    }
+}
+
+fn syntax_error(column: usize, message: String) -> SegmentError {
+    SegmentError::Syntax { column, message }
+}
+
+fn tokenize(definition: &str) -> Result<Vec<Token>, SegmentError> {
+    let mut tokens = Vec::new();
+    let mut chars = definition.chars().enumerate().peekable();
+    while let Some((index, c)) = chars.next() {
+        let column = index + 1;
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '=' => TokenKind::Equals,
+            '!' if chars.next_if(|(_, c)| *c == '=').is_some() => TokenKind::NotEquals,
+            '"' | '\'' => {
+                let mut value = String::new();
+                let mut terminated = false;
+                while let Some((_, next)) = chars.next() {
+                    match next {
+                        '\\' => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => break,
+                        },
+                        closing if closing == c => {
+                            terminated = true;
+                            break;
+                        }
+                        other => value.push(other),
+                    }
+                }
+                if !terminated {
+                    return Err(syntax_error(column, "unterminated string.".into()));
+                }
+                TokenKind::String(value)
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some((_, digit)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    number.push(digit);
+                }
+                TokenKind::Number(number)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = c.to_ascii_lowercase().to_string();
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    word.push(c.to_ascii_lowercase());
+                }
+                TokenKind::Word(word)
+            }
+            other => {
+                return Err(syntax_error(
+                    column,
+                    format!("unexpected character `{}`.", other),
+                ))
+            }
+        };
+        tokens.push(Token { kind, column });
+    }
+    Ok(tokens)
+}
+
+pub(super) fn parse(definition: &str) -> Result<Expr, SegmentError> {
+    let mut parser = Parser {
+        tokens: tokenize(definition)?,
+        position: 0,
+        end_column: definition.chars().count() + 1,
+        depth: 0,
+    };
+    let expr = parser.or()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected("`and`, `or` or the end of the segment"));
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    end_column: usize,
+    /// How many `not`s and parentheses we are in.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// An error about the next token, which was not what we `expected`.
+    fn unexpected(&self, expected: &str) -> SegmentError {
+        match self.peek() {
+            Some(token) => syntax_error(
+                token.column,
+                format!("expected {}, found {}.", expected, token.describe()),
+            ),
+            None => syntax_error(
+                self.end_column,
+                format!("expected {}, found the end of the segment.", expected),
+            ),
+        }
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        let found = self.peek().is_some_and(|t| &t.kind == kind);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        self.eat(&TokenKind::Word(word.into()))
+    }
+
+    fn expect_word(&mut self, words: &[&str]) -> Result<(), SegmentError> {
+        if words.iter().any(|word| self.eat_word(word)) {
+            return Ok(());
+        }
+        Err(self.unexpected(&format!("`{}`", words[0])))
+    }
+
+    fn or(&mut self) -> Result<Expr, SegmentError> {
+        let mut expr = self.and()?;
+        while self.eat_word("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, SegmentError> {
+        let mut expr = self.unary()?;
+        while self.eat_word("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, SegmentError> {
+        let nests = self.peek().is_some_and(|t| {
+            t.kind == TokenKind::LeftParen || t.kind == TokenKind::Word("not".into())
+        });
+        if !nests {
+            return self.condition().map(Expr::Condition);
+        }
+        if self.depth == MAX_NESTING {
+            let column = self.peek().map_or(self.end_column, |t| t.column);
+            return Err(syntax_error(
+                column,
+                "the segment is nested too deeply.".into(),
+            ));
+        }
+        self.depth += 1;
+        let expr = if self.eat_word("not") {
+            Expr::Not(Box::new(self.unary()?))
+        } else {
+            self.eat(&TokenKind::LeftParen);
+            let inner = self.or()?;
+            if !self.eat(&TokenKind::RightParen) {
+                return Err(self.unexpected("`)`"));
+            }
+            inner
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn condition(&mut self) -> Result<Condition, SegmentError> {
+        let field = match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Word(word)) => match word.as_str() {
+                "status" => Some(Field::Status),
+                "email" => Some(Field::Email),
+                "name" => Some(Field::Name),
+                "timezone" => Some(Field::Timezone),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(field) = field {
+            self.position += 1;
+            return self.comparison(field);
+        }
+        if self.eat_word("joined") {
+            return self.joined();
+        }
+        if self.eat_word("in") {
+            self.expect_word(&["list"])?;
+            return Ok(Condition::InList(self.string()?));
+        }
+        let engagement = if self.eat_word("opened") {
+            Engagement::Opened
+        } else if self.eat_word("clicked") {
+            Engagement::Clicked
+        } else {
+            return Err(self.unexpected("a condition"));
+        };
+        self.expect_word(&["any"])?;
+        self.expect_word(&["of"])?;
+        self.eat_word("the");
+        self.expect_word(&["last"])?;
+        let last_issues = self.number()?;
+        self.expect_word(&["issues", "issue"])?;
+        Ok(Condition::Engaged {
+            engagement,
+            last_issues,
+        })
+    }
+
+    fn comparison(&mut self, field: Field) -> Result<Condition, SegmentError> {
+        let comparison = if self.eat(&TokenKind::Equals) {
+            Comparison::Equals
+        } else if self.eat(&TokenKind::NotEquals) {
+            Comparison::NotEquals
+        } else if self.eat_word("contains") {
+            Comparison::Contains
+        } else if self.eat_word("ends_with") {
+            Comparison::EndsWith
+        } else {
+            return Err(self.unexpected("`=`, `!=`, `contains` or `ends_with`"));
+        };
+        Ok(Condition::Compare {
+            field,
+            comparison,
+            value: self.string()?,
+        })
+    }
+
+    fn joined(&mut self) -> Result<Condition, SegmentError> {
+        if self.eat_word("within") {
+            let days = self.number()?;
+            self.expect_word(&["days", "day"])?;
+            Ok(Condition::JoinedWithin { days })
+        } else if self.eat_word("before") {
+            Ok(Condition::JoinedBefore(self.date()?))
+        } else if self.eat_word("after") {
+            Ok(Condition::JoinedAfter(self.date()?))
+        } else {
+            Err(self.unexpected("`within`, `before` or `after`"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SegmentError> {
+        match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::String(value)) => {
+                let value = value.clone();
+                self.position += 1;
+                Ok(value)
+            }
+            _ => Err(self.unexpected("a quoted string")),
+        }
+    }
+
+    fn number(&mut self) -> Result<u32, SegmentError> {
+        let Some(TokenKind::Number(number)) = self.peek().map(|t| &t.kind) else {
+            return Err(self.unexpected("a number"));
+        };
+        let parsed = number.parse();
+        let column = self.tokens[self.position].column;
+        self.position += 1;
+        parsed.map_err(|_| syntax_error(column, "the number is too large.".into()))
+    }
+
+    fn date(&mut self) -> Result<NaiveDate, SegmentError> {
+        let column = self.peek().map_or(self.end_column, |t| t.column);
+        let value = self.string()?;
+        NaiveDate::parse_from_str(&value, "%Y-%m-%d").map_err(|_| {
+            syntax_error(
+                column,
+                format!(
+                    "expected a date such as \"2025-01-31\", found \"{}\".",
+                    value
+                ),
+            )
+        })
+    }
+}
@@ -0,0 +1,182 @@
+//! Compile segments to SQL.
+//!
+//! Only fixed fragments are pushed as SQL text: every value from the
+//! definition goes through `push_bind`.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use super::{Comparison, Condition, Engagement, Expr, Field};
+
+impl Expr {
+    pub(super) fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>, sending: Option<Uuid>) {
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let operator = if matches!(self, Expr::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                builder.push("(");
+                left.push_sql(builder, sending);
+                builder.push(operator);
+                right.push_sql(builder, sending);
+                builder.push(")");
+            }
+            Expr::Not(inner) => {
+                builder.push("NOT ");
+                inner.push_sql(builder, sending);
+            }
+            Expr::Condition(condition) => {
+                // Conditions are never NULL, so that `NOT` means "everybody else".
+                builder.push("(");
+                condition.push_sql(builder, sending);
+                builder.push(")");
+            }
+        }
+    }
+}
+
+impl Field {
+    fn column(&self) -> &'static str {
+        match self {
+            Field::Status => "lower(s.status)",
+            Field::Email => "lower(s.email)",
+            Field::Name => "lower(s.name)",
+            Field::Timezone => "lower(COALESCE(s.timezone, ''))",
+        }
+    }
+}
+
+impl Engagement {
+    fn table(&self) -> &'static str {
+        match self {
+            Engagement::Opened => "issue_opens",
+            Engagement::Clicked => "issue_clicks",
+        }
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+impl Condition {
+    fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>, sending: Option<Uuid>) {
+        match self {
+            Condition::Compare {
+                field,
+                comparison,
+                value,
+            } => {
+                let value = value.to_lowercase();
+                match comparison {
+                    Comparison::Equals | Comparison::NotEquals => {
+                        builder.push(field.column());
+                        builder.push(if *comparison == Comparison::Equals {
+                            " = "
+                        } else {
+                            " <> "
+                        });
+                        builder.push_bind(value);
+                    }
+                    Comparison::Contains => {
+                        builder.push("strpos(");
+                        builder.push(field.column());
+                        builder.push(", ");
+                        builder.push_bind(value);
+                        builder.push(") > 0");
+                    }
+                    Comparison::EndsWith => {
+                        builder.push("starts_with(reverse(");
+                        builder.push(field.column());
+                        builder.push("), reverse(");
+                        builder.push_bind(value);
+                        builder.push("))");
+                    }
+                }
+            }
+            Condition::JoinedWithin { days } => {
+                builder.push("s.subscribed_at >= now() - make_interval(days => ");
+                builder.push_bind(*days as i32);
+                builder.push(")");
+            }
+            Condition::JoinedBefore(date) => {
+                builder.push("s.subscribed_at < ");
+                builder.push_bind(start_of_day(*date));
+            }
+            Condition::JoinedAfter(date) => {
+                builder.push("s.subscribed_at >= ");
+                builder.push_bind(start_of_day(*date) + chrono::Duration::days(1));
+            }
+            Condition::InList(list) => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM subscriber_lists l \
+                     WHERE l.subscriber_id = s.id AND l.list_name = ",
+                );
+                builder.push_bind(list.clone());
+                builder.push(")");
+            }
+            Condition::Engaged {
+                engagement,
+                last_issues,
+            } => {
+                builder.push("EXISTS (SELECT 1 FROM ");
+                builder.push(engagement.table());
+                builder.push(
+                    " e WHERE e.subscriber_id = s.id AND e.newsletter_issue_id IN (\
+                     SELECT i.newsletter_issue_id FROM newsletter_issues i \
+                     WHERE i.published_at IS NOT NULL AND i.newsletter_issue_id IS DISTINCT FROM ",
+                );
+                builder.push_bind(sending);
+                builder.push(" ORDER BY i.published_at DESC LIMIT ");
+                builder.push_bind(*last_issues as i64);
+                builder.push("))");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::segments::Segment;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn compile(definition: &str) -> String {
+        let mut builder = QueryBuilder::<Postgres>::new("");
+        Segment::parse(definition)
+            .unwrap()
+            .push_sql(&mut builder, None);
+        builder.sql().to_owned()
+    }
+
+    #[test]
+    fn values_are_bound_rather_than_inlined() {
+        let sql = compile(
+            r#"name contains "'; DROP TABLE subscriptions; --" and in list "rust"
+                and joined within 30 days and opened any of last 3 issues"#,
+        );
+
+        assert!(!sql.contains("DROP"), "{}", sql);
+        assert!(!sql.contains("rust"), "{}", sql);
+        assert!(!sql.contains("30"), "{}", sql);
+        for parameter in ["$1", "$2", "$3", "$4", "$5"] {
+            assert!(
+                sql.contains(parameter),
+                "{} is missing from {}",
+                parameter,
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn the_structure_of_the_expression_is_preserved() {
+        assert_eq!(
+            compile(r#"not (in list "a" or email = "x") and status = "confirmed""#),
+            "(NOT ((EXISTS (SELECT 1 FROM subscriber_lists l WHERE l.subscriber_id = s.id \
+             AND l.list_name = $1)) OR (lower(s.email) = $2)) AND (lower(s.status) = $3))"
+        );
+    }
+}
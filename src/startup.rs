@@ -1,14 +1,26 @@
 
-use actix_web::{HttpResponse, Responder};
+use actix_session::storage::CookieSessionStore;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
+use actix_web::middleware::from_fn;
+use actix_web::HttpResponse;
 use actix_web::{dev::Server, web, App,  HttpServer};
 use actix_web::web::Data;
+use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 use sqlx::{ postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 use std::{ net::TcpListener};
-use crate::configuration::{DatabaseSettings};
+use std::sync::Arc;
+use crate::authentication::{reject_anonymous_users, require_editor, require_owner};
+use crate::clock::{Clock, SystemClock};
+use crate::configuration::DatabaseSettings;
+use crate::consent::ClientAddressPolicy;
+use crate::request_id::{assign_request_id, RequestIdPolicy, RequestIdRootSpanBuilder};
 
 use crate::{configuration::Settings, email_client::{ EmailClient}, routes};
+use crate::email_templates::EmailTemplates;
+use crate::tracking::Tracker;
 
 pub struct Application {
     port: u16,
@@ -16,18 +28,24 @@ pub struct Application {
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        Self::build_with_clock(configuration, Arc::new(SystemClock)).await
+    }
+
+    /// Like `build`, but reading the time from `clock`: tests use it to make
+    /// time-based codes deterministic.
+    pub async fn build_with_clock(
+        configuration: Settings,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, anyhow::Error> {
+        // Rather than panicking on the first bad value below.
+        configuration.validate()?;
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration.email_client.sender()
-            .expect("Invalid email configuration");
+        let email_client = configuration.email_client.clone().client();
 
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout
-        );
+        let templates = EmailTemplates::load(
+            std::path::Path::new(&configuration.application.templates_directory)
+        )?;
 
         let address = format!(
             "{}:{}", 
@@ -37,7 +55,7 @@ impl Application {
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
         
-        let server = run(listener, connection_pool, email_client)?;
+        let server = run(listener, connection_pool, email_client, templates, clock, configuration)?;
         
         Ok(Self {
             port,
@@ -54,21 +72,259 @@ impl Application {
     }
 }
 
+/// Wrapper type so that the base URL can be retrieved from the app data
+/// without clashing with other `String`s.
+pub struct ApplicationBaseUrl(pub String);
+
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient
+    email_client: EmailClient,
+    templates: EmailTemplates,
+    clock: Arc<dyn Clock>,
+    configuration: Settings,
 ) -> Result<Server, std::io::Error> {
     let db_pool = web::Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let tracker = Data::new(Tracker::new(
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret.clone(),
+    ));
+    let request_id_policy = Data::new(RequestIdPolicy {
+        trust_header: configuration.application.trust_request_id_header,
+    });
+    let client_address_policy = Data::new(ClientAddressPolicy {
+        trust_forwarded_headers: configuration.application.trust_forwarded_headers,
+    });
+    let base_url = Data::new(ApplicationBaseUrl(configuration.application.base_url));
+    let consent = Data::new(configuration.consent);
+    let delivery = Data::new(configuration.delivery);
+    let archive = Data::new(configuration.archive);
+    let templates = Data::new(templates);
+    let clock: Data<dyn Clock> = Data::from(clock);
+    let postmark_webhook = Data::new(configuration.email_client.postmark_webhook);
+    let secret_key = Key::from(configuration.application.hmac_secret.expose_secret().as_bytes());
     let server = HttpServer::new(move|| {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                secret_key.clone(),
+            ))
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            // Outermost, so that everything within sees the request id.
+            .wrap(from_fn(assign_request_id))
             .route("/health_check", web::get().to(routes::health_check))
+            .route("/login", web::get().to(routes::login_form))
+            .route("/login", web::post().to(routes::login))
+            .route("/login/totp", web::get().to(routes::totp_form))
+            .route("/login/totp", web::post().to(routes::verify_totp))
+            .route("/password_reset", web::get().to(routes::password_reset_request_form))
+            .route("/password_reset", web::post().to(routes::request_password_reset))
+            .route("/password_reset/confirm", web::get().to(routes::password_reset_form))
+            .route("/password_reset/confirm", web::post().to(routes::reset_password))
+            .route("/invitations/accept", web::get().to(routes::accept_invitation_form))
+            .route("/invitations/accept", web::post().to(routes::accept_invitation))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    // Any admin can read; the role each route needs beyond
+                    // that is checked here, and only here.
+                    .route("/dashboard", web::get().to(routes::admin_dashboard))
+                    .route("/logout", web::post().to(routes::log_out))
+                    .route("/password", web::post().to(routes::change_password))
+                    .route("/totp", web::post().to(routes::start_totp_enrolment))
+                    .route("/totp/confirm", web::post().to(routes::confirm_totp_enrolment))
+                    .route(
+                        "/issues",
+                        web::post().to(routes::create_issue).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}",
+                        web::put().to(routes::update_issue).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/preview",
+                        web::get().to(routes::preview_issue),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/publish",
+                        web::post().to(routes::publish_issue).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/schedule",
+                        web::put().to(routes::schedule_issue).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/schedule",
+                        web::delete()
+                            .to(routes::cancel_scheduled_issue)
+                            .wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/visibility",
+                        web::put()
+                            .to(routes::set_issue_visibility)
+                            .wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/ab_test",
+                        web::put().to(routes::configure_ab_test).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/ab_test",
+                        web::delete().to(routes::remove_ab_test).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/stats",
+                        web::get().to(routes::issue_stats),
+                    )
+                    .route("/segments", web::get().to(routes::list_segments))
+                    .route(
+                        "/segments",
+                        web::post().to(routes::create_segment).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/segments/preview",
+                        web::post()
+                            .to(routes::preview_segment_definition)
+                            .wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/segments/{segment_id}",
+                        web::put().to(routes::update_segment).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/segments/{segment_id}",
+                        web::delete().to(routes::delete_segment).wrap(from_fn(require_editor)),
+                    )
+                    .route(
+                        "/segments/{segment_id}/preview",
+                        web::get().to(routes::preview_segment),
+                    )
+                    .route("/attributes", web::get().to(routes::list_attributes))
+                    .route(
+                        "/attributes/{name}",
+                        web::put().to(routes::define_attribute).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/attributes/{name}",
+                        web::delete().to(routes::delete_attribute).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/subscribers/import",
+                        web::post().to(routes::import_subscribers).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/attributes",
+                        web::put()
+                            .to(routes::update_subscriber_attributes)
+                            .wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/consent_events",
+                        web::get()
+                            .to(routes::subscriber_consent_history)
+                            .wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/webhooks",
+                        web::get().to(routes::list_webhook_endpoints).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/webhooks",
+                        web::post().to(routes::create_webhook_endpoint).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/webhooks/{endpoint_id}",
+                        web::delete()
+                            .to(routes::delete_webhook_endpoint)
+                            .wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/webhooks/{endpoint_id}/deliveries",
+                        web::get().to(routes::webhook_delivery_log).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/api_keys",
+                        web::get().to(routes::list_api_keys).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/api_keys",
+                        web::post().to(routes::create_api_key).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/api_keys/{api_key_id}",
+                        web::delete().to(routes::revoke_api_key).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/invitations",
+                        web::post().to(routes::invite_admin).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/audit_log",
+                        web::get().to(routes::list_audit_log).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/log_filter",
+                        web::get().to(routes::get_log_filter).wrap(from_fn(require_owner)),
+                    )
+                    .route(
+                        "/log_filter",
+                        web::put().to(routes::set_log_filter).wrap(from_fn(require_owner)),
+                    ),
+            )
+            // Machine clients authenticate each request with an API key: the
+            // handlers check it, and the scope it needs, themselves.
+            .service(
+                web::scope("/api/v1")
+                    .route("/issues", web::post().to(routes::create_issue))
+                    .route("/issues/{newsletter_issue_id}", web::put().to(routes::update_issue))
+                    .route(
+                        "/issues/{newsletter_issue_id}/publish",
+                        web::post().to(routes::publish_issue),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/schedule",
+                        web::put().to(routes::schedule_issue),
+                    )
+                    .route("/subscribers/import", web::post().to(routes::import_subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}/attributes",
+                        web::put().to(routes::update_subscriber_attributes),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/consent_events",
+                        web::get().to(routes::subscriber_consent_history),
+                    ),
+            )
             .route("/subscriptions", web::post().to(routes::subscribe))
+            .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .route("/subscriptions/unsubscribe", web::get().to(routes::unsubscribe))
+            .route("/subscriptions/preferences", web::get().to(routes::get_preferences))
+            .route("/subscriptions/preferences", web::post().to(routes::update_preferences))
+            .route("/me/data", web::get().to(routes::export_subscriber_data))
+            .route("/me/data/request", web::post().to(routes::request_data_access))
+            .route("/me/data/erase", web::get().to(routes::erase_subscriber_data_form))
+            .route("/me/data/erase", web::post().to(routes::erase_subscriber_data))
+            .route("/webhooks/email/postmark", web::post().to(routes::postmark_webhook))
+            .route("/archive", web::get().to(routes::archive))
+            .route("/archive/feed.xml", web::get().to(routes::archive_feed))
+            .route("/archive/{slug}", web::get().to(routes::archived_issue))
+            .route("/o/{token}", web::get().to(routes::track_open))
+            .route("/r/{token}", web::get().to(routes::track_click))
             .route("/test", web::post().to(test_handler))
             .app_data(db_pool.clone())
+            .app_data(request_id_policy.clone())
+            .app_data(client_address_policy.clone())
             .app_data(email_client.clone())
+            .app_data(base_url.clone())
+            .app_data(consent.clone())
+            .app_data(delivery.clone())
+            .app_data(archive.clone())
+            .app_data(templates.clone())
+            .app_data(postmark_webhook.clone())
+            .app_data(tracker.clone())
+            .app_data(clock.clone())
     })
     .listen(listener)?
     .run();
@@ -88,29 +344,6 @@ pub async fn test_handler(params: web::Form<MyParams>) -> HttpResponse{
 }
 
 
-pub async fn build(configuration: Settings) -> Result<Server, std::io::Error> {
-    let connection_pool = PgPoolOptions::new()
-        .connect_lazy_with(configuration.database.with_db());
-
-    let sender_email = configuration.email_client.sender()
-        .expect("Invalid email configuration");
-    let timeout = configuration.email_client.timeout();
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender_email,
-        configuration.email_client.authorization_token,
-        timeout
-    );
-
-    let address = format!(
-        "{}:{}", 
-        configuration.application.host, 
-        configuration.application.port
-    );
-    let listener = TcpListener::bind(address);
-    run(listener?, connection_pool, email_client)
-}
-
 pub fn get_connection_pool(
     configuration: &DatabaseSettings
 ) -> PgPool {
@@ -4,33 +4,293 @@ use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tokio::task::JoinHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
 
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
 
 
 
 
 
+/// The subscriber, and the handle to change its filter while it runs.
 pub fn get_subscribe<Sink>(
     name: String,
     env_filter: String,
     sink: Sink
-)  -> impl Subscriber + Sync + Send 
+)  -> (impl Subscriber + Sync + Send, LogFilter)
 	where 
 	Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static
 {
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
     
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
-        .with(formatting_layer)
-    
+        .with(formatting_layer);
+    (subscriber, LogFilter::new(handle))
 }
 
-pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync, log_filter: LogFilter) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
-}
\ No newline at end of file
+    let _ = GLOBAL_LOG_FILTER.set(log_filter);
+}
+
+static GLOBAL_LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+/// The filter of the subscriber installed by `init_subscriber`, if any.
+pub fn global_log_filter() -> Option<&'static LogFilter> {
+    GLOBAL_LOG_FILTER.get()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LogFilterError {
+    #[error("Invalid filter directive: {0}")]
+    InvalidDirective(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct LogFilterStatus {
+    /// In `RUST_LOG` syntax, e.g. `info,zero2prod::outbound_webhooks=debug`.
+    pub directive: String,
+    /// When a temporary directive gives way to the one before it.
+    pub reverts_at: Option<DateTime<Utc>>,
+}
+
+struct TemporaryFilter {
+    previous_directive: String,
+    reverts_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct LogFilterState {
+    /// Bumped on every change, so that a pending revert can tell whether
+    /// the filter it was meant to undo is still in place.
+    generation: u64,
+    temporary: Option<TemporaryFilter>,
+}
+
+/// Changes which spans and events are recorded, without a restart: to
+/// get the debug logs of one module in production for a while.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    state: Arc<Mutex<LogFilterState>>,
+}
+
+impl LogFilter {
+    fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self {
+            handle,
+            state: Arc::default(),
+        }
+    }
+
+    fn current_directive(&self) -> Result<String, anyhow::Error> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| anyhow::anyhow!("The subscriber is gone: {}", e))
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, LogFilterState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn status(&self) -> Result<LogFilterStatus, anyhow::Error> {
+        let state = self.lock_state();
+        Ok(LogFilterStatus {
+            directive: self.current_directive()?,
+            reverts_at: state.temporary.as_ref().map(|t| t.reverts_at),
+        })
+    }
+
+    /// Replace the filter with `directive`. With a `duration`, the filter in
+    /// place before the first of a series of temporary ones comes back once
+    /// it has elapsed; a permanent change cancels any pending revert.
+    pub fn set(
+        &self,
+        directive: &str,
+        duration: Option<Duration>,
+    ) -> Result<LogFilterStatus, LogFilterError> {
+        let filter = EnvFilter::try_new(directive)
+            .map_err(|e| LogFilterError::InvalidDirective(e.to_string()))?;
+        let mut state = self.lock_state();
+        let previous_directive = match state.temporary.take() {
+            Some(temporary) => temporary.previous_directive,
+            None => self.current_directive()?,
+        };
+        self.handle
+            .reload(filter)
+            .map_err(|e| anyhow::anyhow!("Failed to reload the log filter: {}", e))?;
+        state.generation += 1;
+        if let Some(duration) = duration {
+            let reverts_at = Utc::now()
+                + chrono::Duration::from_std(duration)
+                    .map_err(|e| LogFilterError::UnexpectedError(e.into()))?;
+            state.temporary = Some(TemporaryFilter {
+                previous_directive,
+                reverts_at,
+            });
+            let log_filter = self.clone();
+            let generation = state.generation;
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                log_filter.revert(generation);
+            });
+        }
+        drop(state);
+        Ok(self.status()?)
+    }
+
+    fn revert(&self, generation: u64) {
+        let mut state = self.lock_state();
+        if state.generation != generation {
+            return;
+        }
+        let Some(temporary) = state.temporary.take() else {
+            return;
+        };
+        // It was a valid filter when it was replaced.
+        let filter = EnvFilter::new(&temporary.previous_directive);
+        match self.handle.reload(filter) {
+            Ok(()) => {
+                state.generation += 1;
+                tracing::info!(
+                    directive = %temporary.previous_directive,
+                    "The temporary log filter has expired."
+                );
+            }
+            Err(e) => tracing::error!(error = %e, "Failed to restore the log filter."),
+        }
+    }
+}
+
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+/// Masked unless the configuration says otherwise, so that nothing leaks
+/// before it has been read.
+static REDACT_PII: AtomicBool = AtomicBool::new(true);
+
+/// Whether logs mask subscribers' email addresses and names, see
+/// `TelemetrySettings`.
+pub fn set_pii_redaction(enabled: bool) {
+    REDACT_PII.store(enabled, Ordering::Relaxed);
+}
+
+fn redacting_pii() -> bool {
+    REDACT_PII.load(Ordering::Relaxed)
+}
+
+/// An email address as it may appear in logs: `u***@example.com`. Enough
+/// to tell a typo in the domain, not enough to identify somebody.
+pub struct RedactedEmail<'a>(pub &'a str);
+
+impl std::fmt::Display for RedactedEmail<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !redacting_pii() {
+            return f.write_str(self.0);
+        }
+        match self.0.rsplit_once('@') {
+            Some((local, domain)) => match local.chars().next() {
+                Some(first) => write!(f, "{}***@{}", first, domain),
+                None => write!(f, "***@{}", domain),
+            },
+            None => f.write_str("***"),
+        }
+    }
+}
+
+/// A name as it may appear in logs: not at all.
+pub struct RedactedName<'a>(pub &'a str);
+
+impl std::fmt::Display for RedactedName<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if redacting_pii() {
+            f.write_str("[redacted]")
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_subscribe, set_pii_redaction, RedactedEmail, RedactedName};
+    use crate::domain::{SubscriberEmail, SubscriberName};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps whatever the formatting layer writes.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn emails_keep_their_first_letter_and_domain() {
+        set_pii_redaction(true);
+
+        assert_eq!(
+            RedactedEmail("ursula@example.com").to_string(),
+            "u***@example.com"
+        );
+        assert_eq!(RedactedEmail("@example.com").to_string(), "***@example.com");
+        assert_eq!(RedactedEmail("not an address").to_string(), "***");
+        assert_eq!(RedactedName("Ursula Le Guin").to_string(), "[redacted]");
+    }
+
+    #[test]
+    fn subscriber_details_logged_by_mistake_are_masked() {
+        set_pii_redaction(true);
+        let logs = CapturedLogs::default();
+        let (subscriber, _) = get_subscribe("test".into(), "info".into(), logs.clone());
+        let email = SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap();
+        let name = SubscriberName::parse("Ursula Le Guin".into()).unwrap();
+        let invalid = SubscriberEmail::parse("ursula_le_guin@@gmail.com".into()).unwrap_err();
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(?email, ?name, "Saving new subscriber details in the database");
+            tracing::error!(error.message = %invalid, "Failed to parse an email address");
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("u***@gmail.com"));
+        assert!(!logs.contains("ursula_le_guin"));
+        assert!(!logs.contains("Ursula Le Guin"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Open and click tracking for newsletter issues.
+//!
+//! Every tracked URL carries a token naming the issue, the recipient and,
+//! for clicks, the destination. Tokens are signed with a key derived from
+//! the application's HMAC secret: without a valid signature the redirect
+//! endpoint would forward anybody anywhere on our behalf. The key is derived
+//! rather than the secret used as is, since the secret also signs session
+//! cookies.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret, SecretVec};
+use sha2::Sha256;
+use uuid::Uuid;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrackedEvent {
+    Open {
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    },
+    Click {
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: String,
+    },
+}
+
+/// What the tracking key is derived for, so that it differs from any other
+/// key derived from the same secret.
+const KEY_PURPOSE: &[u8] = b"link-tracking";
+
+pub struct Tracker {
+    base_url: String,
+    key: SecretVec<u8>,
+}
+
+impl Tracker {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        let key = hmac_sha256(hmac_secret.expose_secret().as_bytes(), KEY_PURPOSE)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        Self {
+            base_url,
+            key: SecretVec::new(key),
+        }
+    }
+
+    pub fn open_url(&self, newsletter_issue_id: Uuid, subscriber_id: Uuid) -> String {
+        let payload = format!("open|{}|{}", newsletter_issue_id, subscriber_id);
+        format!("{}/o/{}", self.base_url, self.sign(&payload))
+    }
+
+    pub fn click_url(&self, newsletter_issue_id: Uuid, subscriber_id: Uuid, url: &str) -> String {
+        let payload = format!("click|{}|{}|{}", newsletter_issue_id, subscriber_id, url);
+        format!("{}/r/{}", self.base_url, self.sign(&payload))
+    }
+
+    /// Route every web link of an HTML body through the click redirect and
+    /// add an open-tracking pixel at the end.
+    ///
+    /// Anything that is not an absolute http(s) link (`mailto:`, anchors,
+    /// placeholders filled in later) is left alone.
+    pub fn track_html(&self, html: &str, newsletter_issue_id: Uuid, subscriber_id: Uuid) -> String {
+        let mut tracked = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find("href=\"") {
+            let value_start = start + "href=\"".len();
+            let Some(value_length) = rest[value_start..].find('"') else {
+                break;
+            };
+            let value = &rest[value_start..value_start + value_length];
+            tracked.push_str(&rest[..value_start]);
+            let url = unescape_attribute(value);
+            if is_web_url(&url) {
+                tracked.push_str(&self.click_url(newsletter_issue_id, subscriber_id, &url));
+            } else {
+                tracked.push_str(value);
+            }
+            rest = &rest[value_start + value_length..];
+        }
+        tracked.push_str(rest);
+
+        let pixel = format!(
+            r#"<img src="{}" width="1" height="1" alt="" style="display:none">"#,
+            self.open_url(newsletter_issue_id, subscriber_id)
+        );
+        match tracked.rfind("</body>") {
+            Some(position) => tracked.insert_str(position, &pixel),
+            None => tracked.push_str(&pixel),
+        }
+        tracked
+    }
+
+    /// Check the signature of a token and decode what it stands for.
+    pub fn verify(&self, token: &str) -> Result<TrackedEvent, anyhow::Error> {
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| anyhow::anyhow!("The tracking token is not signed."))?;
+        let payload = URL_SAFE_NO_PAD.decode(payload)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature)?;
+        self.mac(&payload).verify_slice(&signature)?;
+
+        let payload = String::from_utf8(payload)?;
+        let mut parts = payload.splitn(4, '|');
+        let kind = parts.next();
+        let newsletter_issue_id = parts.next().unwrap_or_default().parse()?;
+        let subscriber_id = parts.next().unwrap_or_default().parse()?;
+        match (kind, parts.next()) {
+            (Some("open"), None) => Ok(TrackedEvent::Open {
+                newsletter_issue_id,
+                subscriber_id,
+            }),
+            (Some("click"), Some(url)) if is_web_url(url) => Ok(TrackedEvent::Click {
+                newsletter_issue_id,
+                subscriber_id,
+                url: url.to_owned(),
+            }),
+            _ => anyhow::bail!("The tracking token is malformed."),
+        }
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        let signature = self.mac(payload.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        hmac_sha256(self.key.expose_secret(), payload)
+    }
+}
+
+fn hmac_sha256(key: &[u8], payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    mac
+}
+
+fn is_web_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Undo the escaping of an HTML attribute value, as produced by our
+/// templates and the Markdown sanitizer.
+fn unescape_attribute(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tracking::{TrackedEvent, Tracker};
+    use claims::assert_err;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn tracker(secret: &str) -> Tracker {
+        Tracker::new("https://newsletter.example.com".into(), Secret::new(secret.into()))
+    }
+
+    fn token(url: &str) -> &str {
+        url.rsplit('/').next().unwrap()
+    }
+
+    #[test]
+    fn click_tokens_round_trip() {
+        let tracker = tracker("secret");
+        let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+        let url = tracker.click_url(issue, subscriber, "https://example.com/a?b=c|d");
+
+        assert_eq!(
+            tracker.verify(token(&url)).unwrap(),
+            TrackedEvent::Click {
+                newsletter_issue_id: issue,
+                subscriber_id: subscriber,
+                url: "https://example.com/a?b=c|d".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let url = tracker("another secret").click_url(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "https://evil.example.com",
+        );
+
+        assert_err!(tracker("secret").verify(token(&url)));
+    }
+
+    #[test]
+    fn tokens_signed_with_the_secret_itself_are_rejected() {
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use base64::Engine;
+        use hmac::{Hmac, Mac};
+
+        let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+        let payload = format!("click|{}|{}|https://evil.example.com", issue, subscriber);
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(payload.as_bytes());
+        let signature = mac.finalize().into_bytes();
+        let token = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        );
+
+        assert_err!(tracker("secret").verify(&token));
+    }
+
+    #[test]
+    fn tampered_destinations_are_rejected() {
+        let tracker = tracker("secret");
+        let url = tracker.click_url(Uuid::new_v4(), Uuid::new_v4(), "https://example.com");
+        let (_, signature) = token(&url).split_once('.').unwrap();
+        let forged_payload = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            format!("click|{}|{}|https://evil.example.com", Uuid::new_v4(), Uuid::new_v4()),
+        );
+
+        assert_err!(tracker.verify(&format!("{}.{}", forged_payload, signature)));
+        assert_err!(tracker.verify("not-a-token"));
+    }
+
+    #[test]
+    fn only_web_links_are_rewritten() {
+        let tracker = tracker("secret");
+        let html = r#"<html><body><a href="https://example.com/?a=1&amp;b=2">Link</a>
+<a href="mailto:ursula@example.com">Mail</a>
+<a href="{{ unsubscribe_link }}">Unsubscribe</a></body></html>"#;
+        let (issue, subscriber) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let tracked = tracker.track_html(html, issue, subscriber);
+
+        assert!(!tracked.contains("https://example.com/?a=1"));
+        assert!(tracked.contains(r#"href="mailto:ursula@example.com""#));
+        assert!(tracked.contains(r#"href="{{ unsubscribe_link }}""#));
+        let redirect = tracked
+            .split("href=\"")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        assert_eq!(
+            tracker.verify(token(redirect)).unwrap(),
+            TrackedEvent::Click {
+                newsletter_issue_id: issue,
+                subscriber_id: subscriber,
+                url: "https://example.com/?a=1&b=2".into(),
+            }
+        );
+        assert!(tracked.contains(&format!(
+            r#"<img src="{}""#,
+            tracker.open_url(issue, subscriber)
+        )));
+        assert!(tracked.ends_with("</body></html>"));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn ab_test() -> serde_json::Value {
+    serde_json::json!({
+        "variants": [
+            { "subject": "Subject A" },
+            { "subject": "Subject B", "content": "Hello, *B*!" }
+        ],
+        "sample_fraction": 0.25,
+        "wait_minutes": 60,
+        "metric": "opens"
+    })
+}
+
+async fn issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+/// The subjects of the issues sent so far, along with their HTML bodies.
+async fn sent_issues(app: &TestApp) -> Vec<(String, String)> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .filter(|body| body["Subject"].as_str().unwrap().starts_with("Subject"))
+        .map(|body| {
+            (
+                body["Subject"].as_str().unwrap().to_owned(),
+                body["HtmlBody"].as_str().unwrap().to_owned(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn invalid_ab_tests_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    let mut single_variant = ab_test();
+    single_variant["variants"] = serde_json::json!([{ "subject": "Subject A" }]);
+    let mut oversized_samples = ab_test();
+    oversized_samples["sample_fraction"] = 0.6.into();
+    let mut no_wait = ab_test();
+    no_wait["wait_minutes"] = 0.into();
+    let mut multiline_subject = ab_test();
+    multiline_subject["variants"][0]["subject"] = "Subject\r\nBcc: eve@example.com".into();
+    let test_cases = vec![
+        (single_variant, "a single variant"),
+        (oversized_samples, "samples larger than the audience"),
+        (no_wait, "no wait"),
+        (multiline_subject, "a subject spanning several lines"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.put_ab_test(issue_id, &body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the A/B test had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_winning_variant_goes_to_everybody_else() {
+    let app = spawn_app().await;
+    for name in ["ursula", "octavia", "ted", "nnedi"] {
+        app.create_confirmed_subscriber(&format!("{}@example.com", name))
+            .await;
+    }
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.create_issue("Issue #1", "Hello!").await;
+    app.put_ab_test(issue_id, &ab_test())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // One recipient per variant, and the others wait for the winner.
+    let mut samples = sent_issues(&app).await;
+    samples.sort();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[0].0, "Subject A");
+    assert_eq!(samples[1].0, "Subject B");
+    assert!(samples[1].1.contains("<em>B</em>"));
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+    // Nobody opened variant A; somebody opened variant B.
+    let html = &samples[1].1;
+    let start = html.find(&format!("{}/o/", app.base_url)).unwrap();
+    let end = start + html[start..].find('"').unwrap();
+    let mut pixel = reqwest::Url::parse(&html[start..end]).unwrap();
+    pixel.set_port(Some(app.port)).unwrap();
+    reqwest::get(pixel).await.unwrap().error_for_status().unwrap();
+
+    // Too early to tell.
+    app.decide_due_ab_tests().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(sent_issues(&app).await.len(), 2);
+
+    sqlx::query!("UPDATE issue_ab_tests SET decide_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.decide_due_ab_tests().await;
+    app.dispatch_all_pending_emails().await;
+
+    let sent = sent_issues(&app).await;
+    assert_eq!(sent.len(), 4);
+    assert_eq!(sent.iter().filter(|(s, _)| s == "Subject B").count(), 3);
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+    let stats: serde_json::Value = app.get_issue_stats(issue_id).await.json().await.unwrap();
+    assert_eq!(stats["ab_test"]["winning_variant"], 1);
+    assert_eq!(stats["ab_test"]["variants"][0]["delivered"], 1);
+    assert_eq!(stats["ab_test"]["variants"][1]["delivered"], 3);
+    assert_eq!(stats["ab_test"]["variants"][1]["unique_opens"], 1);
+    // The archive and its feeds show the winner as well.
+    for (page, winner) in [
+        ("/archive/issue-1", "<em>B</em>"),
+        ("/archive/feed.xml", "&lt;em&gt;B&lt;/em&gt;"),
+        ("/archive/feed.xml?format=atom", "&lt;em&gt;B&lt;/em&gt;"),
+    ] {
+        let body = reqwest::get(format!("{}{}", app.address, page))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(body.contains(winner), "{}", page);
+    }
+}
+
+#[tokio::test]
+async fn ab_tests_cannot_be_changed_once_sending_starts() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+
+    let response = app.put_ab_test(issue_id, &ab_test()).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
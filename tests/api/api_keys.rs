@@ -0,0 +1,242 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+
+/// Create an issue through the API, as a machine client would.
+async fn post_issue_with_key(app: &TestApp, key: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/api/v1/issues", &app.address))
+        .bearer_auth(key)
+        .json(&serde_json::json!({ "title": "Newsletter title", "content": "Hi!" }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn list_api_keys(app: &TestApp) -> serde_json::Value {
+    app.api_client
+        .get(format!("{}/admin/api_keys", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_create_api_keys() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_api_key(&serde_json::json!({ "name": "CRM sync", "scopes": ["issues:write"] }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_api_keys_are_not_created() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "", "scopes": ["issues:write"] }),
+            "an empty name",
+        ),
+        (
+            serde_json::json!({ "name": "CRM sync", "scopes": [] }),
+            "no scopes",
+        ),
+        (
+            serde_json::json!({ "name": "CRM sync", "scopes": ["everything"] }),
+            "an unknown scope",
+        ),
+        (
+            serde_json::json!({
+                "name": "CRM sync",
+                "scopes": ["issues:write"],
+                "expires_at": "2000-01-01T00:00:00Z",
+            }),
+            "an expiry in the past",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_api_key(&body).await;
+        assert!(
+            response.status().is_client_error(),
+            "The API did not reject a key with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn keys_are_listed_by_prefix_but_never_shown_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app.create_api_key(&["issues:write"]).await;
+
+    let keys = list_api_keys(&app).await;
+
+    assert_eq!(keys.as_array().unwrap().len(), 1);
+    assert!(key.starts_with(keys[0]["prefix"].as_str().unwrap()));
+    assert_eq!(keys[0]["scopes"], serde_json::json!(["issues:write"]));
+    assert!(keys[0]["last_used_at"].is_null());
+    assert!(!keys.to_string().contains(&key));
+    let stored_hash = sqlx::query!("SELECT key_hash FROM api_keys")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .key_hash;
+    assert_ne!(stored_hash, key);
+}
+
+#[tokio::test]
+async fn a_key_with_the_right_scope_is_accepted_and_its_use_recorded() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app.create_api_key(&["issues:write"]).await;
+
+    let response = post_issue_with_key(&app, &key).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    let keys = list_api_keys(&app).await;
+    assert!(keys[0]["last_used_at"].is_string());
+}
+
+#[tokio::test]
+async fn a_key_without_the_required_scope_is_forbidden() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app
+        .create_api_key(&["subscribers:read", "issues:write"])
+        .await;
+    let newsletter_issue_id = app.create_issue("Newsletter title", "Hi!").await;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/api/v1/issues/{}/publish",
+            &app.address, newsletter_issue_id
+        ))
+        .bearer_auth(&key)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response.text().await.unwrap().contains("issues:publish"));
+}
+
+#[tokio::test]
+async fn invalid_keys_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app.create_api_key(&["issues:write"]).await;
+    let (prefix, _) = key.rsplit_once('_').unwrap();
+    let test_cases = vec![
+        ("zp_abc".to_owned(), "a malformed key"),
+        (
+            format!("{}_{}", prefix, "0".repeat(32)),
+            "a key with the wrong secret",
+        ),
+    ];
+
+    for (key, description) in test_cases {
+        let response = post_issue_with_key(&app, &key).await;
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The API did not reject {}.",
+            description
+        );
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+    }
+}
+
+#[tokio::test]
+async fn revoked_keys_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app.create_api_key(&["issues:write"]).await;
+    let api_key_id = list_api_keys(&app).await[0]["api_key_id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    app.api_client
+        .delete(format!("{}/admin/api_keys/{}", &app.address, api_key_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    assert_eq!(post_issue_with_key(&app, &key).await.status().as_u16(), 401);
+    assert!(list_api_keys(&app).await[0]["revoked_at"].is_string());
+}
+
+#[tokio::test]
+async fn revoking_an_unknown_key_is_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .delete(format!(
+            "{}/admin/api_keys/{}",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn expired_keys_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app.create_api_key(&["issues:write"]).await;
+
+    sqlx::query!("UPDATE api_keys SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(post_issue_with_key(&app, &key).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_api_requires_a_key() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/issues", &app.address))
+        .json(&serde_json::json!({ "title": "Newsletter title", "content": "Hi!" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_session_is_not_enough_to_use_the_api() {
+    let app = spawn_app().await;
+    let viewer = crate::helpers::TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/api/v1/issues", &app.address))
+        .json(&serde_json::json!({ "title": "Newsletter title", "content": "Hi!" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
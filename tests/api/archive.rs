@@ -0,0 +1,253 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_archive(app: &TestApp, path_and_query: &str) -> reqwest::Response {
+    reqwest::get(format!("{}{}", app.address, path_and_query))
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Create and publish an issue, returning its id and archive slug.
+async fn publish(app: &TestApp, issue: serde_json::Value) -> (Uuid, String) {
+    let response: serde_json::Value = app
+        .post_issue(&issue)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = response["newsletter_issue_id"].as_str().unwrap().parse().unwrap();
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+    let slug = sqlx::query!(
+        "SELECT slug FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .slug
+    .unwrap();
+    (issue_id, slug)
+}
+
+async fn put_visibility(app: &TestApp, issue_id: Uuid, private: bool) -> reqwest::Response {
+    app.api_client
+        .put(format!("{}/admin/issues/{}/visibility", app.address, issue_id))
+        .json(&serde_json::json!({ "private": private }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_most_recent_first() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish(&app, serde_json::json!({ "title": "First issue", "content": "Hello" })).await;
+    publish(&app, serde_json::json!({ "title": "Second issue", "content": "Hello" })).await;
+    app.create_issue("Still a draft", "Hello").await;
+
+    let response = get_archive(&app, "/archive").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    let first = html.find("First issue").unwrap();
+    let second = html.find("Second issue").unwrap();
+    assert!(second < first);
+    assert!(html.contains("/archive/first-issue"));
+    assert!(!html.contains("Still a draft"));
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for title in ["One", "Two", "Three"] {
+        publish(&app, serde_json::json!({ "title": title, "content": "Hello" })).await;
+    }
+
+    let first_page = get_archive(&app, "/archive").await.text().await.unwrap();
+    let second_page = get_archive(&app, "/archive?page=2").await.text().await.unwrap();
+
+    assert!(first_page.contains("Three") && first_page.contains("Two"));
+    assert!(!first_page.contains(">One<"));
+    assert!(first_page.contains("/archive?page=2"));
+    assert!(second_page.contains(">One<"));
+    assert!(second_page.contains("/archive?page=1"));
+    assert!(!second_page.contains("/archive?page=3"));
+    assert_eq!(get_archive(&app, "/archive?page=0").await.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn archived_issues_have_no_tracking_or_unsubscribe_link() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let (_, slug) = publish(
+        &app,
+        serde_json::json!({
+            "title": "Issue #1",
+            "content": "Read [this](https://example.com/post).",
+            "tracking": true,
+        }),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = get_archive(&app, &format!("/archive/{}", slug)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(slug, "issue-1");
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(!html.contains("/r/"));
+    assert!(!html.contains("/o/"));
+    assert!(!html.contains("Unsubscribe"));
+    assert!(!html.contains("subscription_token"));
+}
+
+#[tokio::test]
+async fn issues_with_the_same_title_get_different_slugs() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let (_, first) = publish(&app, serde_json::json!({ "title": "Weekly", "content": "A" })).await;
+    let (second_id, second) =
+        publish(&app, serde_json::json!({ "title": "Weekly", "content": "B" })).await;
+
+    assert_eq!(first, "weekly");
+    assert_eq!(second, format!("weekly-{}", &second_id.simple().to_string()[..8]));
+    let html = get_archive(&app, &format!("/archive/{}", second)).await.text().await.unwrap();
+    assert!(html.contains("<p>B</p>"));
+}
+
+#[tokio::test]
+async fn private_issues_stay_out_of_the_archive_and_the_feeds() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (issue_id, slug) = publish(
+        &app,
+        serde_json::json!({ "title": "Members only", "content": "Hello", "private": true }),
+    )
+    .await;
+
+    assert_eq!(get_archive(&app, &format!("/archive/{}", slug)).await.status().as_u16(), 404);
+    let list = get_archive(&app, "/archive").await.text().await.unwrap();
+    assert!(!list.contains("Members only"));
+    let feed = get_archive(&app, "/archive/feed.xml").await.text().await.unwrap();
+    assert!(!feed.contains("Members only"));
+
+    // Unlike the content, the visibility can change after publication.
+    put_visibility(&app, issue_id, false).await.error_for_status().unwrap();
+    assert_eq!(get_archive(&app, &format!("/archive/{}", slug)).await.status().as_u16(), 200);
+    put_visibility(&app, issue_id, true).await.error_for_status().unwrap();
+    assert_eq!(get_archive(&app, &format!("/archive/{}", slug)).await.status().as_u16(), 404);
+    assert_eq!(put_visibility(&app, Uuid::new_v4(), true).await.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_feed_is_available_as_rss_and_atom() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish(&app, serde_json::json!({ "title": "Tips & tricks", "content": "**Bold**" })).await;
+
+    let rss = get_archive(&app, "/archive/feed.xml").await;
+    let atom = get_archive(&app, "/archive/feed.xml?format=atom").await;
+
+    assert_eq!(rss.headers()["Content-Type"], "application/rss+xml; charset=utf-8");
+    let rss = rss.text().await.unwrap();
+    assert!(rss.contains(r#"<rss version="2.0""#));
+    assert!(rss.contains("<title>Tips &amp; tricks</title>"));
+    assert!(rss.contains("<link>http://127.0.0.1/archive/tips-tricks</link>"));
+    assert!(rss.contains("&lt;strong&gt;Bold&lt;/strong&gt;"));
+    assert_eq!(atom.headers()["Content-Type"], "application/atom+xml; charset=utf-8");
+    let atom = atom.text().await.unwrap();
+    assert!(atom.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+    assert!(atom.contains("<title>Tips &amp; tricks</title>"));
+    assert!(atom.contains(r#"<link href="http://127.0.0.1/archive/tips-tricks"/>"#));
+    assert_eq!(
+        get_archive(&app, "/archive/feed.xml?format=json").await.status().as_u16(),
+        400
+    );
+}
+
+#[tokio::test]
+async fn merge_tags_get_their_defaults_outside_emails() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("company", "string", false).await;
+    let content = r#"Hi {{ attributes.company | default: "there" }}, welcome aboard."#;
+    let issue_id = app.create_issue("Merge tags", content).await;
+
+    let preview: serde_json::Value =
+        app.get_issue_preview(issue_id).await.json().await.unwrap();
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+    let page = get_archive(&app, "/archive/merge-tags").await.text().await.unwrap();
+    let rss = get_archive(&app, "/archive/feed.xml").await.text().await.unwrap();
+    let atom = get_archive(&app, "/archive/feed.xml?format=atom").await.text().await.unwrap();
+
+    for body in [
+        preview["html_content"].as_str().unwrap(),
+        preview["text_content"].as_str().unwrap(),
+        &page,
+        &rss,
+        &atom,
+    ] {
+        assert!(body.contains("Hi there, welcome aboard."), "{}", body);
+        assert!(!body.contains("attributes.company"), "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn archive_responses_can_be_revalidated() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (issue_id, slug) =
+        publish(&app, serde_json::json!({ "title": "Issue #1", "content": "Hello" })).await;
+    let client = reqwest::Client::new();
+
+    for page in ["/archive", "/archive/feed.xml", &format!("/archive/{}", slug)] {
+        let response = get_archive(&app, page).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers()["Cache-Control"].to_str().unwrap().contains("public"));
+        let etag = response.headers()["ETag"].clone();
+        let last_modified = response.headers()["Last-Modified"].clone();
+
+        for (header, value) in [("If-None-Match", etag), ("If-Modified-Since", last_modified)] {
+            let response = client
+                .get(format!("{}{}", app.address, page))
+                .header(header, value)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status().as_u16(),
+                304,
+                "{} was not honoured for {}",
+                header,
+                page
+            );
+            assert!(response.headers().contains_key("ETag"));
+        }
+    }
+
+    // Hiding the issue changes the archive, even though nothing new was published.
+    let etag = get_archive(&app, "/archive").await.headers()["ETag"].clone();
+    put_visibility(&app, issue_id, true).await.error_for_status().unwrap();
+    let response = client
+        .get(format!("{}/archive", app.address))
+        .header("If-None-Match", etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,317 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use zero2prod::email_templates::EmailTemplates;
+use zero2prod::routes::send_confirmation_email;
+
+async fn attributes_of(app: &TestApp, email: &str) -> serde_json::Value {
+    sqlx::query!(
+        "SELECT attributes FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .attributes
+}
+
+async fn accept_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_define_attributes() {
+    let app = spawn_app().await;
+
+    let response = app
+        .put_attribute("company", &serde_json::json!({ "type": "string" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn attributes_submitted_at_signup_are_checked_and_stored() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("company", "string", true).await;
+    app.define_attribute("seats", "number", false).await;
+    accept_emails(&app).await;
+    let test_cases = vec![
+        ("attributes.seats=12", "a missing required attribute"),
+        (
+            "attributes.company=Acme&attributes.plan=pro",
+            "an unknown attribute",
+        ),
+        (
+            "attributes.company=Acme&attributes.seats=many",
+            "a mistyped attribute",
+        ),
+    ];
+    for (attributes, description) in test_cases {
+        let body = format!("name=le%20guin&email=ursula%40example.com&{}", attributes);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40example.com&attributes.company=Acme&attributes.seats=12"
+                .into(),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        attributes_of(&app, "ursula@example.com").await,
+        serde_json::json!({ "company": "Acme", "seats": 12.0 })
+    );
+}
+
+#[tokio::test]
+async fn imports_are_rejected_as_a_whole_if_any_row_is_invalid() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("plan", "string", false).await;
+
+    let response = app
+        .post_subscriber_import(&serde_json::json!([
+            { "email": "ursula@example.com", "name": "Ursula", "attributes": { "plan": "pro" } },
+            { "email": "octavia@example.com", "name": "Octavia", "attributes": { "plan": 3 } },
+            { "email": "not-an-email", "name": "Frank" },
+        ]))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let rows: Vec<_> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rows, vec![1, 2]);
+    let count = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_confirmed_with_their_attributes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("plan", "string", false).await;
+    app.create_confirmed_subscriber("frank@example.com").await;
+
+    let response = app
+        .post_subscriber_import(&serde_json::json!([
+            { "email": "ursula@example.com", "name": "Ursula", "attributes": { "plan": "pro" } },
+            { "email": "frank@example.com", "name": "Frank" },
+        ]))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let outcome: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(outcome["imported"], 1);
+    assert_eq!(outcome["skipped"], 1);
+    let saved = sqlx::query!("SELECT status FROM subscriptions WHERE email = 'ursula@example.com'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(
+        attributes_of(&app, "ursula@example.com").await,
+        serde_json::json!({ "plan": "pro" })
+    );
+    let event = sqlx::query!(
+        r#"
+        SELECT e.event_type FROM consent_events e
+        JOIN subscriptions s ON s.id = e.subscription_id
+        WHERE s.email = 'ursula@example.com'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.event_type, "import");
+}
+
+#[tokio::test]
+async fn repeated_and_existing_addresses_are_skipped_whatever_their_case() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("frank@example.com").await;
+
+    let response = app
+        .post_subscriber_import(&serde_json::json!([
+            { "email": "ursula@example.com", "name": "Ursula" },
+            { "email": "Frank@Example.com", "name": "Frank" },
+            { "email": "URSULA@example.com", "name": "Ursula" },
+            { "email": "ursula@example.com", "name": "Ursula" },
+        ]))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let outcome: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(outcome["imported"], 1);
+    assert_eq!(outcome["skipped"], 3);
+    let rows: Vec<_> = outcome["skipped_rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|skipped| skipped["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rows, vec![1, 2, 3]);
+    let count = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 2);
+}
+
+#[tokio::test]
+async fn merge_tags_are_filled_in_for_each_recipient() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("company", "string", false).await;
+    app.post_subscriber_import(&serde_json::json!([
+        { "email": "ursula@example.com", "name": "Ursula", "attributes": { "company": "Acme & Co" } },
+        { "email": "octavia@example.com", "name": "Octavia" },
+    ]))
+    .await
+    .error_for_status()
+    .unwrap();
+    accept_emails(&app).await;
+    let issue_id = app
+        .create_issue(
+            "Hello",
+            r#"Hi {{ attributes.company | default: "there" }}, welcome aboard."#,
+        )
+        .await;
+
+    assert_eq!(
+        app.post_publish_issue(issue_id).await.status().as_u16(),
+        202
+    );
+    app.dispatch_all_pending_emails().await;
+
+    let mut bodies = Vec::new();
+    for request in app.email_server.received_requests().await.unwrap() {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        bodies.push((
+            body["To"].as_str().unwrap().to_owned(),
+            body["HtmlBody"].as_str().unwrap().to_owned(),
+            body["TextBody"].as_str().unwrap().to_owned(),
+        ));
+    }
+    assert_eq!(bodies.len(), 2);
+    for (to, html, text) in bodies {
+        if to.contains("ursula@example.com") {
+            assert!(
+                html.contains("Hi Acme &amp; Co, welcome aboard."),
+                "{}",
+                html
+            );
+            assert!(text.contains("Hi Acme & Co, welcome aboard."), "{}", text);
+        } else {
+            assert!(html.contains("Hi there, welcome aboard."), "{}", html);
+            assert!(text.contains("Hi there, welcome aboard."), "{}", text);
+        }
+    }
+}
+
+#[tokio::test]
+async fn confirmation_emails_can_use_merge_tags() {
+    let app = spawn_app().await;
+    accept_emails(&app).await;
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(directory.join("emails")).unwrap();
+    std::fs::write(
+        directory.join("emails/confirmation.html"),
+        r#"<p>Welcome, {{ attributes.company | default: "friend" }}! {{ confirmation_link }}</p>"#,
+    )
+    .unwrap();
+    let templates = EmailTemplates::load(&directory).unwrap();
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse("ursula@example.com".into()).unwrap(),
+        name: SubscriberName::parse("Ursula".into()).unwrap(),
+        timezone: None,
+        list: None,
+        attributes: serde_json::json!({ "company": "Acme & Co" })
+            .as_object()
+            .unwrap()
+            .clone(),
+    };
+
+    send_confirmation_email(&app.email_client, &templates, new_subscriber, &app.address, "token")
+        .await
+        .unwrap();
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("Welcome, Acme &amp; Co!"), "{}", html);
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains("Welcome, Acme & Co!"), "{}", text);
+}
+
+#[tokio::test]
+async fn issues_cannot_refer_to_unknown_attributes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("company", "string", false).await;
+
+    let response = app
+        .post_issue(&serde_json::json!({
+            "title": "Hello",
+            "content": "Hi {{ attributes.compnay }}!",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_type_of_an_attribute_in_use_cannot_change() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.define_attribute("seats", "string", false).await;
+    app.post_subscriber_import(&serde_json::json!([
+        { "email": "ursula@example.com", "name": "Ursula", "attributes": { "seats": "12" } },
+    ]))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app
+        .put_attribute("seats", &serde_json::json!({ "type": "number" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Deleting the attribute removes it from subscribers, too.
+    let response = app
+        .api_client
+        .delete(format!("{}/admin/attributes/seats", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        attributes_of(&app, "ursula@example.com").await,
+        serde_json::json!({})
+    );
+}
@@ -0,0 +1,173 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use uuid::Uuid;
+
+async fn get_audit_log(app: &TestApp, query: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/audit_log?{}", &app.address, query))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn audit_events(app: &TestApp, query: &str) -> Vec<serde_json::Value> {
+    get_audit_log(app, query)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_read_the_audit_log() {
+    let app = spawn_app().await;
+
+    let response = get_audit_log(&app, "").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn only_owners_can_read_the_audit_log() {
+    for role in ["viewer", "editor"] {
+        let app = spawn_app().await;
+        let user = TestUser::with_role(role);
+        user.store(&app.db_pool).await;
+        user.login(&app).await;
+
+        let response = get_audit_log(&app, "").await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "A {} read the audit log.",
+            role
+        );
+    }
+}
+
+#[tokio::test]
+async fn creating_an_issue_records_who_did_it_and_from_where() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let newsletter_issue_id = app.create_issue("Newsletter title", "Hi!").await;
+
+    let events = audit_events(&app, "").await;
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event["action"], "newsletter_issue.create");
+    assert_eq!(event["target_type"], "newsletter_issue");
+    assert_eq!(event["target_id"], newsletter_issue_id.to_string());
+    assert_eq!(event["actor_user_id"], app.test_user.user_id.to_string());
+    assert!(event["actor_api_key_id"].is_null());
+    assert!(event["before"].is_null());
+    assert_eq!(event["after"]["title"], "Newsletter title");
+    assert_eq!(event["ip_address"], "127.0.0.1");
+    assert!(event["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn updates_record_only_what_changed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = app.create_issue("Newsletter title", "Hi!").await;
+
+    app.api_client
+        .put(format!(
+            "{}/admin/issues/{}",
+            &app.address, newsletter_issue_id
+        ))
+        .json(&serde_json::json!({ "title": "A better title", "content": "Hi!" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let events = audit_events(&app, "action=newsletter_issue.update").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(
+        events[0]["before"],
+        serde_json::json!({ "title": "Newsletter title" })
+    );
+    assert_eq!(
+        events[0]["after"],
+        serde_json::json!({ "title": "A better title" })
+    );
+}
+
+#[tokio::test]
+async fn actions_taken_with_an_api_key_record_the_key() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let key = app.create_api_key(&["issues:write"]).await;
+
+    reqwest::Client::new()
+        .post(format!("{}/api/v1/issues", &app.address))
+        .bearer_auth(&key)
+        .json(&serde_json::json!({ "title": "Newsletter title", "content": "Hi!" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let events = audit_events(&app, "action=newsletter_issue.create").await;
+    assert_eq!(events.len(), 1);
+    assert!(events[0]["actor_user_id"].is_null());
+    let created = audit_events(&app, "action=api_key.create").await;
+    assert_eq!(events[0]["actor_api_key_id"], created[0]["target_id"]);
+    // The key itself is never logged.
+    assert!(!created[0].to_string().contains(&key));
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered_and_paged() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let first = app.create_issue("First", "Hi!").await;
+    let second = app.create_issue("Second", "Hi!").await;
+    app.create_segment("Rust", "in list \"rust\"").await;
+
+    let issues = audit_events(&app, "target_type=newsletter_issue").await;
+    let by_target = audit_events(&app, &format!("target_id={}", first)).await;
+    let by_actor = audit_events(&app, &format!("actor_user_id={}", Uuid::new_v4())).await;
+    let page = audit_events(&app, "limit=1").await;
+    let next_page = audit_events(
+        &app,
+        &format!("limit=1&before_id={}", page[0]["audit_event_id"]),
+    )
+    .await;
+
+    assert_eq!(issues.len(), 2);
+    // Most recent first.
+    assert_eq!(issues[0]["target_id"], second.to_string());
+    assert_eq!(by_target.len(), 1);
+    assert!(by_actor.is_empty());
+    assert_eq!(page[0]["action"], "segment.create");
+    assert_eq!(next_page[0]["target_id"], second.to_string());
+}
+
+#[tokio::test]
+async fn audit_events_cannot_be_changed_or_deleted() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_issue("Newsletter title", "Hi!").await;
+
+    let update = sqlx::query("UPDATE audit_log SET action = 'nothing.happened'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+    let truncate = sqlx::query("TRUNCATE audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+    assert!(truncate.is_err());
+    assert_eq!(audit_events(&app, "").await.len(), 1);
+}
@@ -0,0 +1,81 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_consent_history() {
+    let app = spawn_app().await;
+
+    let response = app.get_consent_events(uuid::Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn signup_confirmation_and_unsubscribe_are_recorded_as_consent_events() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("User-Agent", "consent-test-agent")
+        // Nothing in front of the application vouches for it.
+        .header("X-Forwarded-For", "203.0.113.7")
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("source", "homepage-footer"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_email_links(email_request).remove(0);
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Confirming twice must not produce a second event.
+    reqwest::get(confirmation_link.clone()).await.unwrap();
+    let mut unsubscribe_link = confirmation_link;
+    unsubscribe_link.set_path("/subscriptions/unsubscribe");
+    reqwest::get(unsubscribe_link).await.unwrap().error_for_status().unwrap();
+
+    let subscriber_id = sqlx::query!("SELECT id, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber_id.status, "unsubscribed");
+
+    app.test_user.login(&app).await;
+    let response = app.get_consent_events(subscriber_id.id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let events: Vec<serde_json::Value> = response.json().await.unwrap();
+
+    let event_types: Vec<_> = events.iter().map(|e| e["event_type"].as_str().unwrap()).collect();
+    assert_eq!(event_types, vec!["signup", "confirm", "unsubscribe"]);
+    let signup = &events[0];
+    assert_eq!(signup["ip_address"], "127.0.0.1");
+    assert_eq!(signup["user_agent"], "consent-test-agent");
+    assert_eq!(signup["source"], "homepage-footer");
+    assert_eq!(signup["privacy_policy_version"], "2025-08-01");
+    assert!(!signup["wording"].as_str().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn consent_history_of_an_unknown_subscriber_is_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_consent_events(uuid::Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,260 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::{FeedMode, FeedSource};
+use zero2prod::feed_poller::{poll_feed, FeedClient, PollOutcome};
+
+/// A blog post: its GUID and its title.
+type Post = (&'static str, &'static str);
+
+fn rss(posts: &[Post]) -> String {
+    let items: String = posts
+        .iter()
+        .map(|(guid, title)| {
+            format!(
+                r#"<item>
+<title>{title}</title>
+<link>https://blog.example.com/{guid}</link>
+<guid>{guid}</guid>
+<description>&lt;p&gt;All about {title}.&lt;/p&gt;</description>
+</item>"#
+            )
+        })
+        .collect();
+    format!(
+        r#"<?xml version="1.0"?>
+<rss version="2.0"><channel>
+<title>Blog</title><link>https://blog.example.com</link><description>Posts</description>
+{items}
+</channel></rss>"#
+    )
+}
+
+struct Blog {
+    server: MockServer,
+}
+
+impl Blog {
+    async fn start() -> Self {
+        Self {
+            server: MockServer::start().await,
+        }
+    }
+
+    /// Serve `posts`, most recent first, from now on.
+    async fn publish(&self, posts: &[Post]) {
+        self.server.reset().await;
+        Mock::given(path("/rss.xml"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rss(posts)))
+            .mount(&self.server)
+            .await;
+    }
+
+    fn source(&self, mode: FeedMode, digest: bool) -> FeedSource {
+        FeedSource {
+            name: "blog".into(),
+            url: format!("{}/rss.xml", self.server.uri()),
+            mode,
+            digest,
+            digest_title: None,
+        }
+    }
+}
+
+async fn poll(
+    app: &TestApp,
+    source: &FeedSource,
+    now: chrono::DateTime<Utc>,
+) -> Result<PollOutcome, anyhow::Error> {
+    let client = FeedClient::new(std::time::Duration::from_secs(2));
+    poll_feed(&app.db_pool, &client, &app.templates, &app.delivery, source, now).await
+}
+
+async fn issue(app: &TestApp, issue_id: Uuid) -> (String, String, String) {
+    let issue = sqlx::query!(
+        "SELECT title, markdown_content, status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    (issue.title, issue.markdown_content, issue.status)
+}
+
+#[tokio::test]
+async fn items_already_in_the_feed_are_not_turned_into_issues() {
+    let app = spawn_app().await;
+    let blog = Blog::start().await;
+    blog.publish(&[("second", "Second post"), ("first", "First post")]).await;
+    let source = blog.source(FeedMode::Draft, false);
+
+    let outcome = poll(&app, &source, Utc::now()).await.unwrap();
+
+    assert_eq!(outcome.new_items, 0);
+    assert!(outcome.issues.is_empty());
+    let issues = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+}
+
+#[tokio::test]
+async fn new_items_become_draft_issues_once() {
+    let app = spawn_app().await;
+    let blog = Blog::start().await;
+    let source = blog.source(FeedMode::Draft, false);
+    blog.publish(&[("first", "First post")]).await;
+    poll(&app, &source, Utc::now()).await.unwrap();
+
+    blog.publish(&[("third", "Third post"), ("second", "Second post"), ("first", "First post")])
+        .await;
+    let outcome = poll(&app, &source, Utc::now()).await.unwrap();
+    let again = poll(&app, &source, Utc::now()).await.unwrap();
+
+    assert_eq!(outcome.new_items, 2);
+    assert_eq!(outcome.issues.len(), 2);
+    // Oldest first.
+    let (title, markdown, status) = issue(&app, outcome.issues[0]).await;
+    assert_eq!(title, "Second post");
+    assert_eq!(status, "draft");
+    assert!(markdown.contains("<p>All about Second post.</p>"));
+    assert!(markdown.contains("(https://blog.example.com/second)"));
+    assert_eq!(issue(&app, outcome.issues[1]).await.0, "Third post");
+    assert_eq!(again.new_items, 0);
+    assert!(again.issues.is_empty());
+}
+
+#[tokio::test]
+async fn items_of_feeds_in_publish_mode_are_sent_right_away() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let blog = Blog::start().await;
+    let source = blog.source(FeedMode::Publish, false);
+    blog.publish(&[]).await;
+    poll(&app, &source, Utc::now()).await.unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    blog.publish(&[("first", "First post")]).await;
+    let outcome = poll(&app, &source, Utc::now()).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let (_, _, status) = issue(&app, outcome.issues[0]).await;
+    assert_ne!(status, "draft");
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "First post");
+    assert!(body["HtmlBody"].as_str().unwrap().contains("https://blog.example.com/first"));
+}
+
+#[tokio::test]
+async fn digests_combine_the_new_items_of_a_week() {
+    let app = spawn_app().await;
+    let blog = Blog::start().await;
+    let source = blog.source(FeedMode::Draft, true);
+    let start = Utc::now();
+    blog.publish(&[]).await;
+    poll(&app, &source, start).await.unwrap();
+
+    blog.publish(&[("first", "First post")]).await;
+    let monday = poll(&app, &source, start + Duration::days(1)).await.unwrap();
+    blog.publish(&[("second", "Second post"), ("first", "First post")]).await;
+    let thursday = poll(&app, &source, start + Duration::days(4)).await.unwrap();
+    let next_week = poll(&app, &source, start + Duration::days(7)).await.unwrap();
+    let the_day_after = poll(&app, &source, start + Duration::days(8)).await.unwrap();
+
+    assert!(monday.issues.is_empty());
+    assert!(thursday.issues.is_empty());
+    assert_eq!(next_week.issues.len(), 1);
+    let (title, markdown, status) = issue(&app, next_week.issues[0]).await;
+    assert_eq!(title, "This week on blog");
+    assert_eq!(status, "draft");
+    let first = markdown.find("[First post](https://blog.example.com/first)").unwrap();
+    let second = markdown.find("[Second post](https://blog.example.com/second)").unwrap();
+    assert!(first < second);
+    assert!(the_day_after.issues.is_empty());
+}
+
+#[tokio::test]
+async fn a_failing_feed_changes_nothing() {
+    let app = spawn_app().await;
+    let blog = Blog::start().await;
+    let source = blog.source(FeedMode::Draft, false);
+    Mock::given(path("/rss.xml"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&blog.server)
+        .await;
+
+    assert!(poll(&app, &source, Utc::now()).await.is_err());
+
+    // The next successful poll is still the first one.
+    blog.publish(&[("first", "First post")]).await;
+    let outcome = poll(&app, &source, Utc::now()).await.unwrap();
+    assert_eq!(outcome.new_items, 0);
+    blog.server.reset().await;
+    Mock::given(path("/rss.xml"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>Not a feed</html>"))
+        .mount(&blog.server)
+        .await;
+    assert!(poll(&app, &source, Utc::now()).await.is_err());
+}
+
+#[tokio::test]
+async fn items_that_cannot_be_issued_are_set_aside_without_holding_up_the_rest() {
+    let app = spawn_app().await;
+    let blog = Blog::start().await;
+    let source = blog.source(FeedMode::Draft, false);
+    blog.publish(&[]).await;
+    poll(&app, &source, Utc::now()).await.unwrap();
+    // Make storing the issue of one of the items fail.
+    sqlx::query(
+        r#"
+        CREATE FUNCTION reject_broken_posts() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'This post is broken';
+        END;
+        $$ LANGUAGE plpgsql
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TRIGGER reject_broken_posts BEFORE INSERT ON newsletter_issues
+        FOR EACH ROW WHEN (NEW.title = 'Broken post')
+        EXECUTE FUNCTION reject_broken_posts()
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    blog.publish(&[("third", "Third post"), ("second", "Broken post"), ("first", "First post")])
+        .await;
+    let outcome = poll(&app, &source, Utc::now()).await.unwrap();
+    let again = poll(&app, &source, Utc::now()).await.unwrap();
+
+    assert_eq!(outcome.new_items, 3);
+    assert_eq!(outcome.failed_items, 1);
+    assert_eq!(outcome.issues.len(), 2);
+    assert_eq!(issue(&app, outcome.issues[0]).await.0, "First post");
+    assert_eq!(issue(&app, outcome.issues[1]).await.0, "Third post");
+    let broken = sqlx::query!("SELECT status, failure_reason FROM feed_items WHERE guid = 'second'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(broken.status, "failed");
+    assert!(broken.failure_reason.unwrap().contains("This post is broken"));
+    // It is not tried again.
+    assert_eq!(again.new_items, 0);
+    assert_eq!(again.failed_items, 0);
+}
@@ -0,0 +1,75 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use secrecy::Secret;
+use zero2prod::authentication::{create_first_owner, BootstrapError};
+
+#[tokio::test]
+async fn migrations_seed_no_admin() {
+    let app = spawn_app().await;
+
+    let usernames: Vec<String> = sqlx::query!("SELECT username FROM users")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.username)
+        .collect();
+
+    assert_eq!(usernames, std::slice::from_ref(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn the_first_owner_can_log_in() {
+    let app = spawn_app().await;
+    sqlx::query!("DELETE FROM users")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    create_first_owner(
+        "ursula",
+        Secret::new("a-long-enough-password".into()),
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "ursula",
+            "password": "a-long-enough-password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn there_can_only_be_a_first_owner_once() {
+    let app = spawn_app().await;
+
+    let outcome = create_first_owner(
+        "mallory",
+        Secret::new("a-long-enough-password".into()),
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(matches!(outcome, Err(BootstrapError::OwnerExists)));
+}
+
+#[tokio::test]
+async fn the_first_owner_needs_an_acceptable_password() {
+    let app = spawn_app().await;
+    sqlx::query!("DELETE FROM users")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = create_first_owner(
+        "ursula",
+        Secret::new("everythinghastostartsomewhere".into()),
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(matches!(outcome, Err(BootstrapError::ValidationError(_))));
+}
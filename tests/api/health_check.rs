@@ -7,7 +7,7 @@ async fn health_check_works() {
     let client = reqwest::Client::new();
     // println!("address: {}/health_check", &app.address);
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("failed to execute request");
@@ -1,30 +1,162 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::{DateTime, TimeZone, Utc};
 use once_cell::sync::Lazy;
+use secrecy::ExposeSecret;
+use std::sync::{Arc, Mutex};
 use sqlx::{PgPool, Connection, PgConnection, Executor};
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use uuid::Uuid;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
-use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::clock::Clock;
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, DeliverySettings, PostmarkWebhookSettings,
+    WebhookSettings,
+};
+use zero2prod::email_client::EmailClient;
+use zero2prod::email_templates::EmailTemplates;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::{try_decide_ab_test, try_start_due_issue, SchedulerOutcome};
+use zero2prod::outbound_webhooks::try_deliver_webhook;
+use zero2prod::password_reset_worker::try_send_password_reset_email;
+use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscribe, init_subscriber};
+use zero2prod::tracking::Tracker;
 
 
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
-    if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscribe(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
-    } else {
-        let subscriber = get_subscribe(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
-    }
+    let (subscriber, log_filter) =
+        get_subscribe(subscriber_name, default_filter_level, CapturedLogs);
+    init_subscriber(subscriber, log_filter);
 });
 
+/// Everything the applications of all tests logged so far, to check what
+/// does (not) reach the logs. Echoed to stdout when `TEST_LOG` is set.
+static LOGS: Lazy<Mutex<Vec<u8>>> = Lazy::new(Mutex::default);
+
+#[derive(Clone, Copy)]
+struct CapturedLogs;
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        LOGS.lock().unwrap().extend_from_slice(buf);
+        if std::env::var("TEST_LOG").is_ok() {
+            std::io::Write::write_all(&mut std::io::stdout(), buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
+
+pub fn captured_logs() -> String {
+    String::from_utf8_lossy(&LOGS.lock().unwrap()).into_owned()
+}
+
 
 pub struct TestApp {
     pub address: String,
+    pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub base_url: String,
+    pub postmark_webhook: PostmarkWebhookSettings,
+    pub tracker: Tracker,
+    pub templates: EmailTemplates,
+    pub delivery: DeliverySettings,
+    pub webhooks: WebhookSettings,
+    pub clock: TestClock,
+}
+
+/// The clock of the test application, moved by hand.
+#[derive(Clone)]
+pub struct TestClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl TestClock {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(
+            Utc.with_ymd_and_hms(2025, 11, 11, 9, 0, 0).unwrap(),
+        )))
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+    pub role: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self::with_role("owner")
+    }
+
+    pub fn with_role(role: &str) -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+            role: role.to_owned(),
+        }
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)",
+            self.user_id,
+            self.username,
+            password_hash,
+            self.role,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
 }
 
 impl TestApp {
@@ -40,6 +172,389 @@ impl TestApp {
             .await
             .expect("Failed to execute request")
     }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_totp(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/totp", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_consent_events(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}/consent_events",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_issue(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/issues", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Create a draft issue and return its id.
+    pub async fn create_issue(&self, title: &str, content: &str) -> Uuid {
+        let response: serde_json::Value = self
+            .post_issue(&serde_json::json!({ "title": title, "content": content }))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["newsletter_issue_id"].as_str().unwrap().parse().unwrap()
+    }
+
+    pub async fn get_issue_preview(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/preview",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_ab_test(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/issues/{}/ab_test",
+                &self.address, newsletter_issue_id
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_stats(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/stats",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_issue_schedule(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/issues/{}/schedule",
+                &self.address, newsletter_issue_id
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_issue_schedule(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/issues/{}/schedule",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_publish_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/issues/{}/publish",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_segment(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/segments", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Create a segment and return its id.
+    pub async fn create_segment(&self, name: &str, definition: &str) -> Uuid {
+        let response: serde_json::Value = self
+            .post_segment(&serde_json::json!({ "name": name, "definition": definition }))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["segment_id"].as_str().unwrap().parse().unwrap()
+    }
+
+    pub async fn post_segment_preview(&self, definition: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/segments/preview", &self.address))
+            .json(&serde_json::json!({ "definition": definition }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_attribute(&self, name: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/attributes/{}", &self.address, name))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Define an attribute, e.g. `define_attribute("company", "string", false)`.
+    pub async fn define_attribute(&self, name: &str, attribute_type: &str, required: bool) {
+        self.put_attribute(
+            name,
+            &serde_json::json!({ "type": attribute_type, "required": required }),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+
+    pub async fn post_subscriber_import(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email/postmark", &self.address))
+            .basic_auth(
+                &self.postmark_webhook.username,
+                Some(self.postmark_webhook.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client, &self.base_url, &self.tracker)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Send the password reset links that were requested.
+    pub async fn send_requested_password_reset_links(&self) {
+        while let ExecutionOutcome::TaskCompleted = try_send_password_reset_email(
+            &self.db_pool,
+            &self.email_client,
+            &self.templates,
+            &self.base_url,
+        )
+        .await
+        .unwrap()
+        {}
+    }
+
+    /// Attempt every webhook delivery that is due, once.
+    pub async fn dispatch_due_webhooks(&self) {
+        let client = self.webhooks.client();
+        while let ExecutionOutcome::TaskCompleted =
+            try_deliver_webhook(&self.db_pool, &client, self.webhooks.max_attempts)
+                .await
+                .unwrap()
+        {}
+    }
+
+    pub async fn post_webhook_endpoint(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/webhooks", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_key(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api_keys", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Create an API key with `scopes` and return it.
+    pub async fn create_api_key(&self, scopes: &[&str]) -> String {
+        let response: serde_json::Value = self
+            .post_api_key(&serde_json::json!({ "name": "CRM sync", "scopes": scopes }))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["key"].as_str().unwrap().to_owned()
+    }
+
+    /// Start sending every scheduled issue that is due, returning how many were started.
+    pub async fn start_due_issues(&self) -> usize {
+        let mut started = 0;
+        while let SchedulerOutcome::IssueStarted =
+            try_start_due_issue(&self.db_pool, &self.templates, &self.delivery)
+                .await
+                .unwrap()
+        {
+            started += 1;
+        }
+        started
+    }
+
+    /// Choose the winner of every A/B test whose wait is over.
+    pub async fn decide_due_ab_tests(&self) {
+        while let SchedulerOutcome::WinnerChosen =
+            try_decide_ab_test(&self.db_pool, &self.templates, &self.delivery)
+                .await
+                .unwrap()
+        {}
+    }
+
+    /// Sign up through the public endpoint and return the confirmation link.
+    pub async fn create_unconfirmed_subscriber(&self, email: &str) -> reqwest::Url {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        let body = serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap();
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_email_links(&email_request).remove(0)
+    }
+
+    pub async fn create_confirmed_subscriber(&self, email: &str) {
+        let confirmation_link = self.create_unconfirmed_subscriber(email).await;
+        reqwest::get(confirmation_link)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub async fn post_preferences(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/preferences", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_data_request(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/me/data/request", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Extract the links from the plain-text body of an email sent through
+    /// the mock Postmark server, pointing our own links at the test application.
+    pub fn get_email_links(&self, email_request: &wiremock::Request) -> Vec<reqwest::Url> {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .map(|l| {
+                let mut link = reqwest::Url::parse(l.as_str()).unwrap();
+                // Links to our own application must not hit the live web.
+                if link.host_str() == Some("127.0.0.1") {
+                    link.set_port(Some(self.port)).unwrap();
+                }
+                link
+            })
+            .collect()
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
@@ -49,26 +564,56 @@ pub async fn spawn_app() -> TestApp {
     let configuration = {
         let mut c= get_configuration().expect("Failed to read config");
         c.database.database_name = Uuid::new_v4().to_string();
+        c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Small enough to get to the second archive page quickly.
+        c.archive.page_size = 2;
         c
     };
     // configure_database(&configuration.database).await;
     let db_pool = configure_database(&configuration.database).await;
-    let application = Application::build(configuration.clone())
+    let clock = TestClock::new();
+    let application = Application::build_with_clock(configuration.clone(), Arc::new(clock.clone()))
         .await
         .expect("Failed to build application");
-    let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
+    let port = application.port();
+    let address = format!("http://127.0.0.1:{}", port);
+    drop(tokio::spawn(application.run_until_stopped()));
 
     // let server = build(configuration).await
     //     .expect("Failed to start server");
     // let _ = tokio::spawn(server);
     
-    TestApp {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
+    let test_app = TestApp {
         address,
+        port,
         db_pool,
         email_server,
-    }
+        test_user: TestUser::generate(),
+        api_client: client,
+        email_client: configuration.email_client.clone().client(),
+        tracker: Tracker::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        ),
+        templates: EmailTemplates::load(std::path::Path::new(
+            &configuration.application.templates_directory,
+        ))
+        .unwrap(),
+        delivery: configuration.delivery,
+        webhooks: configuration.webhooks,
+        clock,
+        base_url: configuration.application.base_url,
+        postmark_webhook: configuration.email_client.postmark_webhook,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -98,3 +643,8 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .expect("Failed to migrate the database");
     connection_pool
 }
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
@@ -0,0 +1,323 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_scheduler::{try_start_due_issue, SchedulerOutcome};
+
+async fn issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn schedule_in_an_hour(app: &TestApp, issue_id: Uuid) {
+    let scheduled_at = (Utc::now() + Duration::hours(1)).to_rfc3339();
+    app.put_issue_schedule(issue_id, &serde_json::json!({ "scheduled_at": scheduled_at }))
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+/// Pretend the time of a scheduled issue has come.
+async fn make_due(app: &TestApp, issue_id: Uuid) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_at = now() WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "scheduled_at": "2020-01-01T09:00:00Z" }),
+            "a time in the past",
+        ),
+        (
+            serde_json::json!({ "scheduled_at": "2999-01-01T09:00" }),
+            "a local time without a timezone",
+        ),
+        (
+            serde_json::json!({ "scheduled_at": "2999-01-01T09:00", "timezone": "Nowhere/Land" }),
+            "an unknown timezone",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.put_issue_schedule(issue_id, &body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the schedule had {}.",
+            description
+        );
+    }
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_is_sent_once_it_is_due() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+
+    // Far enough in the future, but within the timezone database's DST rules.
+    let response = app
+        .put_issue_schedule(
+            issue_id,
+            &serde_json::json!({ "scheduled_at": "2036-09-02T09:00", "timezone": "Europe/Rome" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["scheduled_at"], "2036-09-02T07:00:00Z");
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+
+    // Not due yet.
+    assert_eq!(app.start_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+
+    make_due(&app, issue_id).await;
+    assert_eq!(app.start_due_issues().await, 1);
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_start_a_due_issue_exactly_once() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    schedule_in_an_hour(&app, issue_id).await;
+    make_due(&app, issue_id).await;
+
+    let outcomes = tokio::join!(
+        try_start_due_issue(&app.db_pool, &app.templates, &app.delivery),
+        try_start_due_issue(&app.db_pool, &app.templates, &app.delivery),
+        try_start_due_issue(&app.db_pool, &app.templates, &app.delivery),
+    );
+
+    let started = [outcomes.0, outcomes.1, outcomes.2]
+        .into_iter()
+        .filter(|o| matches!(o, Ok(SchedulerOutcome::IssueStarted)))
+        .count();
+    assert_eq!(started, 1);
+    let queued = sqlx::query!("SELECT subscriber_id FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+}
+
+#[tokio::test]
+async fn cancelled_issues_go_back_to_being_drafts() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    schedule_in_an_hour(&app, issue_id).await;
+
+    let response = app.delete_issue_schedule(issue_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+    make_due(&app, issue_id).await;
+    assert_eq!(app.start_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled_and_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    schedule_in_an_hour(&app, issue_id).await;
+
+    let response = app
+        .put_issue_schedule(
+            issue_id,
+            &serde_json::json!({ "scheduled_at": "2999-01-01T09:00:00+01:00" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .api_client
+        .put(format!("{}/admin/issues/{}", app.address, issue_id))
+        .json(&serde_json::json!({ "title": "Issue #1", "content": "Edited" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let issue = sqlx::query!(
+        "SELECT status, scheduled_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "scheduled");
+    assert_eq!(
+        issue.scheduled_at.unwrap().to_rfc3339(),
+        "2999-01-01T08:00:00+00:00"
+    );
+}
+
+#[tokio::test]
+async fn issues_cannot_be_rescheduled_or_cancelled_once_sending_starts() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    schedule_in_an_hour(&app, issue_id).await;
+    make_due(&app, issue_id).await;
+    app.start_due_issues().await;
+
+    let scheduled_at = (Utc::now() + Duration::hours(1)).to_rfc3339();
+    let response = app
+        .put_issue_schedule(issue_id, &serde_json::json!({ "scheduled_at": scheduled_at }))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+    let response = app.delete_issue_schedule(issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn issues_published_right_away_end_up_sent() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn each_subscriber_gets_the_issue_at_their_local_time() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.create_confirmed_subscriber("octavia@example.com").await;
+    sqlx::query!("UPDATE subscriptions SET timezone = 'Asia/Tokyo' WHERE email = 'ursula@example.com'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+
+    let response = app
+        .put_issue_schedule(
+            issue_id,
+            &serde_json::json!({ "scheduled_at": "2036-09-02T09:00", "subscriber_local_time": true }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    // Sending starts when it is 09:00 in the first timezone of the day.
+    assert_eq!(body["scheduled_at"], "2036-09-01T19:00:00Z");
+    make_due(&app, issue_id).await;
+    app.start_due_issues().await;
+
+    let queued = sqlx::query!(
+        r#"
+        SELECT s.email, q.not_before
+        FROM issue_delivery_queue q JOIN subscriptions s ON s.id = q.subscriber_id
+        ORDER BY q.not_before
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued[0].email, "ursula@example.com");
+    assert_eq!(queued[0].not_before.to_rfc3339(), "2036-09-02T00:00:00+00:00");
+    // Subscribers without a timezone get the default one, UTC.
+    assert_eq!(queued[1].email, "octavia@example.com");
+    assert_eq!(queued[1].not_before.to_rfc3339(), "2036-09-02T09:00:00+00:00");
+
+    // Nothing is due yet.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE issue_delivery_queue SET not_before = now() WHERE not_before < '2036-09-02T05:00:00Z'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+}
+
+#[tokio::test]
+async fn issues_that_fail_to_start_are_set_aside() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let segment_id = app.create_segment("Everybody", r#"status = "confirmed""#).await;
+    let issue: serde_json::Value = app
+        .post_issue(&serde_json::json!({
+            "title": "Issue #1",
+            "content": "Hello",
+            "segment_id": segment_id,
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let issue_id: Uuid = issue["newsletter_issue_id"].as_str().unwrap().parse().unwrap();
+    schedule_in_an_hour(&app, issue_id).await;
+    make_due(&app, issue_id).await;
+    // Only possible behind the API's back, which validates definitions.
+    sqlx::query!("UPDATE segments SET definition = 'not a definition'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = try_start_due_issue(&app.db_pool, &app.templates, &app.delivery).await;
+
+    assert!(matches!(outcome, Ok(SchedulerOutcome::IssueFailed)));
+    assert_eq!(issue_status(&app, issue_id).await, "failed");
+    let failure_reason = sqlx::query!(
+        "SELECT failure_reason FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .failure_reason;
+    assert!(failure_reason.is_some());
+    assert_eq!(app.start_due_issues().await, 0);
+    // It can be scheduled again once the problem is fixed.
+    schedule_in_an_hour(&app, issue_id).await;
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+}
@@ -0,0 +1,109 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use std::time::Duration;
+
+async fn get_log_filter(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/log_filter", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn put_log_filter(app: &TestApp, body: &serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .put(format!("{}/admin/log_filter", &app.address))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn only_owners_can_change_the_log_filter() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({ "directive": "debug" });
+
+    assert_eq!(put_log_filter(&app, &body).await.status().as_u16(), 401);
+    for role in ["viewer", "editor"] {
+        let user = TestUser::with_role(role);
+        user.store(&app.db_pool).await;
+        user.login(&app).await;
+
+        let response = put_log_filter(&app, &body).await;
+
+        assert_eq!(response.status().as_u16(), 403, "A {} changed it.", role);
+    }
+}
+
+#[tokio::test]
+async fn invalid_directives_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "directive": "zero2prod=loud" }),
+            "an unknown level",
+        ),
+        (
+            serde_json::json!({ "directive": "debug", "duration_seconds": 0 }),
+            "an empty duration",
+        ),
+        (
+            serde_json::json!({ "directive": "debug", "duration_seconds": 604800 }),
+            "a week-long duration",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = put_log_filter(&app, &body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}.",
+            description
+        );
+    }
+}
+
+// The filter belongs to the process: this is the only test that changes it.
+#[tokio::test]
+async fn temporary_filters_revert_to_the_previous_one() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let initial: serde_json::Value = get_log_filter(&app).await.json().await.unwrap();
+
+    let response = put_log_filter(
+        &app,
+        &serde_json::json!({
+            "directive": "info,zero2prod::outbound_webhooks=debug",
+            "duration_seconds": 1,
+        }),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let changed: serde_json::Value = response.json().await.unwrap();
+    assert!(changed["directive"]
+        .as_str()
+        .unwrap()
+        .contains("zero2prod::outbound_webhooks=debug"));
+    assert!(changed["reverts_at"].is_string());
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let reverted: serde_json::Value = get_log_filter(&app).await.json().await.unwrap();
+    assert_eq!(reverted["directive"], initial["directive"]);
+    assert!(reverted["reverts_at"].is_null());
+    let audit_log: Vec<serde_json::Value> = app
+        .api_client
+        .get(format!(
+            "{}/admin/audit_log?action=log_filter.set",
+            &app.address
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(audit_log.len(), 1);
+}
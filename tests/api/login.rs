@@ -0,0 +1,29 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn an_error_is_returned_on_failure() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let dashboard: serde_json::Value = app.get_admin_dashboard().await.json().await.unwrap();
+    assert_eq!(dashboard["username"], app.test_user.username.as_str());
+}
@@ -1,3 +1,27 @@
+mod ab_testing;
+mod admin_dashboard;
+mod api_keys;
+mod archive;
+mod attributes;
+mod audit_log;
+mod consent_events;
+mod feeds;
+mod first_owner;
 mod helpers;
 mod health_check;
-mod subscriptions;
\ No newline at end of file
+mod issue_scheduling;
+mod log_filter;
+mod login;
+mod newsletter_issues;
+mod outbound_webhooks;
+mod password;
+mod preferences;
+mod request_id;
+mod roles;
+mod segments;
+mod subscriptions;
+mod subscriptions_confirm;
+mod subscriber_data;
+mod totp;
+mod tracking;
+mod webhooks;
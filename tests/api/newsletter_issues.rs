@@ -0,0 +1,223 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_create_an_issue() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_issue(&serde_json::json!({ "title": "Issue #1", "content": "Hello" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn issues_with_an_empty_title_or_content_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (serde_json::json!({ "title": "", "content": "Hello" }), "empty title"),
+        (serde_json::json!({ "title": "Issue #1", "content": " " }), "empty content"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_issue(&body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_preview_shows_both_renderings_of_the_markdown() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app
+        .create_issue(
+            "Issue #1",
+            "## News\n\nRead [the announcement](https://example.com/announcement).\n\n<script>alert(1)</script>",
+        )
+        .await;
+
+    let response = app.get_issue_preview(issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let preview: serde_json::Value = response.json().await.unwrap();
+
+    let html = preview["html_content"].as_str().unwrap();
+    assert!(html.contains("<h2>News</h2>"));
+    assert!(html.contains(r#"href="https://example.com/announcement""#));
+    assert!(!html.contains("<script"));
+    let text = preview["text_content"].as_str().unwrap();
+    assert!(text.contains("[1]: https://example.com/announcement"));
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    let response = app.post_publish_issue(issue_id).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = app
+        .create_issue("Issue #1", "Read [this](https://example.com/post).")
+        .await;
+    let response = app.post_publish_issue(issue_id).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Issue #1");
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(html.contains("/subscriptions/unsubscribe?subscription_token="));
+    assert!(!html.contains("{{"));
+    let links = app.get_email_links(&email_request);
+    assert_eq!(links[0].as_str(), "https://example.com/post");
+    assert_eq!(links[1].path(), "/subscriptions/unsubscribe");
+
+    let delivery = sqlx::query!("SELECT outcome FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.outcome, "delivered");
+}
+
+#[tokio::test]
+async fn deliveries_that_fail_for_a_transient_reason_are_retried_later() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!(
+        r#"SELECT attempts, not_before > now() AS "later!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.attempts, 1);
+    assert!(queued.later);
+    sqlx::query!("UPDATE issue_delivery_queue SET not_before = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let delivery = sqlx::query!("SELECT outcome FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.outcome, "delivered");
+}
+
+#[tokio::test]
+async fn deliveries_the_provider_refuses_are_not_retried() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com").await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("SELECT outcome FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.outcome, "failed");
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[tokio::test]
+async fn the_rendered_issue_is_stored_when_it_is_published() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello *world*").await;
+
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+
+    let issue = sqlx::query!(
+        "SELECT html_content, text_content, published_at FROM newsletter_issues"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(issue.html_content.unwrap().contains("<em>world</em>"));
+    assert!(issue.text_content.unwrap().contains("world"));
+    assert!(issue.published_at.is_some());
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_published_again_or_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app.create_issue("Issue #1", "Hello").await;
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+
+    let response = app.post_publish_issue(issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app
+        .api_client
+        .put(format!("{}/admin/issues/{}", app.address, issue_id))
+        .json(&serde_json::json!({ "title": "Issue #1", "content": "Edited" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+}
@@ -0,0 +1,262 @@
+use crate::helpers::{spawn_app, TestApp};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::issue_delivery_worker::ExecutionOutcome;
+use zero2prod::outbound_webhooks::{sign_payload, try_deliver_webhook, SIGNATURE_HEADER};
+
+/// Register `server` for `events`, returning the endpoint id and its secret.
+async fn register(app: &TestApp, server: &MockServer, events: &[&str]) -> (String, String) {
+    let response: serde_json::Value = app
+        .post_webhook_endpoint(&serde_json::json!({
+            "url": format!("{}/crm", server.uri()),
+            "events": events,
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    (
+        response["endpoint_id"].as_str().unwrap().to_owned(),
+        response["secret"].as_str().unwrap().to_owned(),
+    )
+}
+
+/// The whole value of the header `name`: wiremock splits values on commas,
+/// which the signature header is full of.
+fn header(request: &wiremock::Request, name: &str) -> String {
+    request
+        .headers
+        .iter()
+        .find(|(header_name, _)| header_name.as_str().eq_ignore_ascii_case(name))
+        .map(|(_, values)| {
+            values
+                .iter()
+                .map(|value| value.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .unwrap()
+}
+
+async fn respond_with(server: &MockServer, status: u16) {
+    server.reset().await;
+    Mock::given(path("/crm"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(status))
+        .mount(server)
+        .await;
+}
+
+async fn delivery_log(app: &TestApp, endpoint_id: &str) -> serde_json::Value {
+    app.api_client
+        .get(format!(
+            "{}/admin/webhooks/{}/deliveries",
+            &app.address, endpoint_id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_register_webhooks() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_webhook_endpoint(&serde_json::json!({
+            "url": "https://crm.example.com/hooks",
+            "events": ["subscriber.created"],
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_endpoints_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "url": "not a url", "events": ["subscriber.created"] }),
+            "an invalid URL",
+        ),
+        (
+            serde_json::json!({ "url": "ftp://crm.example.com", "events": ["subscriber.created"] }),
+            "a non-http URL",
+        ),
+        (
+            serde_json::json!({ "url": "https://crm.example.com", "events": [] }),
+            "no events",
+        ),
+        (
+            serde_json::json!({ "url": "https://crm.example.com", "events": ["issue.sent"] }),
+            "an unknown event",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_webhook_endpoint(&body).await;
+        assert!(
+            response.status().is_client_error(),
+            "The API did not reject an endpoint with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn lifecycle_events_are_delivered_signed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    respond_with(&crm, 200).await;
+    let (_, secret) = register(&app, &crm, &["subscriber.created", "subscriber.confirmed"]).await;
+
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.dispatch_due_webhooks().await;
+
+    let requests = crm.received_requests().await.unwrap();
+    let mut types = Vec::new();
+    for request in &requests {
+        let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(payload["version"], 1);
+        assert_eq!(header(request, "Webhook-Version"), "1");
+        assert_eq!(payload["data"]["subscriber"]["email"], "ursula@example.com");
+        let signature = header(request, SIGNATURE_HEADER);
+        let timestamp: i64 = signature
+            .strip_prefix("t=")
+            .and_then(|rest| rest.split(',').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(signature, sign_payload(&secret, timestamp, &request.body));
+        types.push(payload["type"].as_str().unwrap().to_owned());
+    }
+    types.sort();
+    assert_eq!(types, vec!["subscriber.confirmed", "subscriber.created"]);
+}
+
+#[tokio::test]
+async fn endpoints_only_get_the_events_they_asked_for() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    respond_with(&crm, 200).await;
+    register(&app, &crm, &["subscriber.unsubscribed"]).await;
+
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.dispatch_due_webhooks().await;
+
+    assert!(crm.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later_and_logged() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    respond_with(&crm, 500).await;
+    let (endpoint_id, _) = register(&app, &crm, &["subscriber.created"]).await;
+    app.create_unconfirmed_subscriber("ursula@example.com").await;
+
+    app.dispatch_due_webhooks().await;
+    // The retry is not due yet.
+    app.dispatch_due_webhooks().await;
+
+    assert_eq!(crm.received_requests().await.unwrap().len(), 1);
+    let log = delivery_log(&app, &endpoint_id).await;
+    assert_eq!(log[0]["status"], "pending");
+    assert_eq!(log[0]["attempts"][0]["response_status"], 500);
+    assert!(log[0]["next_attempt_at"].is_string());
+
+    respond_with(&crm, 200).await;
+    sqlx::query!("UPDATE webhook_deliveries SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_due_webhooks().await;
+
+    let log = delivery_log(&app, &endpoint_id).await;
+    assert_eq!(log[0]["status"], "delivered");
+    assert_eq!(log[0]["attempts"].as_array().unwrap().len(), 2);
+    assert_eq!(log[0]["attempts"][1]["response_status"], 200);
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_on_after_the_last_attempt() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    respond_with(&crm, 503).await;
+    let (endpoint_id, _) = register(&app, &crm, &["subscriber.created"]).await;
+    app.create_unconfirmed_subscriber("ursula@example.com").await;
+
+    try_deliver_webhook(&app.db_pool, &app.webhooks.client(), 1)
+        .await
+        .unwrap();
+
+    let log = delivery_log(&app, &endpoint_id).await;
+    assert_eq!(log[0]["status"], "failed");
+    assert!(log[0]["next_attempt_at"].is_null());
+}
+
+#[tokio::test]
+async fn deliveries_in_flight_are_not_sent_again_by_other_dispatchers() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    Mock::given(path("/crm"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&crm)
+        .await;
+    let (endpoint_id, _) = register(&app, &crm, &["subscriber.created"]).await;
+    app.create_unconfirmed_subscriber("ursula@example.com").await;
+    let client = app.webhooks.client();
+
+    let outcomes = tokio::join!(
+        try_deliver_webhook(&app.db_pool, &client, app.webhooks.max_attempts),
+        async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            try_deliver_webhook(&app.db_pool, &client, app.webhooks.max_attempts).await
+        },
+    );
+
+    assert!(matches!(outcomes.0, Ok(ExecutionOutcome::TaskCompleted)));
+    assert!(matches!(outcomes.1, Ok(ExecutionOutcome::EmptyQueue)));
+    assert_eq!(crm.received_requests().await.unwrap().len(), 1);
+    let log = delivery_log(&app, &endpoint_id).await;
+    assert_eq!(log[0]["status"], "delivered");
+}
+
+#[tokio::test]
+async fn bounces_reported_by_the_email_provider_are_forwarded() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    respond_with(&crm, 200).await;
+    register(&app, &crm, &["subscriber.bounced"]).await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "Email": "ursula@example.com",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_due_webhooks().await;
+
+    let request = crm.received_requests().await.unwrap().pop().unwrap();
+    let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(payload["type"], "subscriber.bounced");
+    assert_eq!(payload["data"]["subscriber"]["status"], "bounced");
+}
@@ -0,0 +1,307 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const NEW_PASSWORD: &str = "a brand new password";
+
+fn change(current: &str, new: &str, check: &str) -> serde_json::Value {
+    serde_json::json!({
+        "current_password": current,
+        "new_password": new,
+        "new_password_check": check,
+    })
+}
+
+async fn log_in_with(app: &TestApp, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": password,
+    }))
+    .await
+}
+
+/// Give the test user an email address and ask for a reset link, returning
+/// its token.
+async fn request_reset(app: &TestApp) -> String {
+    sqlx::query!(
+        "UPDATE users SET email = 'ursula@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    post_reset_request(app, "ursula@example.com")
+        .await
+        .error_for_status()
+        .unwrap();
+    app.send_requested_password_reset_links().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_email_links(&email_request).remove(0);
+    assert_eq!(link.path(), "/password_reset/confirm");
+    assert_eq!(
+        reqwest::get(link.clone()).await.unwrap().status().as_u16(),
+        200
+    );
+    link.query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn post_reset_request(app: &TestApp, email: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/password_reset", &app.address))
+        .form(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_reset(app: &TestApp, token: &str, new_password: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{}/password_reset/confirm", &app.address))
+        .form(&serde_json::json!({
+            "token": token,
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_change_password(&change(&app.test_user.password, NEW_PASSWORD, NEW_PASSWORD))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_password_changes_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let current = app.test_user.password.clone();
+    let test_cases = vec![
+        (
+            change(&current, NEW_PASSWORD, "something else"),
+            "mismatched new passwords",
+        ),
+        (
+            change(&Uuid::new_v4().to_string(), NEW_PASSWORD, NEW_PASSWORD),
+            "a wrong current password",
+        ),
+        (
+            change(&current, "too short", "too short"),
+            "a short password",
+        ),
+        (
+            change(&current, "password1234", "password1234"),
+            "a breached password",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_change_password(&body).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a change with {}.",
+            description
+        );
+    }
+    assert_is_redirect_to(&log_in_with(&app, &current).await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn changing_the_password_logs_out_every_session() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let other_device = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    other_device
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    let response = app
+        .post_change_password(&change(&app.test_user.password, NEW_PASSWORD, NEW_PASSWORD))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 401);
+    let response = other_device
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_new_password_replaces_the_old_one() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_change_password(&change(&app.test_user.password, NEW_PASSWORD, NEW_PASSWORD))
+        .await;
+
+    assert_eq!(
+        log_in_with(&app, &app.test_user.password)
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+    assert_is_redirect_to(&log_in_with(&app, NEW_PASSWORD).await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_requests_for_unknown_addresses_do_not_send_an_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_reset_request(&app, "nobody@example.com").await;
+    app.send_requested_password_reset_links().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn reset_requests_do_not_wait_for_the_email_to_be_sent() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET email = 'ursula@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Known or not, the address gets the same answer.
+    let known = post_reset_request(&app, "ursula@example.com").await;
+    let unknown = post_reset_request(&app, "nobody@example.com").await;
+
+    assert_eq!(known.status().as_u16(), 200);
+    assert_eq!(unknown.status().as_u16(), 200);
+    assert_eq!(known.text().await.unwrap(), unknown.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn reset_requests_are_throttled_per_address_and_per_client() {
+    let app = spawn_app().await;
+
+    for _ in 0..3 {
+        let response = post_reset_request(&app, "nobody@example.com").await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = post_reset_request(&app, "NOBODY@example.com").await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    for i in 0..7 {
+        let response = post_reset_request(&app, &format!("nobody{}@example.com", i)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = post_reset_request(&app, "somebody.else@example.com").await;
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_reset_link_sets_a_new_password() {
+    let app = spawn_app().await;
+    let token = request_reset(&app).await;
+
+    let response = post_reset(&app, &token, NEW_PASSWORD).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&log_in_with(&app, NEW_PASSWORD).await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_links_work_only_once() {
+    let app = spawn_app().await;
+    let token = request_reset(&app).await;
+
+    post_reset(&app, &token, NEW_PASSWORD).await;
+    let response = post_reset(&app, &token, "yet another new password").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn reset_links_expire() {
+    let app = spawn_app().await;
+    let token = request_reset(&app).await;
+
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = post_reset(&app, &token, NEW_PASSWORD).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn reset_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    let token = request_reset(&app).await;
+
+    let stored = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_ne!(stored.token_hash, token);
+}
+
+#[tokio::test]
+async fn a_reset_with_a_weak_password_keeps_the_link_valid() {
+    let app = spawn_app().await;
+    let token = request_reset(&app).await;
+
+    let response = post_reset(&app, &token, "password1234").await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = post_reset(&app, &token, NEW_PASSWORD).await;
+    assert_is_redirect_to(&response, "/login");
+}
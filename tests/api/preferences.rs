@@ -0,0 +1,97 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscription_token(app: &TestApp) -> String {
+    sqlx::query!("SELECT subscription_token FROM subscriptions_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token
+}
+
+#[tokio::test]
+async fn preferences_show_the_timezone_and_the_default() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let token = subscription_token(&app).await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/preferences?subscription_token={}",
+        app.address, token
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let preferences: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(preferences["email"], "ursula@example.com");
+    assert_eq!(preferences["timezone"], serde_json::Value::Null);
+    assert_eq!(preferences["default_timezone"], "UTC");
+}
+
+#[tokio::test]
+async fn changing_the_timezone_records_a_preference_change() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let token = subscription_token(&app).await;
+    let body = serde_urlencoded::to_string([
+        ("subscription_token", token.as_str()),
+        ("timezone", "Asia/Tokyo"),
+    ])
+    .unwrap();
+
+    let response = app.post_preferences(body.clone()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    // Saving the same preferences again is not a change.
+    app.post_preferences(body).await.error_for_status().unwrap();
+
+    let subscriber = sqlx::query!("SELECT timezone FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.timezone.as_deref(), Some("Asia/Tokyo"));
+    let events = sqlx::query!(
+        "SELECT event_type FROM consent_events WHERE event_type = 'preference_change'"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let token = subscription_token(&app).await;
+    let test_cases = vec![
+        (
+            serde_urlencoded::to_string([
+                ("subscription_token", token.as_str()),
+                ("timezone", "GMT+2"),
+            ])
+            .unwrap(),
+            400,
+            "an invalid timezone",
+        ),
+        (
+            serde_urlencoded::to_string([
+                ("subscription_token", "not-a-token"),
+                ("timezone", "Asia/Tokyo"),
+            ])
+            .unwrap(),
+            401,
+            "an unknown token",
+        ),
+    ];
+
+    for (body, status, description) in test_cases {
+        let response = app.post_preferences(body).await;
+        assert_eq!(
+            status,
+            response.status().as_u16(),
+            "The API did not fail with {} when the form had {}.",
+            status,
+            description
+        );
+    }
+}
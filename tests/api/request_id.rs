@@ -0,0 +1,94 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn request_id(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get("X-Request-Id")
+        .expect("The response has no request id.")
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn received_request_id(request: &wiremock::Request) -> Option<String> {
+    request
+        .headers
+        .iter()
+        .find(|(name, _)| name.as_str().eq_ignore_ascii_case("X-Request-Id"))
+        .map(|(_, values)| values.last().as_str().to_owned())
+}
+
+#[tokio::test]
+async fn every_response_carries_a_fresh_request_id() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let health_check = format!("{}/health_check", &app.address);
+
+    let first = client.get(&health_check).send().await.unwrap();
+    let second = client.get(&health_check).send().await.unwrap();
+
+    assert_ne!(request_id(&first), request_id(&second));
+}
+
+#[tokio::test]
+async fn errors_carry_a_request_id_too() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    request_id(&response);
+}
+
+#[tokio::test]
+async fn inbound_request_ids_are_not_trusted_by_default() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "forged-by-the-client")
+        .send()
+        .await
+        .unwrap();
+
+    assert_ne!(request_id(&response), "forged-by-the-client");
+}
+
+#[tokio::test]
+async fn the_request_id_is_sent_on_to_the_email_provider_and_webhooks() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    Mock::given(path("/crm"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&crm)
+        .await;
+    app.post_webhook_endpoint(&serde_json::json!({
+        "url": format!("{}/crm", crm.uri()),
+        "events": ["subscriber.created"],
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // Webhooks go out later, from the dispatcher, with the id they were
+    // queued under.
+    app.dispatch_due_webhooks().await;
+
+    let request_id = Some(request_id(&response));
+    let email = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(received_request_id(email), request_id);
+    let webhook = &crm.received_requests().await.unwrap()[0];
+    assert_eq!(received_request_id(webhook), request_id);
+}
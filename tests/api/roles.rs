@@ -0,0 +1,220 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Store a user with `role` and log in as them.
+async fn log_in_as(app: &TestApp, role: &str) -> TestUser {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    user.login(app).await;
+    user
+}
+
+async fn post_invitation(app: &TestApp, body: &serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/invitations", &app.address))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Invite `email` as `role`, returning the token sent in the invitation.
+async fn invite(app: &TestApp, email: &str, role: &str) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    post_invitation(app, &serde_json::json!({ "email": email, "role": role }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_email_links(&email_request).remove(0);
+    assert_eq!(link.path(), "/invitations/accept");
+    link.query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn accept(app: &TestApp, token: &str, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{}/invitations/accept", &app.address))
+        .form(&serde_json::json!({
+            "token": token,
+            "username": username,
+            "password": password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn viewers_can_read_but_not_draft() {
+    let app = spawn_app().await;
+    log_in_as(&app, "viewer").await;
+
+    let segments = app
+        .api_client
+        .get(format!("{}/admin/segments", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let draft = app
+        .post_issue(&serde_json::json!({ "title": "Newsletter title", "content": "Hi!" }))
+        .await;
+
+    assert_eq!(segments.status().as_u16(), 200);
+    assert_eq!(draft.status().as_u16(), 403);
+    assert!(draft.text().await.unwrap().contains("editor"));
+}
+
+#[tokio::test]
+async fn editors_can_draft_but_not_publish() {
+    let app = spawn_app().await;
+    log_in_as(&app, "editor").await;
+
+    let newsletter_issue_id = app.create_issue("Newsletter title", "Hi!").await;
+    let response = app.post_publish_issue(newsletter_issue_id).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response.text().await.unwrap().contains("owner"));
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_integrations() {
+    let app = spawn_app().await;
+    log_in_as(&app, "editor").await;
+
+    let response = app
+        .post_api_key(&serde_json::json!({ "name": "CRM sync", "scopes": ["issues:write"] }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_session_of_a_removed_user_is_rejected() {
+    let app = spawn_app().await;
+    let user = log_in_as(&app, "owner").await;
+
+    sqlx::query!("DELETE FROM users WHERE user_id = $1", user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn only_owners_can_invite_admins() {
+    let app = spawn_app().await;
+    log_in_as(&app, "editor").await;
+
+    let response = post_invitation(
+        &app,
+        &serde_json::json!({ "email": "ursula@example.com", "role": "owner" }),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invited_admins_get_the_role_they_were_invited_with() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite(&app, "ursula@example.com", "editor").await;
+
+    let response = accept(&app, &token, "ursula", "a-long-enough-password").await;
+
+    assert_eq!(response.status().as_u16(), 303);
+    let user = sqlx::query!("SELECT role, email FROM users WHERE username = 'ursula'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.role, "editor");
+    assert_eq!(user.email.as_deref(), Some("ursula@example.com"));
+    app.post_login(&serde_json::json!({
+        "username": "ursula",
+        "password": "a-long-enough-password",
+    }))
+    .await;
+    let newsletter_issue_id = app.create_issue("Newsletter title", "Hi!").await;
+    let response = app.post_publish_issue(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invitations_can_only_be_accepted_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite(&app, "ursula@example.com", "viewer").await;
+
+    accept(&app, &token, "ursula", "a-long-enough-password")
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = accept(&app, &token, "ursula2", "a-long-enough-password").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_invitations_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite(&app, "ursula@example.com", "viewer").await;
+
+    sqlx::query!("UPDATE admin_invitations SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = accept(&app, &token, "ursula", "a-long-enough-password").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invitation_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite(&app, "ursula@example.com", "viewer").await;
+
+    let stored = sqlx::query!("SELECT token_hash FROM admin_invitations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_ne!(stored.token_hash, token);
+}
+
+#[tokio::test]
+async fn usernames_cannot_be_taken_twice() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = invite(&app, "ursula@example.com", "viewer").await;
+
+    let response = accept(
+        &app,
+        &token,
+        &app.test_user.username,
+        "a-long-enough-password",
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,202 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn add_to_list(app: &TestApp, email: &str, list: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_lists (subscriber_id, list_name, joined_at)
+        SELECT id, $2, now() FROM subscriptions WHERE email = $1
+        "#,
+        email,
+        list,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Record that `email` opened a (fake) issue published just now.
+async fn record_open(app: &TestApp, email: &str) {
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, markdown_content, created_at, published_at, status
+        )
+        VALUES ($1, 'Earlier issue', 'Hello', $2, $2, 'sent')
+        "#,
+        issue_id,
+        Utc::now(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_opens (newsletter_issue_id, subscriber_id, opened_at)
+        SELECT $1, id, now() FROM subscriptions WHERE email = $2
+        "#,
+        issue_id,
+        email,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_segments() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_segment(&serde_json::json!({ "name": "Rust", "definition": "in list \"rust\"" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected_with_an_explanation() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        ("", "in list \"rust\"", "an empty name"),
+        ("Rust", "in lists \"rust\"", "a syntax error"),
+        ("Rust", "joined within 30", "a missing unit"),
+        ("Rust", "status = \"subscribed\"", "an unknown status"),
+    ];
+
+    for (name, definition, description) in test_cases {
+        let response = app
+            .post_segment(&serde_json::json!({ "name": name, "definition": definition }))
+            .await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the segment had {}.",
+            description
+        );
+        assert!(!response.text().await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn segment_names_are_unique() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_segment("Rust", "in list \"rust\"").await;
+
+    let response = app
+        .post_segment(&serde_json::json!({ "name": "Rust", "definition": "in list \"go\"" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn the_preview_counts_matching_subscribers() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.create_confirmed_subscriber("octavia@example.com").await;
+    app.create_unconfirmed_subscriber("frank@example.com").await;
+    for email in ["ursula@example.com", "frank@example.com"] {
+        add_to_list(&app, email, "rust").await;
+    }
+
+    let response = app
+        .post_segment_preview("in list \"rust\" and joined within 30 days")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let size: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(size["subscribers"], 2);
+    assert_eq!(size["recipients"], 1);
+    let response = app.post_segment_preview("in list rust").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_addressed_to_a_segment_only_reach_its_members() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for email in [
+        "ursula@example.com",
+        "octavia@example.com",
+        "frank@example.com",
+    ] {
+        app.create_confirmed_subscriber(email).await;
+    }
+    add_to_list(&app, "ursula@example.com", "rust").await;
+    add_to_list(&app, "octavia@example.com", "rust").await;
+    record_open(&app, "ursula@example.com").await;
+    record_open(&app, "frank@example.com").await;
+    let segment_id = app
+        .create_segment(
+            "Engaged rustaceans",
+            r#"status = "confirmed" and in list "rust" and opened any of last 3 issues"#,
+        )
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_issue(&serde_json::json!({
+            "title": "Issue #1",
+            "content": "Hello",
+            "segment_id": segment_id,
+        }))
+        .await;
+    let issue: serde_json::Value = response.json().await.unwrap();
+    let issue_id: Uuid = issue["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        app.post_publish_issue(issue_id).await.status().as_u16(),
+        202
+    );
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["To"].as_str().unwrap().contains("ursula@example.com"));
+    // The segment cannot go away while an issue is addressed to it.
+    let response = app
+        .api_client
+        .delete(format!("{}/admin/segments/{}", app.address, segment_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn issues_cannot_be_addressed_to_unknown_segments() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_issue(&serde_json::json!({
+            "title": "Issue #1",
+            "content": "Hello",
+            "segment_id": Uuid::new_v4(),
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,113 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn create_subscriber_and_request_data(app: &TestApp) -> Vec<reqwest::Url> {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_data_request("email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    app.get_email_links(email_request)
+}
+
+#[tokio::test]
+async fn data_requests_for_unknown_addresses_do_not_send_an_email() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_data_request("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_data_access_link_returns_the_subscriber_data_as_json() {
+    let app = spawn_app().await;
+    let links = create_subscriber_and_request_data(&app).await;
+    assert_eq!(links.len(), 2);
+
+    let response = reqwest::get(links[0].clone()).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let body = response.text().await.unwrap();
+    let export: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(export["subscription"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["subscription"]["name"], "le guin");
+    assert_eq!(export["data_access_tokens"].as_array().unwrap().len(), 1);
+    // Tokens are credentials: anybody holding the export could use them.
+    let subscription_token = sqlx::query!("SELECT subscription_token FROM subscriptions_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token;
+    assert!(!body.contains(&subscription_token));
+    let data_access_token = links[0].query_pairs().find(|(k, _)| k == "token").unwrap().1;
+    assert!(!body.contains(&*data_access_token));
+}
+
+#[tokio::test]
+async fn data_access_with_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/me/data?token=not-a-token", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn erasure_removes_the_subscriber_and_leaves_a_tombstone() {
+    let app = spawn_app().await;
+    let links = create_subscriber_and_request_data(&app).await;
+    let token = links[1]
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/me/data/erase", app.address))
+        .form(&[("token", token.as_str())])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let remaining = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+    let tombstones = sqlx::query!("SELECT email_hash FROM erased_subscribers")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert!(!tombstones[0].email_hash.contains("ursula"));
+
+    // The token died with the subscriber.
+    let response = reqwest::get(links[0].clone()).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
@@ -1,4 +1,5 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{captured_logs, spawn_app};
+use uuid::Uuid;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -8,6 +9,11 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
     
     // let client = reqwest::Client::new();
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
     // let response = client
     //     .post(&format!("{}/subscriptions", &app.address))
     //     .header("Content-Type", "application/x-www-form-urlencoded")
@@ -28,10 +34,50 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 }
 
 
+#[tokio::test]
+async fn subscribe_stores_the_timezone_of_the_subscriber() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&timezone=America%2FLos_Angeles";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT timezone FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.timezone.as_deref(), Some("America/Los_Angeles"));
+}
+
+#[tokio::test]
+async fn subscribe_adds_the_subscriber_to_the_list_of_the_form() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&list=rust";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT list_name FROM subscriber_lists")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved list membership.");
+    assert_eq!(saved.list_name, "rust");
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
+    // let client = reqwest::Client::new();
     let test_cases = vec![
         ("name=le%20guin", "missing the email"),
         ("email=ursula_le_guin%40gmail.com", "missing the name"),
@@ -66,6 +112,8 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
      ("name=&email=ursula_le_guin%40gmail.com", "empty name"),   
      ("name=Ursula&email=", "empty email"),
      ("name=Ursula&email=definitely-not-an-email", "invalid email"),
+     ("name=Ursula&email=ursula_le_guin%40gmail.com&timezone=Mars%2FOlympus", "invalid timezone"),
+     ("name=Ursula&email=ursula_le_guin%40gmail.com&list=Rust%20Weekly", "invalid list"),
     ];
     for (body, description) in test_cases {
         // let response = client
@@ -77,9 +125,9 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         //     .expect("Failed to execute request.");
         let response = app.post_subscriptions(body.into()).await;
         assert_eq!(
-         200,
+         400,
          response.status().as_u16(),   
-         "The API did not return a 200 OK when the payload was {}.",
+         "The API did not return a 400 Bad Request when the payload was {}.",
          description
         );
     }
@@ -133,4 +181,32 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     let text_link = get_link(body["TextBody"].as_str().unwrap());
 
     assert_eq!(html_link, text_link);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn subscriber_emails_and_names_are_masked_in_the_logs() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    // Unique, as the logs of every test end up in the same place.
+    let local_part = format!("ursula-{}", Uuid::new_v4());
+    let domain = format!("{}.example.com", Uuid::new_v4());
+    let name = format!("Ursula-{}", Uuid::new_v4());
+
+    let valid = app
+        .post_subscriptions(format!("name={}&email={}%40{}", name, local_part, domain))
+        .await;
+    let invalid = app
+        .post_subscriptions(format!("name={}&email={}%40%40{}", name, local_part, domain))
+        .await;
+
+    assert_eq!(valid.status().as_u16(), 200);
+    assert_eq!(invalid.status().as_u16(), 400);
+    let logs = captured_logs();
+    assert!(logs.contains(&format!("u***@{}", domain)));
+    assert!(!logs.contains(&local_part));
+    assert!(!logs.contains(&name));
+}
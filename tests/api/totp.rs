@@ -0,0 +1,205 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use chrono::Duration;
+use zero2prod::authentication::TotpSecret;
+use zero2prod::clock::Clock;
+
+async fn start_enrolment(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/totp", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn confirm_enrolment(app: &TestApp, code: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/totp/confirm", &app.address))
+        .json(&serde_json::json!({ "code": code }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Enable TOTP for the test user, returning the secret and the recovery
+/// codes. The user is logged out afterwards.
+async fn enable_totp(app: &TestApp) -> (TotpSecret, Vec<String>) {
+    app.test_user.login(app).await;
+    let enrolment: serde_json::Value = start_enrolment(app).await.json().await.unwrap();
+    let secret = TotpSecret::parse(enrolment["secret"].as_str().unwrap()).unwrap();
+    let confirmation: serde_json::Value = confirm_enrolment(app, &secret.code_at(app.clock.now()))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let recovery_codes = confirmation["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_owned())
+        .collect();
+    app.api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+    // The code used to confirm cannot be used again.
+    app.clock.advance(Duration::seconds(30));
+    (secret, recovery_codes)
+}
+
+async fn enter_password(app: &TestApp) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn enrolment_hands_out_an_otpauth_uri() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let enrolment: serde_json::Value = start_enrolment(&app).await.json().await.unwrap();
+
+    let uri = enrolment["otpauth_uri"].as_str().unwrap();
+    assert!(uri.starts_with("otpauth://totp/"));
+    assert!(uri.contains(enrolment["secret"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn enrolment_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    start_enrolment(&app).await.error_for_status().unwrap();
+
+    let response = confirm_enrolment(&app, "000000").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let user = sqlx::query!("SELECT totp_secret FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(user.totp_secret.is_none());
+}
+
+#[tokio::test]
+async fn recovery_codes_are_stored_hashed() {
+    let app = spawn_app().await;
+
+    let (_, recovery_codes) = enable_totp(&app).await;
+
+    assert_eq!(recovery_codes.len(), 10);
+    let stored = sqlx::query!("SELECT code_hash FROM totp_recovery_codes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 10);
+    assert!(stored
+        .iter()
+        .all(|row| !recovery_codes.contains(&row.code_hash)));
+}
+
+#[tokio::test]
+async fn totp_cannot_be_enabled_twice() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+    enter_password(&app).await;
+    app.post_login_totp(&secret.code_at(app.clock.now())).await;
+
+    let response = start_enrolment(&app).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn the_password_alone_does_not_log_in_once_totp_is_enabled() {
+    let app = spawn_app().await;
+    enable_totp(&app).await;
+
+    enter_password(&app).await;
+
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_valid_code_completes_the_login() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+    enter_password(&app).await;
+
+    let response = app.post_login_totp(&secret.code_at(app.clock.now())).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn codes_cannot_be_replayed() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+    let code = secret.code_at(app.clock.now());
+    enter_password(&app).await;
+    app.post_login_totp(&code).await;
+    app.api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+    enter_password(&app).await;
+
+    let response = app.post_login_totp(&code).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_totp_step_needs_the_password_step_first() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+
+    let response = app.post_login_totp(&secret.code_at(app.clock.now())).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn recovery_codes_work_only_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_totp(&app).await;
+    enter_password(&app).await;
+
+    let response = app.post_login_totp(&recovery_codes[0].to_uppercase()).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+    enter_password(&app).await;
+    let response = app.post_login_totp(&recovery_codes[0]).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn failed_attempts_are_rate_limited() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+    enter_password(&app).await;
+
+    for _ in 0..5 {
+        let response = app.post_login_totp("000000").await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = app.post_login_totp(&secret.code_at(app.clock.now())).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    app.clock.advance(Duration::minutes(16));
+    let response = app.post_login_totp(&secret.code_at(app.clock.now())).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
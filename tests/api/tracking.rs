@@ -0,0 +1,167 @@
+use crate::helpers::{spawn_app, TestApp};
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::tracking::Tracker;
+
+/// Publish an issue with a single link to a single confirmed subscriber
+/// and return the HTML body of the email they received.
+async fn publish_and_deliver(app: &TestApp, tracking: bool) -> (Uuid, String) {
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.test_user.login(app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response: serde_json::Value = app
+        .post_issue(&serde_json::json!({
+            "title": "Issue #1",
+            "content": "Read [this](https://example.com/post).",
+            "tracking": tracking,
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = response["newsletter_issue_id"].as_str().unwrap().parse().unwrap();
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    (issue_id, body["HtmlBody"].as_str().unwrap().to_owned())
+}
+
+/// Find the first tracking URL of the given kind and point it at the test application.
+fn tracking_link(app: &TestApp, html: &str, prefix: &str) -> reqwest::Url {
+    let start = html.find(&format!("{}/{}/", app.base_url, prefix)).unwrap();
+    let end = start + html[start..].find('"').unwrap();
+    let mut link = reqwest::Url::parse(&html[start..end]).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn links_of_tracked_issues_go_through_the_redirect() {
+    let app = spawn_app().await;
+
+    let (_, html) = publish_and_deliver(&app, true).await;
+
+    assert!(!html.contains(r#"href="https://example.com/post""#));
+    assert!(html.contains(&format!("{}/r/", app.base_url)));
+    assert!(html.contains(&format!("{}/o/", app.base_url)));
+    // The unsubscribe link is still a direct link.
+    assert!(html.contains("/subscriptions/unsubscribe?subscription_token="));
+}
+
+#[tokio::test]
+async fn issues_without_tracking_are_sent_untouched() {
+    let app = spawn_app().await;
+
+    let (_, html) = publish_and_deliver(&app, false).await;
+
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(!html.contains(&format!("{}/r/", app.base_url)));
+    assert!(!html.contains(&format!("{}/o/", app.base_url)));
+}
+
+#[tokio::test]
+async fn clicking_a_tracked_link_redirects_and_is_recorded() {
+    let app = spawn_app().await;
+    let (issue_id, html) = publish_and_deliver(&app, true).await;
+
+    let response = app
+        .api_client
+        .get(tracking_link(&app, &html, "r"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers()["Location"], "https://example.com/post");
+    let click = sqlx::query!("SELECT newsletter_issue_id, url FROM issue_clicks")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(click.newsletter_issue_id, issue_id);
+    assert_eq!(click.url, "https://example.com/post");
+}
+
+#[tokio::test]
+async fn forged_links_are_not_followed() {
+    let app = spawn_app().await;
+    let forged = Tracker::new(app.base_url.clone(), Secret::new("not-our-secret".into()))
+        .click_url(Uuid::new_v4(), Uuid::new_v4(), "https://evil.example.com");
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/r/{}",
+            app.address,
+            forged.rsplit('/').next().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.headers().get("Location").is_none());
+}
+
+#[tokio::test]
+async fn loading_the_pixel_records_an_open() {
+    let app = spawn_app().await;
+    let (issue_id, html) = publish_and_deliver(&app, true).await;
+
+    let response = app
+        .api_client
+        .get(tracking_link(&app, &html, "o"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let open = sqlx::query!("SELECT newsletter_issue_id FROM issue_opens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(open.newsletter_issue_id, issue_id);
+}
+
+#[tokio::test]
+async fn stats_count_unique_opens_and_clicks() {
+    let app = spawn_app().await;
+    let (issue_id, html) = publish_and_deliver(&app, true).await;
+    for _ in 0..2 {
+        app.api_client.get(tracking_link(&app, &html, "o")).send().await.unwrap();
+        app.api_client.get(tracking_link(&app, &html, "r")).send().await.unwrap();
+    }
+
+    let response = app.get_issue_stats(issue_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(stats["delivered"], 1);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(
+        stats["top_links"],
+        serde_json::json!([{ "url": "https://example.com/post", "clicks": 2, "unique_clicks": 1 }])
+    );
+}
+
+#[tokio::test]
+async fn stats_of_unknown_issues_are_a_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_issue_stats(Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
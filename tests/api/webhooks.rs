@@ -0,0 +1,204 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn bounce(bounce_type: &str, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807u64,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Email": email,
+        "BouncedAt": "2025-08-19T16:33:54.9070259Z",
+        "Description": "The server was unable to deliver your message.",
+        "Inactive": true,
+        "CanActivate": true
+    })
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn requests_without_credentials_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email/postmark", &app.address))
+        .json(&bounce("HardBounce", "ursula@example.com"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="webhooks""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn requests_with_the_wrong_password_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email/postmark", &app.address))
+        .basic_auth(&app.postmark_webhook.username, Some("not-the-password"))
+        .json(&bounce("HardBounce", "ursula@example.com"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_hard_bounce_marks_the_subscriber_as_bounced_and_is_recorded() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+
+    let response = app
+        .post_postmark_webhook(&bounce("HardBounce", "Ursula@example.com"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, "ursula@example.com").await, "bounced");
+    let event = sqlx::query!("SELECT record_type, event_type, subscriber_id FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(event.event_type.as_deref(), Some("HardBounce"));
+    assert!(event.subscriber_id.is_some());
+}
+
+#[tokio::test]
+async fn a_soft_bounce_is_recorded_without_changing_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+
+    let response = app
+        .post_postmark_webhook(&bounce("SoftBounce", "ursula@example.com"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, "ursula@example.com").await, "confirmed");
+    let events = sqlx::query!("SELECT email_event_id FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn a_spam_complaint_marks_the_subscriber_as_complained() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let mut complaint = bounce("SpamComplaint", "ursula@example.com");
+    complaint["RecordType"] = "SpamComplaint".into();
+
+    let response = app.post_postmark_webhook(&complaint).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, "ursula@example.com").await, "complained");
+
+    // A later bounce does not water the complaint down.
+    app.post_postmark_webhook(&bounce("HardBounce", "ursula@example.com"))
+        .await;
+    assert_eq!(subscriber_status(&app, "ursula@example.com").await, "complained");
+}
+
+#[tokio::test]
+async fn a_manual_suppression_unsubscribes_and_records_consent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "SubscriptionChange",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "ChangedAt": "2025-08-19T10:53:34.416071Z",
+            "Recipient": "ursula@example.com",
+            "Origin": "Recipient",
+            "SuppressSending": true,
+            "SuppressionReason": "ManualSuppression"
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, "ursula@example.com").await, "unsubscribed");
+    let events = sqlx::query!("SELECT event_type, source FROM consent_events ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let last = events.last().unwrap();
+    assert_eq!(last.event_type, "unsubscribe");
+    assert_eq!(last.source.as_deref(), Some("postmark"));
+}
+
+#[tokio::test]
+async fn bounced_subscribers_do_not_receive_issues() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    app.post_postmark_webhook(&bounce("HardBounce", "ursula@example.com"))
+        .await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.create_issue("Issue #1", "Hello!").await;
+    app.post_publish_issue(issue_id).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn other_record_types_are_recorded_and_acknowledged() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Open",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": "ursula@example.com"
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let event = sqlx::query!("SELECT record_type FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.record_type, "Open");
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({ "Type": "HardBounce" }), "missing record type"),
+        (
+            serde_json::json!({ "RecordType": "Bounce", "Type": "HardBounce" }),
+            "missing email",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_postmark_webhook(&body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 when the payload was {}.",
+            description
+        );
+    }
+}